@@ -8,28 +8,30 @@
 // except according to those terms.
 
 use codecs::aac::AacHeaders;
-use codecs::vorbis::{self, VorbisHeaders};
+use codecs::opus::OpusHeaders;
+use codecs::vorbis::VorbisHeaders;
+use error::MediaError;
+use registry::Registry;
 
 use libc::c_int;
 
-#[cfg(feature="ffmpeg")]
-use codecs::libavcodec;
-#[cfg(target_os="macos")]
-use platform;
-
 pub trait AudioHeaders {
     fn vorbis_headers<'a>(&'a self) -> Option<&'a VorbisHeaders>;
     fn aac_headers<'a>(&'a self) -> Option<&'a AacHeaders>;
+    fn opus_headers<'a>(&'a self) -> Option<&'a OpusHeaders>;
 }
 
 pub trait AudioDecoderInfo {
-    fn create_decoder(self: Box<Self>) -> Box<AudioDecoder + 'static>;
+    fn create_decoder(self: Box<Self>) -> Result<Box<AudioDecoder + 'static>,MediaError>;
 }
 
 pub trait AudioDecoder {
-    fn decode(&mut self, data: &[u8]) -> Result<(),()>;
-    fn decoded_samples<'a>(&'a mut self) -> Result<Box<DecodedAudioSamples + 'a>,()>;
+    fn decode(&mut self, data: &[u8]) -> Result<(),MediaError>;
+    fn decoded_samples<'a>(&'a mut self) -> Result<Box<DecodedAudioSamples + 'a>,MediaError>;
     fn acknowledge(&mut self, sample_count: c_int);
+    /// Tells the decoder that it is starting partway through the stream, as after a seek, so that
+    /// it doesn't discard the priming samples that some encoders put at the start of the stream.
+    fn start_mid_stream(&mut self) {}
 }
 
 pub trait DecodedAudioSamples {
@@ -47,27 +49,39 @@ impl AudioHeaders for EmptyAudioHeadersImpl {
     fn aac_headers<'a>(&'a self) -> Option<&'a AacHeaders> {
         None
     }
+    fn opus_headers<'a>(&'a self) -> Option<&'a OpusHeaders> {
+        None
+    }
 }
 
 #[allow(missing_copy_implementations)]
 pub struct RegisteredAudioDecoder {
     pub id: [u8; 4],
     pub constructor: extern "Rust" fn(headers: &AudioHeaders, sample_rate: f64, channels: u16)
-                                      -> Box<AudioDecoderInfo + 'static>,
+                                      -> Result<Box<AudioDecoderInfo + 'static>,MediaError>,
 }
 
 impl RegisteredAudioDecoder {
-    pub fn get(codec_id: &[u8]) -> Result<&'static RegisteredAudioDecoder,()> {
-        for decoder in AUDIO_DECODERS.iter() {
+    /// Finds the highest-priority decoder in `registry` for the given codec.
+    pub fn get<'a>(registry: &'a Registry, codec_id: &[u8])
+                   -> Result<&'a RegisteredAudioDecoder,MediaError> {
+        for decoder in registry.audio_decoders().iter() {
             if decoder.id == codec_id {
                 return Ok(decoder)
             }
         }
-        Err(())
+        Err(MediaError::UnsupportedCodec)
+    }
+
+    /// Returns every decoder in `registry` for the given codec, in order of preference. Callers
+    /// should fall back to the next candidate if one fails.
+    pub fn get_all<'a>(registry: &'a Registry, codec_id: &[u8])
+                       -> Vec<&'a RegisteredAudioDecoder> {
+        registry.audio_decoders().iter().filter(|decoder| decoder.id == codec_id).collect()
     }
 
     pub fn new(&self, headers: &AudioHeaders, sample_rate: f64, channels: u16)
-               -> Box<AudioDecoderInfo + 'static> {
+               -> Result<Box<AudioDecoderInfo + 'static>,MediaError> {
         (self.constructor)(headers, sample_rate, channels)
     }
 
@@ -75,28 +89,3 @@ impl RegisteredAudioDecoder {
         self.id
     }
 }
-
-#[cfg(all(target_os="macos", feature="ffmpeg"))]
-pub static AUDIO_DECODERS: [RegisteredAudioDecoder; 3] = [
-    vorbis::AUDIO_DECODER,
-    libavcodec::AUDIO_DECODER,
-    platform::macos::audiounit::AUDIO_DECODER,
-];
-
-#[cfg(all(target_os="macos", not(feature="ffmpeg")))]
-pub static AUDIO_DECODERS: [RegisteredAudioDecoder; 2] = [
-    vorbis::AUDIO_DECODER,
-    platform::macos::audiounit::AUDIO_DECODER,
-];
-
-#[cfg(all(not(target_os="macos"), feature="ffmpeg"))]
-pub static AUDIO_DECODERS: [RegisteredAudioDecoder; 2] = [
-    vorbis::AUDIO_DECODER,
-    libavcodec::AUDIO_DECODER,
-];
-
-#[cfg(all(not(target_os="macos"), not(feature="ffmpeg")))]
-pub static AUDIO_DECODERS: [RegisteredAudioDecoder; 1] = [
-    vorbis::AUDIO_DECODER,
-];
-
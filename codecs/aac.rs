@@ -8,6 +8,7 @@
 // except according to those terms.
 
 use audiodecoder::AudioHeaders;
+use codecs::opus::OpusHeaders;
 use codecs::vorbis::VorbisHeaders;
 
 pub struct AacHeaders {
@@ -21,5 +22,83 @@ impl AudioHeaders for AacHeaders {
     fn aac_headers<'a>(&'a self) -> Option<&'a AacHeaders> {
         Some(self)
     }
+    fn opus_headers<'a>(&'a self) -> Option<&'a OpusHeaders> {
+        None
+    }
 }
 
+/// The audio object types of the AAC profiles.
+pub const OBJECT_TYPE_MAIN: u8 = 1;
+pub const OBJECT_TYPE_LC: u8 = 2;
+pub const OBJECT_TYPE_SSR: u8 = 3;
+pub const OBJECT_TYPE_LTP: u8 = 4;
+/// Spectral band replication (HE-AAC), layered on top of another object type.
+const OBJECT_TYPE_SBR: u8 = 5;
+
+/// The sampling rates that an AudioSpecificConfig can refer to by index.
+const SAMPLING_FREQUENCIES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350
+];
+
+impl AacHeaders {
+    /// Builds an AudioSpecificConfig for streams that don't come with one. If `sbr` is true, the
+    /// stream is HE-AAC, `sample_rate` is the rate of the core AAC stream, and the output runs at
+    /// twice that rate.
+    pub fn from_parameters(object_type: u8, sample_rate: u32, channels: u16, sbr: bool)
+                           -> AacHeaders {
+        let mut writer = BitWriter::new();
+        if sbr {
+            writer.write(OBJECT_TYPE_SBR as u32, 5);
+            write_sampling_frequency(&mut writer, sample_rate);
+            writer.write(channels as u32, 4);
+            write_sampling_frequency(&mut writer, sample_rate * 2);
+            writer.write(object_type as u32, 5);
+        } else {
+            writer.write(object_type as u32, 5);
+            write_sampling_frequency(&mut writer, sample_rate);
+            writer.write(channels as u32, 4);
+        }
+
+        // The GASpecificConfig: 1024-sample frames, no core coder, and no extension.
+        writer.write(0, 3);
+        AacHeaders {
+            esds_chunk: writer.bytes,
+        }
+    }
+}
+
+fn write_sampling_frequency(writer: &mut BitWriter, sample_rate: u32) {
+    match SAMPLING_FREQUENCIES.iter().position(|&frequency| frequency == sample_rate) {
+        Some(index) => writer.write(index as u32, 4),
+        None => {
+            writer.write(0xf, 4);
+            writer.write(sample_rate, 24)
+        }
+    }
+}
+
+/// Writes values most significant bit first, padding the last byte with zeroes.
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit_count: usize,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            bit_count: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, bits: usize) {
+        for bit_index in range(0, bits).rev() {
+            if self.bit_count % 8 == 0 {
+                self.bytes.push(0)
+            }
+            let bit = ((value >> bit_index) & 1) as u8;
+            *self.bytes.last_mut().unwrap() |= bit << (7 - self.bit_count % 8);
+            self.bit_count += 1
+        }
+    }
+}
@@ -7,6 +7,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use error::MediaError;
 use videodecoder::VideoHeaders;
 
 /// Constructs an AVCC chunk from a set of decoder headers.
@@ -20,7 +21,7 @@ pub fn create_avcc_chunk(headers: &VideoHeaders) -> Vec<u8> {
         seq_headers[0][1],
         seq_headers[0][2],
         seq_headers[0][3],
-        0xff,   // 4 bytes per NALU
+        0b11111100 | (headers.h264_nal_length_size() - 1),
         (seq_headers.len() as u8) | 0b11100000,
     ]);
 
@@ -38,3 +39,72 @@ pub fn create_avcc_chunk(headers: &VideoHeaders) -> Vec<u8> {
     avcc
 }
 
+/// Decoder headers read out of an AVCC chunk (an `AVCDecoderConfigurationRecord`), as found in the
+/// codec private data of Matroska tracks.
+pub struct AvccHeaders {
+    seq_headers: Vec<Vec<u8>>,
+    pict_headers: Vec<Vec<u8>>,
+    nal_length_size: u8,
+}
+
+impl AvccHeaders {
+    /// Parses an AVCC chunk. This is the inverse of `create_avcc_chunk()`.
+    pub fn parse(avcc: &[u8]) -> Result<AvccHeaders,MediaError> {
+        if avcc.len() < 6 || avcc[0] != 1 {
+            return Err(MediaError::Malformed)
+        }
+        // Lengths of 1, 2, or 4 bytes are allowed.
+        let nal_length_size = (avcc[4] & 0b11) + 1;
+        if nal_length_size == 3 {
+            return Err(MediaError::Malformed)
+        }
+        let mut data = &avcc[5..];
+        let seq_headers = try!(read_parameter_sets(&mut data, 0b00011111));
+        let pict_headers = try!(read_parameter_sets(&mut data, 0xff));
+        if seq_headers.is_empty() {
+            return Err(MediaError::Malformed)
+        }
+        Ok(AvccHeaders {
+            seq_headers: seq_headers,
+            pict_headers: pict_headers,
+            nal_length_size: nal_length_size,
+        })
+    }
+}
+
+/// Reads a count, masked with `count_mask`, followed by that many parameter sets, each preceded by
+/// its 16-bit big-endian length.
+fn read_parameter_sets(data: &mut &[u8], count_mask: u8) -> Result<Vec<Vec<u8>>,MediaError> {
+    if data.is_empty() {
+        return Err(MediaError::Malformed)
+    }
+    let count = data[0] & count_mask;
+    *data = &(*data)[1..];
+    let mut parameter_sets = Vec::new();
+    for _ in range(0, count) {
+        if data.len() < 2 {
+            return Err(MediaError::Malformed)
+        }
+        let length = ((data[0] as usize) << 8) | (data[1] as usize);
+        if data.len() - 2 < length {
+            return Err(MediaError::Malformed)
+        }
+        parameter_sets.push(data[2..2 + length].to_vec());
+        *data = &(*data)[2 + length..];
+    }
+    Ok(parameter_sets)
+}
+
+impl VideoHeaders for AvccHeaders {
+    fn h264_seq_headers<'a>(&'a self) -> Option<Vec<&'a [u8]>> {
+        Some(self.seq_headers.iter().map(|header| header.as_slice()).collect())
+    }
+
+    fn h264_pict_headers<'a>(&'a self) -> Option<Vec<&'a [u8]>> {
+        Some(self.pict_headers.iter().map(|header| header.as_slice()).collect())
+    }
+
+    fn h264_nal_length_size(&self) -> u8 {
+        self.nal_length_size
+    }
+}
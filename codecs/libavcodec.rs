@@ -11,6 +11,7 @@
 
 use audiodecoder;
 use codecs::h264;
+use error::MediaError;
 use pixelformat::PixelFormat;
 use timing::Timestamp;
 use videodecoder;
@@ -83,7 +84,8 @@ impl AvCodecContext {
         }
     }
 
-    pub fn open(&self, codec: &AvCodec, options: AvDictionary) -> (Result<(),()>, AvDictionary) {
+    pub fn open(&self, codec: &AvCodec, options: AvDictionary)
+                -> (Result<(),c_int>, AvDictionary) {
         // The memory management that `libavcodec` expects around the `options` argument is really
         // weird.
         let mut options_not_found = options.dictionary;
@@ -98,7 +100,7 @@ impl AvCodecContext {
         if result == 0 {
             (Ok(()), options_not_found)
         } else {
-            (Err(()), options_not_found)
+            (Err(result), options_not_found)
         }
     }
 
@@ -134,7 +136,8 @@ impl AvCodecContext {
         }
     }
 
-    pub fn decode_video(&self, picture: &AvFrame, packet: &mut AvPacket) -> Result<bool,()> {
+    /// Decodes a video packet. Returns true if a picture was produced.
+    pub fn decode_video(&self, picture: &AvFrame, packet: &mut AvPacket) -> Result<bool,c_int> {
         let mut got_picture = 0;
         let result = unsafe {
             ffi::avcodec_decode_video2(self.context.ptr(),
@@ -142,14 +145,16 @@ impl AvCodecContext {
                                        &mut got_picture,
                                        packet.packet.ptr())
         };
-        if result >= 0 && got_picture != 0 {
-            Ok(result > 0)
+        if result >= 0 {
+            Ok(got_picture != 0)
         } else {
-            Err(())
+            Err(result)
         }
     }
 
-    pub fn decode_audio(&self, frame: &AvFrame, packet: &mut AvPacket) -> Result<c_int,()> {
+    /// Decodes an audio packet. Returns the number of bytes consumed if a frame was produced.
+    pub fn decode_audio(&self, frame: &AvFrame, packet: &mut AvPacket)
+                        -> Result<Option<c_int>,c_int> {
         let mut got_frame = 0;
         let result = unsafe {
             ffi::avcodec_decode_audio4(self.context.ptr(),
@@ -157,10 +162,12 @@ impl AvCodecContext {
                                        &mut got_frame,
                                        packet.packet.ptr())
         };
-        if result >= 0 && got_frame != 0 {
-            Ok(result)
+        if result < 0 {
+            Err(result)
+        } else if got_frame != 0 {
+            Ok(Some(result))
         } else {
-            Err(())
+            Ok(None)
         }
     }
 
@@ -442,6 +449,10 @@ pub mod samples {
     }
 }
 
+fn libavcodec_error(err: c_int) -> MediaError {
+    MediaError::backend(err as i64, "libavcodec")
+}
+
 // Implementation of the abstract `VideoDecoder` interface
 
 #[allow(dead_code)]
@@ -452,15 +463,20 @@ struct VideoDecoderImpl {
 
 impl VideoDecoderImpl {
     fn h264(headers: &videodecoder::VideoHeaders, _: i32, _: i32)
-           -> Result<Box<videodecoder::VideoDecoder + 'static>,()> {
+           -> Result<Box<videodecoder::VideoDecoder + 'static>,MediaError> {
         init();
 
         let avcc = h264::create_avcc_chunk(headers);
-        let codec = try!(AvCodec::find_decoder(AV_CODEC_ID_H264));
+        let codec = match AvCodec::find_decoder(AV_CODEC_ID_H264) {
+            Ok(codec) => codec,
+            Err(_) => return Err(MediaError::UnsupportedCodec),
+        };
         let mut context = AvCodecContext::new(&codec);
         context.set_extra_data(avcc);
         let (result, _) = context.open(&codec, AvDictionary::new());
-        try!(result);
+        if let Err(err) = result {
+            return Err(libavcodec_error(err))
+        }
         Ok(Box::new(VideoDecoderImpl {
             codec: codec,
             context: RefCell::new(context),
@@ -470,7 +486,7 @@ impl VideoDecoderImpl {
 
 impl videodecoder::VideoDecoder for VideoDecoderImpl {
     fn decode_frame(&self, data: &[u8], presentation_time: &Timestamp)
-                    -> Result<Box<videodecoder::DecodedVideoFrame + 'static>,()> {
+                    -> Result<Box<videodecoder::DecodedVideoFrame + 'static>,MediaError> {
         let mut data: Vec<_> = data.iter().map(|x| *x).collect();
         for _ in range(0, FF_INPUT_BUFFER_PADDING_SIZE) {
             data.push(0);
@@ -489,7 +505,8 @@ impl videodecoder::VideoDecoder for VideoDecoderImpl {
                     frame: frame,
                 }) as Box<videodecoder::DecodedVideoFrame>)
             }
-            Ok(false) | Err(_) => Err(()),
+            Ok(false) => Err(MediaError::NeedMoreData),
+            Err(err) => Err(libavcodec_error(err)),
         }
     }
 }
@@ -551,20 +568,23 @@ struct AudioDecoderInfoImpl {
 
 impl AudioDecoderInfoImpl {
     fn aac(_: &audiodecoder::AudioHeaders, sample_rate: f64, channels: u16)
-           -> Box<audiodecoder::AudioDecoderInfo + 'static> {
-        Box::new(AudioDecoderInfoImpl {
+           -> Result<Box<audiodecoder::AudioDecoderInfo + 'static>,MediaError> {
+        Ok(Box::new(AudioDecoderInfoImpl {
             sample_rate: sample_rate as c_int,
             channels: channels as c_int,
-        })
+        }) as Box<audiodecoder::AudioDecoderInfo + 'static>)
     }
 }
 
 impl audiodecoder::AudioDecoderInfo for AudioDecoderInfoImpl {
     fn create_decoder(self: Box<AudioDecoderInfoImpl>)
-                      -> Box<audiodecoder::AudioDecoder + 'static> {
+                      -> Result<Box<audiodecoder::AudioDecoder + 'static>,MediaError> {
         init();
 
-        let codec = AvCodec::find_decoder(AV_CODEC_ID_AAC).unwrap();
+        let codec = match AvCodec::find_decoder(AV_CODEC_ID_AAC) {
+            Ok(codec) => codec,
+            Err(_) => return Err(MediaError::UnsupportedCodec),
+        };
         let context = AvCodecContext::new(&codec);
         let mut options = AvDictionary::new();
         options.set("ac", self.channels.to_string().as_slice());
@@ -572,11 +592,13 @@ impl audiodecoder::AudioDecoderInfo for AudioDecoderInfoImpl {
         options.set("request_sample_fmt", "fltp");
 
         let (result, _) = context.open(&codec, options);
-        result.unwrap();
-        Box::new(AudioDecoderImpl {
+        if let Err(err) = result {
+            return Err(libavcodec_error(err))
+        }
+        Ok(Box::new(AudioDecoderImpl {
             context: context,
             frame: None,
-        }) as Box<audiodecoder::AudioDecoder + 'static>
+        }) as Box<audiodecoder::AudioDecoder + 'static>)
     }
 }
 
@@ -586,7 +608,7 @@ struct AudioDecoderImpl {
 }
 
 impl audiodecoder::AudioDecoder for AudioDecoderImpl {
-    fn decode(&mut self, data: &[u8]) -> Result<(),()> {
+    fn decode(&mut self, data: &[u8]) -> Result<(),MediaError> {
         let data_len = data.len();
         let mut data: Vec<_> = data.iter().map(|x| *x).collect();
         for _ in range(0, FF_INPUT_BUFFER_PADDING_SIZE) {
@@ -596,16 +618,18 @@ impl audiodecoder::AudioDecoder for AudioDecoderImpl {
         let frame = AvFrame::new();
         let result = self.context.decode_audio(&frame, &mut packet);
         match result {
-            Ok(length) if length as usize == data_len => {
+            Ok(Some(length)) if length as usize == data_len => {
                 self.frame = Some(frame);
                 Ok(())
             }
-            _ => Err(()),
+            Ok(Some(_)) => Err(MediaError::Malformed),
+            Ok(None) => Err(MediaError::NeedMoreData),
+            Err(err) => Err(libavcodec_error(err)),
         }
     }
 
     fn decoded_samples<'a>(&'a mut self)
-                           -> Result<Box<audiodecoder::DecodedAudioSamples + 'a>,()> {
+                           -> Result<Box<audiodecoder::DecodedAudioSamples + 'a>,MediaError> {
         match self.frame {
             Some(ref frame) => {
                 Ok(Box::new(DecodedAudioSamplesImpl {
@@ -613,7 +637,7 @@ impl audiodecoder::AudioDecoder for AudioDecoderImpl {
                     channels: self.context.channels(),
                 }) as Box<audiodecoder::DecodedAudioSamples>)
             }
-            None => Err(()),
+            None => Err(MediaError::NeedMoreData),
         }
     }
 
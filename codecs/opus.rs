@@ -0,0 +1,414 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Opus audio decoding, via `libopus`. The header parsing is always available to the container
+//! readers; the decoder itself requires the `opus` feature.
+
+use audiodecoder;
+use codecs::aac::AacHeaders;
+use codecs::vorbis::VorbisHeaders;
+use container::MetadataTag;
+use error::MediaError;
+
+#[cfg(feature="opus")]
+use libc::{c_float, c_int};
+#[cfg(feature="opus")]
+use std::cmp;
+
+/// The rate at which Opus always runs internally. Granule positions and the pre-skip count samples
+/// at this rate, whatever the rate of the original input was.
+pub const SAMPLE_RATE: u32 = 48000;
+
+/// The number of samples per channel in the longest possible packet: 120 ms at 48 kHz.
+#[cfg(feature="opus")]
+const MAX_FRAME_SIZE: usize = 5760;
+
+/// The contents of the `OpusHead` identification header.
+pub struct OpusHead {
+    pub channels: u8,
+    /// The number of samples, at 48 kHz, to discard from the start of the decoded output.
+    pub pre_skip: u16,
+    /// The sampling rate of the original input. This is for information only.
+    pub input_sample_rate: u32,
+    /// The gain to apply to the decoded output, in units of 1/256 dB.
+    pub output_gain: i16,
+    pub stream_count: u8,
+    pub coupled_count: u8,
+    /// The decoded channel that each output channel comes from.
+    pub mapping: Vec<u8>,
+}
+
+impl OpusHead {
+    pub fn parse(data: &[u8]) -> Result<OpusHead,MediaError> {
+        if data.len() < 19 || !data.starts_with(b"OpusHead") {
+            return Err(MediaError::Malformed)
+        }
+
+        // Only the major version, in the upper four bits, affects compatibility.
+        if (data[8] >> 4) != 0 {
+            return Err(MediaError::UnsupportedCodec)
+        }
+        let channels = data[9];
+        if channels == 0 {
+            return Err(MediaError::Malformed)
+        }
+
+        let (stream_count, coupled_count, mapping) = match data[18] {
+            // Mono or stereo, with no mapping table.
+            0 => {
+                if channels > 2 {
+                    return Err(MediaError::Malformed)
+                }
+                (1, channels - 1, range(0, channels).collect())
+            }
+            _ => {
+                if data.len() < 21 + channels as usize {
+                    return Err(MediaError::Malformed)
+                }
+                (data[19], data[20], data[21..21 + channels as usize].to_vec())
+            }
+        };
+        if stream_count == 0 || coupled_count > stream_count {
+            return Err(MediaError::Malformed)
+        }
+
+        Ok(OpusHead {
+            channels: channels,
+            pre_skip: read_u16_le(&data[10..]),
+            input_sample_rate: read_u32_le(&data[12..]),
+            output_gain: read_u16_le(&data[16..]) as i16,
+            stream_count: stream_count,
+            coupled_count: coupled_count,
+            mapping: mapping,
+        })
+    }
+}
+
+/// Returns the number of samples per channel, at 48 kHz, that a packet decodes to, from its
+/// table-of-contents byte. Returns `None` if the packet is malformed.
+pub fn packet_sample_count(packet: &[u8]) -> Option<u32> {
+    if packet.is_empty() {
+        return None
+    }
+    let config = packet[0] >> 3;
+    let frame_size = if config < 12 {
+        // SILK only: 10, 20, 40 or 60 ms.
+        [480u32, 960, 1920, 2880][(config & 3) as usize]
+    } else if config < 16 {
+        // Hybrid: 10 or 20 ms.
+        [480u32, 960][(config & 1) as usize]
+    } else {
+        // CELT only: 2.5, 5, 10 or 20 ms.
+        [120u32, 240, 480, 960][(config & 3) as usize]
+    };
+    let frame_count = match packet[0] & 3 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => {
+            if packet.len() < 2 {
+                return None
+            }
+            (packet[1] & 0x3f) as u32
+        }
+    };
+    Some(frame_size * frame_count)
+}
+
+/// Parses an `OpusTags` header and returns its tags. The layout is that of a Vorbis comment
+/// header, minus the framing bit.
+pub fn comment_tags(packet: &[u8]) -> Option<Vec<MetadataTag>> {
+    if !packet.starts_with(b"OpusTags") {
+        return None
+    }
+    let mut data = &packet[8..];
+    if read_field(&mut data).is_none() {
+        return None
+    }
+    if data.len() < 4 {
+        return None
+    }
+    let comment_count = read_u32_le(data);
+    data = &data[4..];
+
+    let mut tags = Vec::new();
+    for _ in range(0, comment_count) {
+        let comment = match read_field(&mut data) {
+            Some(comment) => comment,
+            None => break,
+        };
+        if let Some(tag) = MetadataTag::from_comment(comment) {
+            tags.push(tag)
+        }
+    }
+    Some(tags)
+}
+
+/// Reads a field preceded by its 32-bit little-endian length, and advances `data` past it.
+fn read_field<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+    if data.len() < 4 {
+        return None
+    }
+    let length = read_u32_le(*data) as usize;
+    if data.len() - 4 < length {
+        return None
+    }
+    let field = &(*data)[4..4 + length];
+    *data = &(*data)[4 + length..];
+    Some(field)
+}
+
+fn read_u16_le(data: &[u8]) -> u16 {
+    (data[0] as u16) | ((data[1] as u16) << 8)
+}
+
+fn read_u32_le(data: &[u8]) -> u32 {
+    (data[0] as u32) | ((data[1] as u32) << 8) | ((data[2] as u32) << 16) |
+        ((data[3] as u32) << 24)
+}
+
+#[cfg(feature="opus")]
+pub struct MultistreamDecoder {
+    decoder: *mut ffi::OpusMSDecoder,
+    channels: c_int,
+}
+
+#[cfg(feature="opus")]
+impl Drop for MultistreamDecoder {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::opus_multistream_decoder_destroy(self.decoder)
+        }
+    }
+}
+
+#[cfg(feature="opus")]
+impl MultistreamDecoder {
+    pub fn new(sample_rate: i32, head: &OpusHead) -> Result<MultistreamDecoder,c_int> {
+        let mut err = 0;
+        let decoder = unsafe {
+            ffi::opus_multistream_decoder_create(sample_rate,
+                                                 head.channels as c_int,
+                                                 head.stream_count as c_int,
+                                                 head.coupled_count as c_int,
+                                                 head.mapping.as_ptr(),
+                                                 &mut err)
+        };
+        if decoder.is_null() || err != ffi::OPUS_OK {
+            return Err(err)
+        }
+        Ok(MultistreamDecoder {
+            decoder: decoder,
+            channels: head.channels as c_int,
+        })
+    }
+
+    /// Sets the gain to apply to the output, in units of 1/256 dB.
+    pub fn set_gain(&mut self, gain: i16) -> Result<(),c_int> {
+        let err = unsafe {
+            ffi::opus_multistream_decoder_ctl(self.decoder,
+                                              ffi::OPUS_SET_GAIN_REQUEST,
+                                              gain as c_int)
+        };
+        if err == ffi::OPUS_OK {
+            Ok(())
+        } else {
+            Err(err)
+        }
+    }
+
+    /// Decodes a packet into `pcm`, with the channels interleaved, and returns the number of
+    /// samples per channel.
+    pub fn decode_float(&mut self, packet: &[u8], pcm: &mut [c_float]) -> Result<usize,c_int> {
+        let result = unsafe {
+            ffi::opus_multistream_decode_float(self.decoder,
+                                               packet.as_ptr(),
+                                               packet.len() as i32,
+                                               pcm.as_mut_ptr(),
+                                               pcm.len() as c_int / self.channels,
+                                               0)
+        };
+        if result >= 0 {
+            Ok(result as usize)
+        } else {
+            Err(result)
+        }
+    }
+}
+
+// Implementation of the abstract `AudioDecoder` interface
+
+pub struct OpusHeaders {
+    /// The `OpusHead` packet, which Matroska stores as the codec private data.
+    pub id: Vec<u8>,
+}
+
+impl audiodecoder::AudioHeaders for OpusHeaders {
+    fn vorbis_headers<'a>(&'a self) -> Option<&'a VorbisHeaders> {
+        None
+    }
+    fn aac_headers<'a>(&'a self) -> Option<&'a AacHeaders> {
+        None
+    }
+    fn opus_headers<'a>(&'a self) -> Option<&'a OpusHeaders> {
+        Some(self)
+    }
+}
+
+#[cfg(feature="opus")]
+struct AudioDecoderInfoImpl {
+    head: OpusHead,
+}
+
+#[cfg(feature="opus")]
+impl AudioDecoderInfoImpl {
+    pub fn new(headers: &audiodecoder::AudioHeaders, _: f64, _: u16)
+               -> Result<Box<audiodecoder::AudioDecoderInfo + 'static>,MediaError> {
+        let headers = match headers.opus_headers() {
+            Some(headers) => headers,
+            None => return Err(MediaError::Malformed),
+        };
+        let head = try!(OpusHead::parse(headers.id.as_slice()));
+        Ok(Box::new(AudioDecoderInfoImpl {
+            head: head,
+        }) as Box<audiodecoder::AudioDecoderInfo + 'static>)
+    }
+}
+
+#[cfg(feature="opus")]
+impl audiodecoder::AudioDecoderInfo for AudioDecoderInfoImpl {
+    fn create_decoder(self: Box<AudioDecoderInfoImpl>)
+                      -> Result<Box<audiodecoder::AudioDecoder + 'static>,MediaError> {
+        // Container readers report Opus tracks at 48 kHz, whatever the rate of the original input.
+        let mut decoder = match MultistreamDecoder::new(SAMPLE_RATE as i32, &self.head) {
+            Ok(decoder) => decoder,
+            Err(err) => return Err(opus_error(err)),
+        };
+        if let Err(err) = decoder.set_gain(self.head.output_gain) {
+            return Err(opus_error(err))
+        }
+
+        let channels = self.head.channels as usize;
+        Ok(Box::new(AudioDecoderImpl {
+            decoder: decoder,
+            buffer: range(0, MAX_FRAME_SIZE * channels).map(|_| 0.0).collect(),
+            samples: range(0, channels).map(|_| Vec::new()).collect(),
+            samples_to_skip: self.head.pre_skip as usize,
+        }) as Box<audiodecoder::AudioDecoder + 'static>)
+    }
+}
+
+#[cfg(feature="opus")]
+struct AudioDecoderImpl {
+    decoder: MultistreamDecoder,
+    /// Interleaved output from the decoder.
+    buffer: Vec<c_float>,
+    /// Decoded samples that haven't been acknowledged yet, one vector per channel.
+    samples: Vec<Vec<c_float>>,
+    /// The number of samples still to discard from the start of the output. The encoder puts
+    /// these there to prime itself, so they are only present at the start of the stream.
+    samples_to_skip: usize,
+}
+
+#[cfg(feature="opus")]
+impl audiodecoder::AudioDecoder for AudioDecoderImpl {
+    fn decode(&mut self, data: &[u8]) -> Result<(),MediaError> {
+        let sample_count = match self.decoder.decode_float(data, self.buffer.as_mut_slice()) {
+            Ok(sample_count) => sample_count,
+            Err(err) => return Err(opus_error(err)),
+        };
+
+        let skip = cmp::min(self.samples_to_skip, sample_count);
+        self.samples_to_skip -= skip;
+        let channels = self.samples.len();
+        for (channel_index, channel) in self.samples.iter_mut().enumerate() {
+            for sample_index in range(skip, sample_count) {
+                channel.push(self.buffer[sample_index * channels + channel_index])
+            }
+        }
+        Ok(())
+    }
+
+    fn decoded_samples<'b>(&'b mut self)
+                           -> Result<Box<audiodecoder::DecodedAudioSamples + 'b>,MediaError> {
+        Ok(Box::new(DecodedAudioSamplesImpl {
+            samples: self.samples.as_slice(),
+        }) as Box<audiodecoder::DecodedAudioSamples + 'b>)
+    }
+
+    fn acknowledge(&mut self, sample_count: c_int) {
+        for channel in self.samples.iter_mut() {
+            let sample_count = cmp::min(sample_count as usize, channel.len());
+            let remaining = channel[sample_count..].to_vec();
+            *channel = remaining
+        }
+    }
+
+    fn start_mid_stream(&mut self) {
+        self.samples_to_skip = 0
+    }
+}
+
+#[cfg(feature="opus")]
+struct DecodedAudioSamplesImpl<'a> {
+    samples: &'a [Vec<c_float>],
+}
+
+#[cfg(feature="opus")]
+impl<'a> audiodecoder::DecodedAudioSamples for DecodedAudioSamplesImpl<'a> {
+    fn samples<'b>(&'b self, channel: i32) -> Option<&'b [f32]> {
+        self.samples.get(channel as usize).map(|samples| samples.as_slice())
+    }
+}
+
+#[cfg(feature="opus")]
+fn opus_error(err: c_int) -> MediaError {
+    MediaError::backend(err as i64, "libopus")
+}
+
+#[cfg(feature="opus")]
+pub const AUDIO_DECODER: audiodecoder::RegisteredAudioDecoder =
+    audiodecoder::RegisteredAudioDecoder {
+        id: [ b'o', b'p', b'u', b's' ],
+        constructor: AudioDecoderInfoImpl::new,
+    };
+
+#[cfg(feature="opus")]
+#[allow(missing_copy_implementations)]
+#[allow(non_snake_case)]
+pub mod ffi {
+    use libc::{c_float, c_int, c_uchar};
+
+    #[repr(C)]
+    pub struct OpusMSDecoder;
+
+    pub const OPUS_OK: c_int = 0;
+    pub const OPUS_SET_GAIN_REQUEST: c_int = 4034;
+
+    #[link(name="opus")]
+    extern {
+        pub fn opus_multistream_decoder_create(Fs: i32,
+                                               channels: c_int,
+                                               streams: c_int,
+                                               coupled_streams: c_int,
+                                               mapping: *const c_uchar,
+                                               error: *mut c_int)
+                                               -> *mut OpusMSDecoder;
+        pub fn opus_multistream_decoder_destroy(st: *mut OpusMSDecoder);
+        pub fn opus_multistream_decode_float(st: *mut OpusMSDecoder,
+                                             data: *const c_uchar,
+                                             len: i32,
+                                             pcm: *mut c_float,
+                                             frame_size: c_int,
+                                             decode_fec: c_int)
+                                             -> c_int;
+        pub fn opus_multistream_decoder_ctl(st: *mut OpusMSDecoder, request: c_int, ...)
+                                            -> c_int;
+    }
+}
+
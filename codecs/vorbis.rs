@@ -9,9 +9,12 @@
 
 use audiodecoder;
 use codecs::aac::AacHeaders;
+use codecs::opus::OpusHeaders;
+use container::MetadataTag;
 use containers::ogg::Packet;
+use error::MediaError;
 
-use libc::{c_float, c_int};
+use libc::{c_float, c_int, c_long};
 use std::mem;
 use std::ptr;
 use std::slice;
@@ -52,6 +55,27 @@ impl VorbisInfo {
             Err(err)
         }
     }
+
+    pub fn channels(&self) -> c_int {
+        self.info.channels
+    }
+
+    pub fn rate(&self) -> c_long {
+        self.info.rate
+    }
+
+    /// Returns the block size of an audio packet, in samples. All three headers must have been
+    /// read first.
+    pub fn packet_blocksize(&mut self, packet: &mut Packet) -> Result<c_long,c_long> {
+        let size = unsafe {
+            ffi::vorbis_packet_blocksize(&mut *self.info, packet.raw_packet())
+        };
+        if size >= 0 {
+            Ok(size)
+        } else {
+            Err(size)
+        }
+    }
 }
 
 pub struct VorbisComment {
@@ -76,6 +100,23 @@ impl VorbisComment {
             }
         }
     }
+
+    /// Returns the user comments as tags. Comments are of the form `KEY=value`; any that aren't
+    /// are skipped.
+    pub fn tags(&self) -> Vec<MetadataTag> {
+        let mut tags = Vec::new();
+        for i in range(0, self.comment.comments as isize) {
+            let comment = unsafe {
+                let length = *self.comment.comment_lengths.offset(i);
+                let ptr = *self.comment.user_comments.offset(i) as *const u8;
+                slice::from_raw_buf(&ptr, length as usize)
+            };
+            if let Some(tag) = MetadataTag::from_comment(comment) {
+                tags.push(tag)
+            }
+        }
+        tags
+    }
 }
 
 #[allow(dead_code)]
@@ -236,6 +277,15 @@ impl VorbisHeaders {
     pub fn setup<'a>(&'a self) -> &'a [u8] {
         &self.data.as_slice()[self.id_size + self.comment_size..]
     }
+
+    /// Parses the comment header and returns its tags.
+    pub fn tags(&self) -> Result<Vec<MetadataTag>,c_int> {
+        let mut info = VorbisInfo::new();
+        let mut comment = VorbisComment::new();
+        try!(info.header_in(&mut comment, &mut Packet::new(self.id(), 0)));
+        try!(info.header_in(&mut comment, &mut Packet::new(self.comment(), 1)));
+        Ok(comment.tags())
+    }
 }
 
 impl audiodecoder::AudioHeaders for VorbisHeaders {
@@ -245,6 +295,9 @@ impl audiodecoder::AudioHeaders for VorbisHeaders {
     fn aac_headers<'a>(&'a self) -> Option<&'a AacHeaders> {
         None
     }
+    fn opus_headers<'a>(&'a self) -> Option<&'a OpusHeaders> {
+        None
+    }
 }
 
 struct AudioDecoderInfoImpl {
@@ -253,26 +306,37 @@ struct AudioDecoderInfoImpl {
 
 impl AudioDecoderInfoImpl {
     pub fn new(headers: &audiodecoder::AudioHeaders, _: f64, _: u16)
-               -> Box<audiodecoder::AudioDecoderInfo + 'static> {
+               -> Result<Box<audiodecoder::AudioDecoderInfo + 'static>,MediaError> {
         let mut info = VorbisInfo::new();
         let mut comment = VorbisComment::new();
-        let headers = headers.vorbis_headers().unwrap();
-        info.header_in(&mut comment, &mut Packet::new(headers.id(), 0)).unwrap();
-        info.header_in(&mut comment, &mut Packet::new(headers.comment(), 1)).unwrap();
-        info.header_in(&mut comment, &mut Packet::new(headers.setup(), 2)).unwrap();
-        Box::new(AudioDecoderInfoImpl {
+        let headers = match headers.vorbis_headers() {
+            Some(headers) => headers,
+            None => return Err(MediaError::Malformed),
+        };
+        let packets = [headers.id(), headers.comment(), headers.setup()];
+        for (packet_index, packet) in packets.iter().enumerate() {
+            if let Err(err) = info.header_in(&mut comment,
+                                             &mut Packet::new(*packet, packet_index as i64)) {
+                return Err(vorbis_error(err))
+            }
+        }
+        Ok(Box::new(AudioDecoderInfoImpl {
             info: info,
-        }) as Box<audiodecoder::AudioDecoderInfo + 'static>
+        }) as Box<audiodecoder::AudioDecoderInfo + 'static>)
     }
 }
 
 impl audiodecoder::AudioDecoderInfo for AudioDecoderInfoImpl {
     fn create_decoder(self: Box<AudioDecoderInfoImpl>)
-                      -> Box<audiodecoder::AudioDecoder + 'static> {
-        Box::new(AudioDecoderImpl {
-            state: VorbisDspState::new(self.info).unwrap(),
+                      -> Result<Box<audiodecoder::AudioDecoder + 'static>,MediaError> {
+        let state = match VorbisDspState::new(self.info) {
+            Ok(state) => state,
+            Err(err) => return Err(vorbis_error(err)),
+        };
+        Ok(Box::new(AudioDecoderImpl {
+            state: state,
             packet_index: 3,
-        }) as Box<audiodecoder::AudioDecoder + 'static>
+        }) as Box<audiodecoder::AudioDecoder + 'static>)
     }
 }
 
@@ -282,28 +346,31 @@ struct AudioDecoderImpl {
 }
 
 impl audiodecoder::AudioDecoder for AudioDecoderImpl {
-    fn decode(&mut self, data: &[u8]) -> Result<(),()> {
-        let mut block = VorbisBlock::new(&mut self.state).unwrap();
+    fn decode(&mut self, data: &[u8]) -> Result<(),MediaError> {
+        let mut block = match VorbisBlock::new(&mut self.state) {
+            Ok(block) => block,
+            Err(err) => return Err(vorbis_error(err)),
+        };
         let result = block.synthesis(&mut Packet::new(data, self.packet_index));
         self.packet_index += 1;
-        if result.is_err() {
-            return Err(())
+        if let Err(err) = result {
+            return Err(vorbis_error(err))
         }
         match block.block_in() {
             Ok(_) => Ok(()),
-            Err(_) => Err(()),
+            Err(err) => Err(vorbis_error(err)),
         }
     }
 
     fn decoded_samples<'b>(&'b mut self)
-                           -> Result<Box<audiodecoder::DecodedAudioSamples + 'b>,()> {
+                           -> Result<Box<audiodecoder::DecodedAudioSamples + 'b>,MediaError> {
         match self.state.pcm_out() {
             Ok(pcm) => {
                 Ok(Box::new(DecodedAudioSamplesImpl {
                     pcm: pcm,
                 }) as Box<audiodecoder::DecodedAudioSamples + 'b>)
             }
-            Err(_) => Err(()),
+            Err(err) => Err(vorbis_error(err)),
         }
     }
 
@@ -322,6 +389,10 @@ impl<'a> audiodecoder::DecodedAudioSamples for DecodedAudioSamplesImpl<'a> {
     }
 }
 
+fn vorbis_error(err: c_int) -> MediaError {
+    MediaError::backend(err as i64, "libvorbis")
+}
+
 pub const AUDIO_DECODER: audiodecoder::RegisteredAudioDecoder =
     audiodecoder::RegisteredAudioDecoder {
         id: [ b'v', b'o', b'r', b'b' ],
@@ -436,6 +507,7 @@ pub mod ffi {
         pub fn vorbis_synthesis_pcmout(v: *mut vorbis_dsp_state, pcm: *mut *mut *mut c_float)
                                        -> c_int;
         pub fn vorbis_synthesis_read(v: *mut vorbis_dsp_state, samples: c_int) -> c_int;
+        pub fn vorbis_packet_blocksize(vi: *mut vorbis_info, op: *mut ogg_packet) -> c_long;
     }
 
     #[link(name="rustogg")]
@@ -9,11 +9,13 @@
 
 #![allow(missing_copy_implementations)]
 
+use error::MediaError;
 use pixelformat::PixelFormat;
 use timing::Timestamp;
 use videodecoder;
 
 use libc::{c_int, c_long, c_uint};
+use std::ffi::c_str_to_bytes;
 use std::ptr;
 use std::slice;
 use std::u32;
@@ -30,6 +32,14 @@ impl VpxCodecIface {
             },
         }
     }
+
+    pub fn vp9() -> VpxCodecIface {
+        VpxCodecIface {
+            iface: unsafe {
+                ffi::vpx_codec_vp9_dx()
+            },
+        }
+    }
 }
 
 pub struct VpxCodec {
@@ -150,7 +160,14 @@ impl VpxImage {
     pub fn plane<'a>(&'a self, index: c_uint) -> &'a [u8] {
         assert!(index < 4);
         unsafe {
-            let len = (self.stride(index) as c_uint) * (*self.image).h;
+            // The chroma planes have fewer rows than the luma plane if they're subsampled
+            // vertically.
+            let mut height = (*self.image).h;
+            if index == 1 || index == 2 {
+                let shift = (*self.image).y_chroma_shift as usize;
+                height = (height + (1 << shift) - 1) >> shift
+            }
+            let len = (self.stride(index) as c_uint) * height;
             slice::from_raw_mut_buf(&(*self.image).planes[index as usize], len as usize)
         }
     }
@@ -168,6 +185,16 @@ impl VpxImage {
     }
 }
 
+/// Converts a libvpx error code to a `MediaError`, preserving the code and libvpx's description
+/// of it.
+fn vpx_error(err: ffi::vpx_codec_err_t) -> MediaError {
+    let description = unsafe {
+        let description = ffi::vpx_codec_err_to_string(err);
+        String::from_utf8_lossy(c_str_to_bytes(&description)).into_owned()
+    };
+    MediaError::backend(err as i64, format!("libvpx: {}", description).as_slice())
+}
+
 // Implementation of the abstract `VideoDecoder` interface
 
 struct VideoDecoderImpl {
@@ -175,34 +202,51 @@ struct VideoDecoderImpl {
 }
 
 impl VideoDecoderImpl {
-    fn new(_: &videodecoder::VideoHeaders, _: i32, _: i32)
-           -> Result<Box<videodecoder::VideoDecoder + 'static>,()> {
-        match VpxCodec::init(&VpxCodecIface::vp8()) {
+    fn new(iface: &VpxCodecIface)
+           -> Result<Box<videodecoder::VideoDecoder + 'static>,MediaError> {
+        match VpxCodec::init(iface) {
             Ok(codec) => {
                 Ok(Box::new(VideoDecoderImpl {
                     codec: codec,
                 }) as Box<videodecoder::VideoDecoder>)
             }
-            Err(_) => Err(()),
+            Err(err) => Err(vpx_error(err)),
         }
     }
+
+    fn new_vp8(_: &videodecoder::VideoHeaders, _: i32, _: i32)
+               -> Result<Box<videodecoder::VideoDecoder + 'static>,MediaError> {
+        VideoDecoderImpl::new(&VpxCodecIface::vp8())
+    }
+
+    fn new_vp9(_: &videodecoder::VideoHeaders, _: i32, _: i32)
+               -> Result<Box<videodecoder::VideoDecoder + 'static>,MediaError> {
+        VideoDecoderImpl::new(&VpxCodecIface::vp9())
+    }
 }
 
 impl videodecoder::VideoDecoder for VideoDecoderImpl {
     fn decode_frame(&self, data: &[u8], presentation_time: &Timestamp)
-                    -> Result<Box<videodecoder::DecodedVideoFrame + 'static>,()> {
-        if self.codec.decode(data, 0).is_err() {
-            return Err(())
+                    -> Result<Box<videodecoder::DecodedVideoFrame + 'static>,MediaError> {
+        if let Err(err) = self.codec.decode(data, 0) {
+            return Err(vpx_error(err))
         }
         let image = match self.codec.frame(&mut None) {
-            None => return Err(()),
+            None => return Err(MediaError::NeedMoreData),
             Some(image) => image,
         };
-        if image.format() != ffi::VPX_IMG_FMT_I420 {
-            return Err(())
-        }
+        let pixel_format = match image.format() {
+            ffi::VPX_IMG_FMT_I420 => PixelFormat::I420,
+            ffi::VPX_IMG_FMT_I422 => PixelFormat::I422,
+            ffi::VPX_IMG_FMT_I444 => PixelFormat::I444,
+            ffi::VPX_IMG_FMT_I42016 => PixelFormat::I42016(image.bit_depth() as u8),
+            ffi::VPX_IMG_FMT_I42216 => PixelFormat::I42216(image.bit_depth() as u8),
+            ffi::VPX_IMG_FMT_I44416 => PixelFormat::I44416(image.bit_depth() as u8),
+            _ => return Err(MediaError::UnsupportedCodec),
+        };
         Ok(Box::new(DecodedVideoFrameImpl {
             image: image,
+            pixel_format: pixel_format,
             presentation_time: *presentation_time,
         }) as Box<videodecoder::DecodedVideoFrame>)
     }
@@ -210,6 +254,7 @@ impl videodecoder::VideoDecoder for VideoDecoderImpl {
 
 struct DecodedVideoFrameImpl {
     image: VpxImage,
+    pixel_format: PixelFormat<'static>,
     presentation_time: Timestamp,
 }
 
@@ -227,7 +272,7 @@ impl videodecoder::DecodedVideoFrame for DecodedVideoFrameImpl {
     }
 
     fn pixel_format<'a>(&'a self) -> PixelFormat<'a> {
-        PixelFormat::I420
+        self.pixel_format
     }
 
     fn presentation_time(&self) -> Timestamp {
@@ -254,7 +299,13 @@ impl<'a> videodecoder::DecodedVideoFrameLockGuard for DecodedVideoFrameLockGuard
 pub const VIDEO_DECODER: videodecoder::RegisteredVideoDecoder =
     videodecoder::RegisteredVideoDecoder {
         id: [ b'V', b'P', b'8', b'0' ],
-        constructor: VideoDecoderImpl::new,
+        constructor: VideoDecoderImpl::new_vp8,
+    };
+
+pub const VP9_VIDEO_DECODER: videodecoder::RegisteredVideoDecoder =
+    videodecoder::RegisteredVideoDecoder {
+        id: [ b'V', b'P', b'9', b'0' ],
+        constructor: VideoDecoderImpl::new_vp9,
     };
 
 #[allow(non_camel_case_types)]
@@ -361,6 +412,7 @@ pub mod ffi {
 
     extern {
         pub fn vpx_codec_vp8_dx() -> *mut vpx_codec_iface_t;
+        pub fn vpx_codec_vp9_dx() -> *mut vpx_codec_iface_t;
         pub fn vpx_codec_dec_init_ver(ctx: *mut vpx_codec_ctx_t,
                                       iface: *mut vpx_codec_iface_t,
                                       cfg: *const vpx_codec_dec_cfg_t,
@@ -376,6 +428,7 @@ pub mod ffi {
         pub fn vpx_codec_get_frame(ctx: *mut vpx_codec_ctx_t, iter: *mut vpx_codec_iter_t)
                                    -> *mut vpx_image_t;
         pub fn vpx_img_free(img: *mut vpx_image_t);
+        pub fn vpx_codec_err_to_string(err: vpx_codec_err_t) -> *const c_char;
     }
 }
 
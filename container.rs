@@ -8,21 +8,50 @@
 // except according to those terms.
 
 use audiodecoder;
-use containers::gif;
-use containers::mkv;
-use containers::mp4;
+use error::MediaError;
 use pixelformat::PixelFormat;
+use registry::Registry;
 use streaming::StreamReader;
 use timing::Timestamp;
 use videodecoder;
 
 use libc::{c_double, c_int, c_long};
+use std::ascii::AsciiExt;
+use std::io::{Read, Seek, SeekFrom};
 use std::str;
 
 pub trait ContainerReader {
     fn track_count(&self) -> u16;
     fn track_by_index<'a>(&'a self, index: u16) -> Box<Track + 'a>;
     fn track_by_number<'a>(&'a self, number: c_long) -> Box<Track + 'a>;
+
+    /// Returns the total running time of the media, if the container records it.
+    fn duration(&self) -> Option<Timestamp>;
+
+    /// Returns the metadata tags (title, artist, and so on) stored in the file.
+    fn metadata(&self) -> Vec<MetadataTag>;
+
+    /// Returns the chapters of the media, in order. Containers that have no notion of chapters
+    /// return an empty list.
+    fn chapters(&self) -> Vec<Chapter> {
+        Vec::new()
+    }
+
+    /// Finds the keyframe at or before `time` in the first video track, or in the first track if
+    /// there is no video.
+    fn seek(&self, time: &Timestamp) -> Result<SeekPosition,MediaError> {
+        if self.track_count() == 0 {
+            return Err(MediaError::EndOfStream)
+        }
+        let mut track_index = 0;
+        for index in range(0, self.track_count()) {
+            if self.track_by_index(index).track_type() == TrackType::Video {
+                track_index = index;
+                break
+            }
+        }
+        self.track_by_index(track_index).seek(time)
+    }
 }
 
 pub trait Track {
@@ -34,9 +63,23 @@ pub trait Track {
 
     fn number(&self) -> c_long;
     fn codec(&self) -> Option<Vec<u8>>;
-    fn cluster<'a>(&'a self, cluster_index: i32) -> Result<Box<Cluster + 'a>,()>;
+
+    /// Returns the running time of this track, if the container records it.
+    fn duration(&self) -> Option<Timestamp>;
+
+    fn cluster<'a>(&'a self, cluster_index: i32) -> Result<Box<Cluster + 'a>,MediaError>;
+
+    /// Finds the nearest keyframe at or before `time`. Containers that know where the track ends
+    /// return `MediaError::EndOfStream` for times past it.
+    fn seek(&self, time: &Timestamp) -> Result<SeekPosition,MediaError>;
+
     fn as_video_track<'a>(&'a self) -> Result<Box<VideoTrack + 'a>,()>;
     fn as_audio_track<'a>(&'a self) -> Result<Box<AudioTrack + 'a>,()>;
+
+    /// Returns this track as a subtitle track, if it is one in a format that can be read.
+    fn as_subtitle_track<'a>(&'a self) -> Result<Box<SubtitleTrack + 'a>,()> {
+        Err(())
+    }
 }
 
 pub trait VideoTrack : Track {
@@ -64,26 +107,214 @@ pub trait AudioTrack : Track {
     fn headers(&self) -> Box<audiodecoder::AudioHeaders>;
 }
 
+pub trait SubtitleTrack : Track {
+    /// Returns the header that applies to every cue, if the format has one: the script info and
+    /// style sections for ASS/SSA, or the file header for WebVTT.
+    fn header(&self) -> Option<String>;
+
+    /// Returns the cues that start in the given cluster, in order. Returns
+    /// `MediaError::EndOfStream` if there is no such cluster.
+    fn cues(&self, cluster_index: i32) -> Result<Vec<SubtitleCue>,MediaError>;
+}
+
 pub trait Cluster {
-    /// Reads out a frame from this cluster.
+    /// Reads out a frame from this cluster. Returns `MediaError::EndOfStream` if there are no more
+    /// frames for the given track in this cluster.
     fn read_frame<'a>(&'a self, frame_index: i32, track_number: c_long)
-                      -> Result<Box<Frame + 'a>,()>;
+                      -> Result<Box<Frame + 'a>,MediaError>;
 }
 
 pub trait Frame {
     fn len(&self) -> c_long;
-    fn read(&self, buffer: &mut [u8]) -> Result<(),()>;
+    fn read(&self, buffer: &mut [u8]) -> Result<(),MediaError>;
+    /// Returns the contents of this frame without copying, if the container reader already has it
+    /// in memory. The Matroska and Ogg readers slice frames directly out of streams that are
+    /// entirely in memory (for example, a `MmapStreamReader`), and the MP4 reader returns the
+    /// sample that `mp4v2` has already read. Otherwise, use `read()`.
+    fn data<'a>(&'a self) -> Option<&'a [u8]> {
+        None
+    }
     fn track_number(&self) -> c_long;
     /// Returns the absolute time of this frame.
     fn time(&self) -> Timestamp;
     /// Returns the rendering offset of this frame, in the same time units as `time`.
     fn rendering_offset(&self) -> i64;
+    /// Returns true if this frame can be decoded without reference to any other frame.
+    fn is_keyframe(&self) -> bool;
+    /// Returns the duration of this frame in the same time units as `time`, if the container
+    /// records it.
+    fn duration(&self) -> Option<i64>;
+    /// Returns true if no other frame depends on this one, so that it can be dropped without
+    /// affecting the rest of the stream.
+    fn is_discardable(&self) -> bool;
+    /// Returns true if this frame begins a new section of the stream that has to be decoded from
+    /// scratch, such as the next link of a chained Ogg stream. Once this frame has been read, the
+    /// track's codec, headers and parameters describe the new section, so the decoder must be
+    /// recreated from them.
+    fn discontinuity(&self) -> bool {
+        false
+    }
+}
+
+/// A piece of subtitle text and the interval over which it should be shown.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubtitleCue {
+    pub start: Timestamp,
+    /// The time at which the cue should be hidden. If `None`, the cue stays up until the next one
+    /// starts.
+    pub end: Option<Timestamp>,
+    pub payload: SubtitlePayload,
+}
+
+impl SubtitleCue {
+    /// Returns the text of this cue with all styling removed, suitable for a plain caption
+    /// display or a screen reader.
+    pub fn text(&self) -> String {
+        match self.payload {
+            SubtitlePayload::Text(ref text) => text.clone(),
+            SubtitlePayload::Ass(ref event) => {
+                // The text is the last of the nine comma-separated fields of a Matroska ASS event
+                // (`ReadOrder,Layer,Style,Name,MarginL,MarginR,MarginV,Effect,Text`), and may
+                // itself contain commas.
+                let text = event.splitn(8, ',').last().unwrap_or("");
+                strip_markup(text.replace("\\N", "\n").replace("\\n", "\n").as_slice(),
+                             '{',
+                             '}')
+            }
+            SubtitlePayload::WebVtt(ref text) => strip_markup(text.as_slice(), '<', '>'),
+        }
+    }
+}
+
+/// The contents of a subtitle cue.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SubtitlePayload {
+    /// Plain UTF-8 text.
+    Text(String),
+    /// An ASS/SSA dialogue event, to be styled according to the track's header.
+    Ass(String),
+    /// The payload of a WebVTT cue, which may contain WebVTT markup such as `<i>`.
+    WebVtt(String),
+}
+
+/// Removes every run of characters between `open` and `close`, inclusive.
+fn strip_markup(text: &str, open: char, close: char) -> String {
+    let mut result = String::new();
+    let mut in_markup = false;
+    for ch in text.chars() {
+        if in_markup {
+            in_markup = ch != close
+        } else if ch == open {
+            in_markup = true
+        } else {
+            result.push(ch)
+        }
+    }
+    result
+}
+
+/// A keyframe that playback can start from, as returned by `Track::seek()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SeekPosition {
+    /// The index of the cluster containing the keyframe, suitable for passing to
+    /// `Track::cluster()`.
+    pub cluster_index: i32,
+    /// The index of the keyframe within its cluster, suitable for passing to
+    /// `Cluster::read_frame()`.
+    pub frame_index: i32,
+    /// The time of the keyframe.
+    pub time: Timestamp,
+}
+
+/// A metadata tag. Keys from the various container formats are normalized to lowercase names such
+/// as `title`, `artist`, `album`, `album_artist`, `date`, and `track_number`; keys with no common
+/// name are simply lowercased.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetadataTag {
+    pub key: String,
+    pub value: String,
+}
+
+impl MetadataTag {
+    pub fn new(key: &str, value: String) -> MetadataTag {
+        MetadataTag {
+            key: normalize_metadata_key(key),
+            value: value,
+        }
+    }
+
+    /// Parses a comment of the form `KEY=value`, as found in Vorbis and Opus comment headers.
+    /// Returns `None` if the comment has no `=`.
+    pub fn from_comment(comment: &[u8]) -> Option<MetadataTag> {
+        let separator = match comment.iter().position(|&byte| byte == b'=') {
+            Some(separator) => separator,
+            None => return None,
+        };
+        let key = String::from_utf8_lossy(&comment[0..separator]);
+        let value = String::from_utf8_lossy(&comment[separator + 1..]);
+        Some(MetadataTag::new(key.as_slice(), value.into_owned()))
+    }
+}
+
+/// A chapter, which may be divided into subchapters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chapter {
+    pub start: Timestamp,
+    /// The end of the chapter. If `None`, the chapter runs until the next one starts, or until
+    /// the end of the media.
+    pub end: Option<Timestamp>,
+    /// The title of the chapter, possibly in several languages.
+    pub titles: Vec<ChapterTitle>,
+    pub children: Vec<Chapter>,
+}
+
+/// The title of a chapter in one language.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChapterTitle {
+    pub title: String,
+    /// The ISO 639-2 code of the language of the title, if known.
+    pub language: Option<String>,
+    /// The ISO 3166-1 code of the country that the title is localized for, if any.
+    pub country: Option<String>,
+}
+
+/// Maps a tag name from Matroska, Vorbis comments, or similar to its normalized form.
+pub fn normalize_metadata_key(key: &str) -> String {
+    static TABLE: [(&'static str, &'static str); 19] = [
+        ("TITLE", "title"),
+        ("ARTIST", "artist"),
+        ("ALBUM", "album"),
+        ("ALBUMARTIST", "album_artist"),
+        ("ALBUM ARTIST", "album_artist"),
+        ("ALBUM_ARTIST", "album_artist"),
+        ("COMPOSER", "composer"),
+        ("GENRE", "genre"),
+        ("DATE", "date"),
+        ("DATE_RELEASED", "date"),
+        ("DATE_RECORDED", "date"),
+        ("YEAR", "date"),
+        ("TRACKNUMBER", "track_number"),
+        ("PART_NUMBER", "track_number"),
+        ("COMMENT", "comment"),
+        ("DESCRIPTION", "comment"),
+        ("ENCODER", "encoder"),
+        ("ENCODED_BY", "encoded_by"),
+        ("COPYRIGHT", "copyright"),
+    ];
+    let upper_key = key.to_ascii_uppercase();
+    for &(name, normalized_name) in TABLE.iter() {
+        if name == upper_key.as_slice() {
+            return normalized_name.to_string()
+        }
+    }
+    key.to_ascii_lowercase()
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum TrackType {
     Video,
     Audio,
+    Subtitle,
     Other,
 }
 
@@ -121,30 +352,88 @@ impl<'a,'b> TrackExt for &'a (Track + 'b) {
                     result.push_str(format!("  Cluster Count: {}\n", cluster_count).as_slice());
                 }
             }
+            TrackType::Subtitle => {
+                if let Ok(subtitle_track) = self.as_subtitle_track() {
+                    let has_header = subtitle_track.header().is_some();
+                    result.push_str(format!("  Header: {}\n", has_header).as_slice());
+                }
+            }
             _ => {}
         }
         result
     }
 }
 
+/// A byte pattern that identifies a container format when found `offset` bytes into the stream.
+#[derive(Copy, Debug)]
+pub struct Signature {
+    pub offset: usize,
+    pub pattern: &'static [u8],
+}
+
+impl Signature {
+    pub fn matches(&self, header: &[u8]) -> bool {
+        header.len() >= self.offset + self.pattern.len() &&
+            &header[self.offset..self.offset + self.pattern.len()] == self.pattern
+    }
+}
+
+/// The number of bytes at the start of the stream that `RegisteredContainerReader::sniff()`
+/// examines. All signatures must fit within this.
+pub const SNIFF_LENGTH: usize = 16;
+
 #[allow(missing_copy_implementations)]
 pub struct RegisteredContainerReader {
     pub mime_types: &'static [&'static str],
+    pub signatures: &'static [Signature],
     pub read: extern "Rust" fn(reader: Box<StreamReader>)
-                               -> Result<Box<ContainerReader + 'static>,()>,
+                               -> Result<Box<ContainerReader + 'static>,MediaError>,
 }
 
 impl RegisteredContainerReader {
-    pub fn get(mime_type: &str) -> Result<&'static RegisteredContainerReader,()> {
-        for container_reader in CONTAINER_READERS.iter() {
-            if container_reader.mime_types.iter().any(|mime| mime == &mime_type) {
+    /// Finds the highest-priority container reader in `registry` for the given MIME type.
+    /// Parameters such as `codecs` are ignored.
+    pub fn get<'a>(registry: &'a Registry, mime_type: &str)
+                   -> Result<&'a RegisteredContainerReader,MediaError> {
+        let essence = mime_type_essence(mime_type);
+        for container_reader in registry.container_readers().iter() {
+            if container_reader.mime_types.iter().any(|mime| *mime == essence.as_slice()) {
                 return Ok(container_reader)
             }
         }
-        Err(())
+        Err(MediaError::UnsupportedContainer)
+    }
+
+    /// Finds the highest-priority container reader in `registry` whose signature matches the
+    /// first few bytes of the stream. The stream is left positioned at the beginning.
+    pub fn sniff<'a>(registry: &'a Registry, reader: &mut StreamReader)
+                     -> Result<&'a RegisteredContainerReader,MediaError> {
+        if reader.seek(SeekFrom::Start(0)).is_err() {
+            return Err(MediaError::UnsupportedContainer)
+        }
+        let mut header = [0; SNIFF_LENGTH];
+        let mut header_length = 0;
+        while header_length < SNIFF_LENGTH {
+            match reader.read(&mut header[header_length..]) {
+                Ok(0) | Err(_) => break,
+                Ok(bytes_read) => header_length += bytes_read,
+            }
+        }
+        if reader.seek(SeekFrom::Start(0)).is_err() {
+            return Err(MediaError::UnsupportedContainer)
+        }
+
+        let header = &header[0..header_length];
+        for container_reader in registry.container_readers().iter() {
+            if container_reader.signatures.iter().any(|signature| signature.matches(header)) {
+                return Ok(container_reader)
+            }
+        }
+        Err(MediaError::UnsupportedContainer)
     }
 
-    pub fn new(&self, reader: Box<StreamReader>) -> Result<Box<ContainerReader + 'static>,()> {
+    pub fn new(&self, reader: Box<StreamReader>)
+               -> Result<Box<ContainerReader + 'static>,MediaError> {
         (self.read)(reader)
     }
 
@@ -153,9 +442,53 @@ impl RegisteredContainerReader {
     }
 }
 
-pub static CONTAINER_READERS: [RegisteredContainerReader; 3] = [
-    mkv::CONTAINER_READER,
-    mp4::CONTAINER_READER,
-    gif::CONTAINER_READER,
-];
+/// Returns the lowercased type and subtype of a MIME type, without any parameters, as described in
+/// the WHATWG MIME Sniffing standard. For example, `video/webm; codecs="vp8, vorbis"` becomes
+/// `video/webm`.
+pub fn mime_type_essence(mime_type: &str) -> String {
+    let essence = match mime_type.find(';') {
+        Some(index) => &mime_type[0..index],
+        None => mime_type,
+    };
+    essence.trim().to_ascii_lowercase()
+}
 
+#[cfg(test)]
+mod tests {
+    use super::{MetadataTag, SubtitleCue, SubtitlePayload};
+    use timing::{TimeBase, Timestamp};
+
+    fn cue(payload: SubtitlePayload) -> SubtitleCue {
+        SubtitleCue {
+            start: Timestamp::new(0, TimeBase::new(1, 1000)),
+            end: None,
+            payload: payload,
+        }
+    }
+
+    #[test]
+    fn comments_are_split_at_the_first_equals_sign() {
+        let tag = MetadataTag::from_comment(b"ALBUMARTIST=A=B").unwrap();
+        assert_eq!(tag.key, "album_artist");
+        assert_eq!(tag.value, "A=B");
+        assert_eq!(MetadataTag::from_comment(b"no separator"), None);
+    }
+
+    #[test]
+    fn plain_text_is_unchanged() {
+        let text = "<i>Hello</i>, {world}".to_string();
+        assert_eq!(cue(SubtitlePayload::Text(text.clone())).text(), text);
+    }
+
+    #[test]
+    fn ass_text_is_the_last_field_without_overrides() {
+        let event = "12,0,Default,,0,0,0,,{\\i1}Hello,{\\i0} there\\Nworld".to_string();
+        assert_eq!(cue(SubtitlePayload::Ass(event)).text(), "Hello, there\nworld");
+    }
+
+    #[test]
+    fn webvtt_text_has_tags_removed() {
+        let text = "<v Roger>Hello <b>there</b></v>".to_string();
+        assert_eq!(cue(SubtitlePayload::WebVtt(text)).text(), "Hello there");
+    }
+}
@@ -14,17 +14,19 @@
 #![allow(non_snake_case)]
 
 use container;
+use error::MediaError;
 use pixelformat::{Palette, PixelFormat, RgbColor};
-use streaming::StreamReader;
-use timing::Timestamp;
+use streaming::{self, StreamReader};
+use timing::{TimeBase, Timestamp};
 use videodecoder;
 
 use libc::{self, c_double, c_int, c_long, c_uchar, c_uint, c_void, size_t};
 use std::cell::RefCell;
 use std::i32;
+use std::io::{Seek, SeekFrom};
 use std::mem;
 use std::num::FromPrimitive;
-use std::old_io::{BufReader, BufWriter, SeekStyle};
+use std::old_io::{BufReader, BufWriter};
 use std::ptr;
 use std::slice;
 
@@ -57,7 +59,7 @@ impl FileType {
                 file: file,
                 next_record_byte_offset: 0,
             };
-            file.next_record_byte_offset = file.reader().tell().unwrap();
+            file.next_record_byte_offset = file.reader().seek(SeekFrom::Current(0)).unwrap();
             Ok(file)
         } else {
             Err(error)
@@ -88,9 +90,25 @@ impl FileType {
 
     /// This function is a port of the inner loop of `DGifSlurp()`. Returns true if there are more
     /// records or false if we're done.
+    ///
+    /// If reading the record fails, anything it added is discarded, so the record can be read
+    /// again from the start once more of the stream is available.
     pub fn read_record(&mut self) -> Result<bool,()> {
+        let (image_count, extension_block_count) = unsafe {
+            ((*self.file).ImageCount, (*self.file).ExtensionBlockCount)
+        };
+        let result = self.read_record_without_rollback();
+        if result.is_err() {
+            unsafe {
+                self.discard_partial_record(image_count, extension_block_count)
+            }
+        }
+        result
+    }
+
+    fn read_record_without_rollback(&mut self) -> Result<bool,()> {
         let next_record_byte_offset = self.next_record_byte_offset;
-        self.reader().seek(next_record_byte_offset as i64, SeekStyle::SeekSet).unwrap();
+        self.reader().seek(SeekFrom::Start(next_record_byte_offset)).unwrap();
 
         let mut record_type = 0;
         unsafe {
@@ -167,10 +185,48 @@ impl FileType {
             _ => return Ok(false),
         }
 
-        self.next_record_byte_offset = self.reader().tell().unwrap();
+        self.next_record_byte_offset = self.reader().seek(SeekFrom::Current(0)).unwrap();
         Ok(true)
     }
 
+    /// Frees the images and extension blocks beyond the given counts, which were added by a record
+    /// that we failed to read in full.
+    unsafe fn discard_partial_record(&mut self, image_count: c_int, extension_block_count: c_int) {
+        while (*self.file).ImageCount > image_count {
+            {
+                let saved_image = self.mut_saved_images().last_mut().unwrap();
+                if !saved_image.RasterBits.is_null() {
+                    libc::free(saved_image.RasterBits as *mut c_void);
+                    saved_image.RasterBits = ptr::null_mut();
+                }
+                if !saved_image.ImageDesc.ColorMap.is_null() {
+                    ffi::GifFreeMapObject(saved_image.ImageDesc.ColorMap);
+                    saved_image.ImageDesc.ColorMap = ptr::null_mut();
+                }
+
+                // The image took ownership of the extension blocks that preceded it. Give them
+                // back so that they're attached to the image when we read it again.
+                if !saved_image.ExtensionBlocks.is_null() {
+                    (*self.file).ExtensionBlocks = saved_image.ExtensionBlocks;
+                    (*self.file).ExtensionBlockCount = saved_image.ExtensionBlockCount;
+                    saved_image.ExtensionBlocks = ptr::null_mut();
+                    saved_image.ExtensionBlockCount = 0;
+                }
+            }
+            (*self.file).ImageCount -= 1;
+        }
+
+        // `GifAddExtensionBlock()` reallocates the array, so we only need to free the data of the
+        // blocks we added.
+        while (*self.file).ExtensionBlockCount > extension_block_count {
+            (*self.file).ExtensionBlockCount -= 1;
+            let block = (*self.file).ExtensionBlocks
+                                    .offset((*self.file).ExtensionBlockCount as isize);
+            libc::free((*block).Bytes as *mut c_void);
+            (*block).Bytes = ptr::null_mut();
+        }
+    }
+
     /// A port of a section of `DGifSlurp`.
     fn read_image(&mut self) -> Result<(),()> {
         unsafe {
@@ -221,6 +277,13 @@ impl FileType {
         Ok(())
     }
 
+    /// Returns the giflib error code of the last operation that failed.
+    pub fn error(&self) -> c_int {
+        unsafe {
+            (*self.file).Error
+        }
+    }
+
     pub fn width(&self) -> ffi::GifWord {
         unsafe {
             (*self.file).SWidth
@@ -277,7 +340,8 @@ extern "C" fn read_func(file: *mut ffi::GifFileType, buffer: *mut ffi::GifByteTy
 
     unsafe {
         let reader: &mut Box<Box<StreamReader>> = mem::transmute(&mut (*file).UserData);
-        match reader.read(slice::from_raw_mut_buf(&buffer, len as usize)) {
+        let buffer = slice::from_raw_mut_buf(&buffer, len as usize);
+        match streaming::read_fully(&mut ***reader, buffer) {
             Ok(number_read) => number_read as c_int,
             _ => -1
         }
@@ -448,10 +512,14 @@ pub struct ContainerReaderImpl {
 }
 
 impl ContainerReaderImpl {
-    pub fn new(reader: Box<StreamReader>) -> Result<Box<container::ContainerReader + 'static>,()> {
+    pub fn new(reader: Box<StreamReader>)
+               -> Result<Box<container::ContainerReader + 'static>,MediaError> {
+        // giflib closes the stream if opening fails, so check this up front.
+        let incomplete = streaming::is_waiting_for_data(&*reader);
         let file = match FileType::new(reader) {
             Ok(file) => file,
-            Err(_) => return Err(()),
+            Err(_) if incomplete => return Err(MediaError::NeedMoreData),
+            Err(error) => return Err(MediaError::backend(error as i64, "giflib")),
         };
         Ok(Box::new(ContainerReaderImpl {
             file: RefCell::new(file),
@@ -471,6 +539,12 @@ impl container::ContainerReader for ContainerReaderImpl {
     fn track_by_number<'a>(&'a self, _: c_long) -> Box<container::Track + 'a> {
         self.track_by_index(0)
     }
+    fn duration(&self) -> Option<Timestamp> {
+        get_duration(&self.file)
+    }
+    fn metadata(&self) -> Vec<container::MetadataTag> {
+        get_metadata(&self.file)
+    }
 }
 
 struct TrackImpl<'a> {
@@ -494,10 +568,19 @@ impl<'a> container::Track for TrackImpl<'a> {
         Some(vec![b'G', b'I', b'F', b'f'])
     }
 
-    fn cluster<'b>(&'b self, cluster_index: i32) -> Result<Box<container::Cluster + 'b>,()> {
+    fn duration(&self) -> Option<Timestamp> {
+        get_duration(self.file)
+    }
+
+    fn cluster<'b>(&'b self, cluster_index: i32)
+                   -> Result<Box<container::Cluster + 'b>,MediaError> {
         get_cluster(self.file, cluster_index)
     }
 
+    fn seek(&self, time: &Timestamp) -> Result<container::SeekPosition,MediaError> {
+        seek(self.file, time)
+    }
+
     fn as_video_track<'b>(&'b self) -> Result<Box<container::VideoTrack + 'b>,()> {
         Ok(Box::new(VideoTrackImpl {
             file: self.file,
@@ -530,10 +613,19 @@ impl<'a> container::Track for VideoTrackImpl<'a> {
         Some(vec![b'G', b'I', b'F', b'f'])
     }
 
-    fn cluster<'b>(&'b self, cluster_index: i32) -> Result<Box<container::Cluster + 'b>,()> {
+    fn duration(&self) -> Option<Timestamp> {
+        get_duration(self.file)
+    }
+
+    fn cluster<'b>(&'b self, cluster_index: i32)
+                   -> Result<Box<container::Cluster + 'b>,MediaError> {
         get_cluster(self.file, cluster_index)
     }
 
+    fn seek(&self, time: &Timestamp) -> Result<container::SeekPosition,MediaError> {
+        seek(self.file, time)
+    }
+
     fn as_video_track<'b>(&'b self) -> Result<Box<container::VideoTrack + 'b>,()> {
         Ok(Box::new(VideoTrackImpl {
             file: self.file,
@@ -581,11 +673,16 @@ impl<'a> container::VideoTrack for VideoTrackImpl<'a> {
 }
 
 fn get_cluster<'a>(file: &'a RefCell<FileType>, cluster_index: i32)
-                   -> Result<Box<container::Cluster + 'a>,()> {
+                   -> Result<Box<container::Cluster + 'a>,MediaError> {
     // Read and decode frames until we get to the given cluster index.
     while file.borrow().saved_images().len() < (cluster_index as usize + 1) {
-        match file.borrow_mut().read_record() {
-            Err(_) | Ok(false) => return Err(()),
+        let result = file.borrow_mut().read_record();
+        match result {
+            Err(_) if streaming::is_waiting_for_data(file.borrow_mut().reader()) => {
+                return Err(MediaError::NeedMoreData)
+            }
+            Err(_) => return Err(MediaError::backend(file.borrow().error() as i64, "giflib")),
+            Ok(false) => return Err(MediaError::EndOfStream),
             Ok(true) => {}
         }
     }
@@ -596,6 +693,26 @@ fn get_cluster<'a>(file: &'a RefCell<FileType>, cluster_index: i32)
     }) as Box<container::Cluster + 'a>)
 }
 
+/// Every GIF frame is a keyframe, so this just finds the image that is showing at `time`. If
+/// `time` is past the end, this returns the last image.
+fn seek(file: &RefCell<FileType>, time: &Timestamp)
+        -> Result<container::SeekPosition,MediaError> {
+    try!(get_cluster(file, 0));
+    let mut image_index = 0;
+    while get_time(file, image_index + 1) <= *time {
+        match get_cluster(file, image_index as i32 + 1) {
+            Ok(_) => image_index += 1,
+            Err(MediaError::EndOfStream) => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(container::SeekPosition {
+        cluster_index: image_index as i32,
+        frame_index: 0,
+        time: get_time(file, image_index),
+    })
+}
+
 struct ClusterImpl<'a> {
     file: &'a RefCell<FileType>,
     image_index: usize,
@@ -603,14 +720,14 @@ struct ClusterImpl<'a> {
 
 impl<'a> container::Cluster for ClusterImpl<'a> {
     fn read_frame<'b>(&'b self, frame_index: i32, _: c_long)
-                  -> Result<Box<container::Frame + 'b>,()> {
+                  -> Result<Box<container::Frame + 'b>,MediaError> {
         if frame_index == 0 {
             Ok(Box::new(FrameImpl {
                 file: self.file,
                 image_index: self.image_index,
             }) as Box<container::Frame + 'b>)
         } else {
-            Err(())
+            Err(MediaError::EndOfStream)
         }
     }
 }
@@ -631,26 +748,31 @@ impl<'a> container::Frame for FrameImpl<'a> {
         (2 + (color_map.colors().len() * 3) + saved_image.raster_bits().len()) as c_long
     }
 
-    fn read(&self, buffer: &mut [u8]) -> Result<(),()> {
+    fn read(&self, buffer: &mut [u8]) -> Result<(),MediaError> {
         let file = self.file.borrow();
         let saved_image = &file.saved_images()[self.image_index];
         let mut writer = BufWriter::new(buffer);
         let color_map = match saved_image.image_desc().color_map() {
             Some(map) => map,
-            None => file.color_map().unwrap(),
+            None => {
+                match file.color_map() {
+                    Some(map) => map,
+                    None => return Err(MediaError::Malformed),
+                }
+            }
         };
 
         if writer.write_le_u16(color_map.colors().len() as u16).is_err() {
-            return Err(())
+            return Err(MediaError::Malformed)
         }
         for color in color_map.colors().iter() {
             if writer.write_all(&[color.Red, color.Green, color.Blue]).is_err() {
-                return Err(())
+                return Err(MediaError::Malformed)
             }
         }
         match writer.write_all(saved_image.raster_bits()) {
             Ok(_) => Ok(()),
-            Err(_) => Err(()),
+            Err(_) => Err(MediaError::Malformed),
         }
     }
 
@@ -665,6 +787,26 @@ impl<'a> container::Frame for FrameImpl<'a> {
     fn rendering_offset(&self) -> i64 {
         0
     }
+
+    fn is_keyframe(&self) -> bool {
+        true
+    }
+
+    fn duration(&self) -> Option<i64> {
+        let file = self.file.borrow();
+        let saved_image = &file.saved_images()[self.image_index];
+        for i in 0..saved_image.extension_block_count() {
+            if let ExtensionBlock::Graphics(block) = saved_image.extension_block(i) {
+                return Some(block.delay_time() as i64)
+            }
+        }
+        None
+    }
+
+    fn is_discardable(&self) -> bool {
+        // Each image is drawn on top of the previous ones.
+        false
+    }
 }
 
 /// FIXME(pcwalton): This is O(n)!
@@ -687,15 +829,70 @@ fn get_time(file: &RefCell<FileType>, image_index: usize) -> Timestamp {
             }
         }
     }
-    Timestamp {
-        ticks: time_so_far,
-        ticks_per_second: 100.0,
+    // GIF delays are in hundredths of a second.
+    Timestamp::new(time_so_far, TimeBase::new(1, 100))
+}
+
+fn read_all_records(file: &RefCell<FileType>) -> Result<(),()> {
+    loop {
+        let result = file.borrow_mut().read_record();
+        match result {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(_) => return Err(()),
+        }
+    }
+}
+
+/// Returns the sum of the delays of all images. This has to read the whole file.
+fn get_duration(file: &RefCell<FileType>) -> Option<Timestamp> {
+    if read_all_records(file).is_err() {
+        return None
     }
+    let image_count = file.borrow().saved_images().len();
+    if image_count == 0 {
+        return None
+    }
+    Some(get_time(file, image_count))
+}
+
+/// Returns the text of all comment extensions as `comment` tags. This has to read the whole file.
+fn get_metadata(file: &RefCell<FileType>) -> Vec<container::MetadataTag> {
+    let mut tags = Vec::new();
+    if read_all_records(file).is_err() {
+        return tags
+    }
+    let file = file.borrow();
+    let mut add_comments = |extension_block: ExtensionBlock| {
+        if let ExtensionBlock::Comment(comment) = extension_block {
+            let comment = String::from_utf8_lossy(comment);
+            tags.push(container::MetadataTag::new("comment", comment.into_owned()))
+        }
+    };
+    for saved_image in file.saved_images().iter() {
+        for i in 0..saved_image.extension_block_count() {
+            add_comments(saved_image.extension_block(i))
+        }
+    }
+    for i in 0..file.extension_block_count() {
+        add_comments(file.extension_block(i))
+    }
+    tags
 }
 
 pub const CONTAINER_READER: container::RegisteredContainerReader =
     container::RegisteredContainerReader {
         mime_types: &["image/gif"],
+        signatures: &[
+            container::Signature {
+                offset: 0,
+                pattern: b"GIF87a",
+            },
+            container::Signature {
+                offset: 0,
+                pattern: b"GIF89a",
+            },
+        ],
         read: ContainerReaderImpl::new,
     };
 
@@ -709,7 +906,7 @@ struct VideoDecoderImpl {
 
 impl VideoDecoderImpl {
     fn new(_: &videodecoder::VideoHeaders, width: i32, height: i32)
-           -> Result<Box<videodecoder::VideoDecoder + 'static>,()> {
+           -> Result<Box<videodecoder::VideoDecoder + 'static>,MediaError> {
         Ok(Box::new(VideoDecoderImpl {
             width: width,
             height: height,
@@ -719,11 +916,11 @@ impl VideoDecoderImpl {
 
 impl videodecoder::VideoDecoder for VideoDecoderImpl {
     fn decode_frame(&self, data: &[u8], presentation_time: &Timestamp)
-                    -> Result<Box<videodecoder::DecodedVideoFrame + 'static>,()> {
+                    -> Result<Box<videodecoder::DecodedVideoFrame + 'static>,MediaError> {
         let mut reader = BufReader::new(data);
         let palette_size = match reader.read_le_u16() {
             Ok(size) => size,
-            Err(_) => return Err(()),
+            Err(_) => return Err(MediaError::Malformed),
         };
         let mut palette = Vec::new();
         let mut color_bytes = [0, 0, 0];
@@ -736,12 +933,12 @@ impl videodecoder::VideoDecoder for VideoDecoderImpl {
                         b: color_bytes[2],
                     })
                 }
-                _ => return Err(()),
+                _ => return Err(MediaError::Malformed),
             }
         }
         let pixels = match reader.read_to_end() {
             Ok(pixels) => pixels,
-            Err(_) => return Err(()),
+            Err(_) => return Err(MediaError::Malformed),
         };
         Ok(Box::new(DecodedVideoFrameImpl {
             width: self.width,
@@ -931,6 +1128,7 @@ pub mod ffi {
                                     Len: c_uint,
                                     ExtData: *mut c_uchar)
                                     -> c_int;
+        pub fn GifFreeMapObject(Object: *mut ColorMapObject);
         pub fn DGifExtensionToGCB(GifExtensionLength: size_t,
                                   GifExtension: *const GifByteType,
                                   GCB: *mut GraphicsControlBlock)
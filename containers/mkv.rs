@@ -8,23 +8,50 @@
 // except according to those terms.
 
 use audiodecoder;
+use codecs::aac::{self, AacHeaders};
+use codecs::h264::AvccHeaders;
+use codecs::opus::{self, OpusHeaders};
 use codecs::vorbis::VorbisHeaders;
 use container;
+use error::MediaError;
 use pixelformat::PixelFormat;
-use streaming::StreamReader;
-use timing::Timestamp;
+use streaming::{self, StreamReader};
+use timing::{self, TimeBase, Timestamp};
 use videodecoder;
 
 use libc::{c_char, c_double, c_int, c_long, c_longlong, c_uchar, c_ulong, c_void, size_t};
+use std::cell::{Cell, RefCell};
+use std::cmp;
 use std::ffi;
+use std::io::{Seek, SeekFrom};
+use std::iter;
 use std::mem;
 use std::num::FromPrimitive;
-use std::old_io::SeekStyle;
 use std::ptr;
+use std::rc::Rc;
 use std::slice;
 
+/// The error code that libwebm returns when the file isn't a valid Matroska file.
+pub const E_FILE_FORMAT_INVALID: c_long = -2;
+/// The error code that libwebm returns when it needs more data than is currently available.
+pub const E_BUFFER_NOT_FULL: c_long = -3;
+
+/// The number of clusters whose frame indices are kept around. Playback usually reads from one or
+/// two clusters at a time (more if the tracks are badly interleaved).
+const FRAME_INDEX_CACHE_SIZE: usize = 4;
+
+/// The kind of a block entry that holds a SimpleBlock.
+pub const BLOCK_KIND_SIMPLE: c_long = 1;
+/// The kind of a block entry that holds a BlockGroup.
+pub const BLOCK_KIND_GROUP: c_long = 2;
+
+/// The SimpleBlock flag indicating that the block can be discarded during playback if needed.
+pub const SIMPLE_BLOCK_FLAG_DISCARDABLE: u8 = 0x01;
+
 pub struct MkvReader {
     reader: WebmIMkvReaderRef,
+    /// The stream, which is owned by `reader`.
+    stream: *const Box<StreamReader>,
 }
 
 impl Drop for MkvReader {
@@ -37,12 +64,21 @@ impl Drop for MkvReader {
 
 impl MkvReader {
     pub fn new(reader: Box<StreamReader>) -> MkvReader {
+        let stream = unsafe {
+            mem::transmute::<Box<Box<_>>,*mut Box<StreamReader>>(Box::new(reader))
+        };
         MkvReader {
             reader: unsafe {
-                WebmCustomMkvReaderCreate(&READER_CALLBACKS,
-                                          mem::transmute::<Box<Box<_>>,
-                                                           *mut c_void>(Box::new(reader)))
+                WebmCustomMkvReaderCreate(&READER_CALLBACKS, stream as *mut c_void)
             },
+            stream: stream,
+        }
+    }
+
+    /// Returns the contents of the stream, if it's all in memory.
+    pub fn bytes<'a>(&'a self) -> Option<&'a [u8]> {
+        unsafe {
+            (*self.stream).as_bytes()
         }
     }
 }
@@ -58,12 +94,24 @@ extern "C" fn read_callback(pos: c_longlong,
 
     unsafe {
         let reader: &mut Box<Box<StreamReader>> = mem::transmute(&mut user_data);
-        if reader.seek(pos, SeekStyle::SeekSet).is_err() {
-            return -1
+
+        // libwebm checks the available size before most reads, but not all of them.
+        let error = if streaming::is_waiting_for_data(&***reader) {
+            E_BUFFER_NOT_FULL as c_int
+        } else {
+            -1
+        };
+        if (pos + len as c_longlong) as u64 > reader.available_size() {
+            return error
         }
-        match reader.read_at_least(len as usize, slice::from_raw_mut_buf(&buf, len as usize)) {
+
+        if reader.seek(SeekFrom::Start(pos as u64)).is_err() {
+            return error
+        }
+        match streaming::read_fully(&mut ***reader,
+                                    slice::from_raw_mut_buf(&buf, len as usize)) {
             Ok(number_read) if number_read == len as usize => 0,
-            _ => -1,
+            _ => error,
         }
     }
 }
@@ -129,6 +177,10 @@ impl EbmlHeader {
 
 pub struct Segment {
     segment: WebmSegmentRef,
+    /// The cluster most recently returned by `cluster()`, from which the next lookup starts.
+    cluster_cursor: Cell<WebmClusterRef>,
+    /// Recently used frame indices, keyed by cluster index, with the most recent last.
+    frame_indices: RefCell<Vec<(c_long, Rc<FrameIndex>)>>,
 }
 
 impl Drop for Segment {
@@ -148,6 +200,8 @@ impl Segment {
         if err == 0 {
             Ok(Segment {
                 segment: segment,
+                cluster_cursor: Cell::new(ptr::null_mut()),
+                frame_indices: RefCell::new(Vec::new()),
             })
         } else {
             Err(err)
@@ -165,6 +219,35 @@ impl Segment {
         }
     }
 
+    /// Parses the elements that precede the first cluster (info, tracks, and so on). A positive
+    /// error is the position up to which data is needed.
+    pub fn parse_headers(&self) -> Result<(),c_longlong> {
+        match unsafe {
+            WebmSegmentParseHeaders(self.segment)
+        } {
+            0 => Ok(()),
+            err => Err(err),
+        }
+    }
+
+    /// Loads the next cluster. Returns false if there are no more clusters.
+    pub fn load_cluster(&self) -> Result<bool,c_long> {
+        match unsafe {
+            WebmSegmentLoadCluster(self.segment)
+        } {
+            0 => Ok(true),
+            1 => Ok(false),
+            err => Err(err),
+        }
+    }
+
+    /// Returns true if every cluster has been loaded.
+    pub fn done_parsing(&self) -> bool {
+        unsafe {
+            WebmSegmentDoneParsing(self.segment)
+        }
+    }
+
     pub fn tracks<'a>(&'a self) -> Option<Tracks<'a>> {
         let tracks = unsafe {
             WebmSegmentGetTracks(self.segment)
@@ -183,6 +266,44 @@ impl Segment {
         }
     }
 
+    pub fn cues<'a>(&'a self) -> Option<Cues<'a>> {
+        let cues = unsafe {
+            WebmSegmentGetCues(self.segment)
+        };
+        if cues == ptr::null_mut() {
+            return None
+        }
+        Some(Cues {
+            cues: cues,
+        })
+    }
+
+    pub fn tags<'a>(&'a self) -> Option<Tags<'a>> {
+        let tags = unsafe {
+            WebmSegmentGetTags(self.segment)
+        };
+        if tags == ptr::null_mut() {
+            return None
+        }
+        Some(Tags {
+            tags: tags,
+        })
+    }
+
+    /// Parses the chapters of this segment, including nested chapters and the edition and chapter
+    /// flags, which libwebm itself skips.
+    pub fn chapters(&self) -> Option<Chapters> {
+        let chapters = unsafe {
+            WebmSegmentParseChapters(self.segment)
+        };
+        if chapters == ptr::null_mut() {
+            return None
+        }
+        Some(Chapters {
+            chapters: chapters,
+        })
+    }
+
     pub fn info<'a>(&'a self) -> SegmentInfo<'a> {
         SegmentInfo {
             segment_info: unsafe {
@@ -216,6 +337,119 @@ impl Segment {
             })
         }
     }
+
+    /// Returns the loaded cluster with the given index. libwebm can only walk the clusters in
+    /// order, so this starts from the cluster it last returned, or from the first cluster if the
+    /// one wanted comes before that. Reading the clusters in order thus takes constant time each.
+    pub fn cluster<'a>(&'a self, index: c_long) -> Option<Cluster<'a>> {
+        let cursor = Cluster {
+            cluster: self.cluster_cursor.get(),
+        };
+        let mut cluster = if !cursor.cluster.is_null() && cursor.index() <= index {
+            cursor
+        } else {
+            match self.first() {
+                Some(cluster) => cluster,
+                None => return None,
+            }
+        };
+        loop {
+            if cluster.eos() {
+                return None
+            }
+            if cluster.index() >= index {
+                break
+            }
+            cluster = match self.next(cluster) {
+                Some(cluster) => cluster,
+                None => return None,
+            };
+        }
+        if cluster.index() != index {
+            return None
+        }
+        self.cluster_cursor.set(cluster.cluster);
+        Some(cluster)
+    }
+
+    /// Returns the frame index of a fully parsed cluster, building it if it isn't cached.
+    fn frame_index(&self, cluster: &Cluster) -> Result<Rc<FrameIndex>,MediaError> {
+        let cluster_index = cluster.index();
+        let mut frame_indices = self.frame_indices.borrow_mut();
+        let cached_position = frame_indices.iter().position(|&(index, _)| index == cluster_index);
+        if let Some(cached_position) = cached_position {
+            let entry = frame_indices.remove(cached_position);
+            let frame_index = entry.1.clone();
+            frame_indices.push(entry);
+            return Ok(frame_index)
+        }
+
+        let frame_index = Rc::new(try!(FrameIndex::new(cluster)));
+        if frame_indices.len() >= FRAME_INDEX_CACHE_SIZE {
+            frame_indices.remove(0);
+        }
+        frame_indices.push((cluster_index, frame_index.clone()));
+        Ok(frame_index)
+    }
+}
+
+/// The frames of each track in a cluster, so that a frame can be found without scanning the
+/// cluster's blocks.
+struct FrameIndex {
+    /// For each track number, the block index and lace index of each of the track's frames, in
+    /// order.
+    tracks: Vec<(c_longlong, Vec<(c_long, c_int)>)>,
+}
+
+impl FrameIndex {
+    fn new(cluster: &Cluster) -> Result<FrameIndex,MediaError> {
+        let mut tracks: Vec<(c_longlong, Vec<(c_long, c_int)>)> = Vec::new();
+        for block_index in range(0, cluster.entry_count()) {
+            let block = match cluster.entry(block_index) {
+                Ok(block_entry) => block_entry.block(),
+                Err(err) => return Err(webm_error(err as c_longlong)),
+            };
+            let track_number = block.track_number();
+            let existing_track_index = tracks.iter().position(|&(number, _)| {
+                number == track_number
+            });
+            let track_index = match existing_track_index {
+                Some(track_index) => track_index,
+                None => {
+                    tracks.push((track_number, Vec::new()));
+                    tracks.len() - 1
+                }
+            };
+            for lace_index in range(0, block.frame_count()) {
+                tracks[track_index].1.push((block_index, lace_index))
+            }
+        }
+        Ok(FrameIndex {
+            tracks: tracks,
+        })
+    }
+
+    /// Returns the block index and lace index of the given frame of a track.
+    fn frame(&self, track_number: c_longlong, frame_index: i32) -> Option<(c_long, c_int)> {
+        if frame_index < 0 {
+            return None
+        }
+        match self.tracks.iter().find(|&&(number, _)| number == track_number) {
+            Some(&(_, ref frames)) => frames.get(frame_index as usize).map(|&frame| frame),
+            None => None,
+        }
+    }
+
+    /// Returns the index of the first block of the given track after `block_index`, if there is
+    /// one in the cluster.
+    fn next_block(&self, track_number: c_longlong, block_index: c_long) -> Option<c_long> {
+        match self.tracks.iter().find(|&&(number, _)| number == track_number) {
+            Some(&(_, ref frames)) => {
+                frames.iter().map(|&(index, _)| index).find(|&index| index > block_index)
+            }
+            None => None,
+        }
+    }
 }
 
 pub struct SegmentInfo<'a> {
@@ -228,6 +462,294 @@ impl<'a> SegmentInfo<'a> {
             WebmSegmentInfoGetTimeCodeScale(self.segment_info)
         }
     }
+
+    /// Returns the duration of the segment in nanoseconds, or a negative value if the file
+    /// doesn't specify it.
+    fn duration(&self) -> c_longlong {
+        unsafe {
+            WebmSegmentInfoGetDuration(self.segment_info)
+        }
+    }
+}
+
+pub struct Cues<'a> {
+    cues: WebmCuesRef,
+}
+
+impl<'a> Cues<'a> {
+    /// Loads all cue points. libwebm parses them lazily, so this must be called before `find()`.
+    pub fn load(&self) {
+        unsafe {
+            while !WebmCuesDoneParsing(self.cues) {
+                if !WebmCuesLoadCuePoint(self.cues) {
+                    break
+                }
+            }
+        }
+    }
+
+    /// Finds the last cue point for the given track at or before `time_ns`.
+    pub fn find(&self, time_ns: c_longlong, track: &Track) -> Option<CuePosition<'a>> {
+        let (mut cue_point, mut track_position) = (ptr::null_mut(), ptr::null_mut());
+        let found = unsafe {
+            WebmCuesFind(self.cues, time_ns, track.track, &mut cue_point, &mut track_position)
+        };
+        if !found || cue_point == ptr::null_mut() || track_position == ptr::null_mut() {
+            return None
+        }
+        Some(CuePosition {
+            cue_point: cue_point,
+            track_position: track_position,
+        })
+    }
+
+    pub fn block(&self, position: &CuePosition<'a>) -> Option<BlockEntry<'a>> {
+        let entry = unsafe {
+            WebmCuesGetBlock(self.cues, position.cue_point, position.track_position)
+        };
+        if entry == ptr::null_mut() {
+            return None
+        }
+        Some(BlockEntry {
+            entry: entry,
+        })
+    }
+}
+
+pub struct CuePosition<'a> {
+    cue_point: WebmCuePointRef,
+    track_position: WebmCueTrackPositionRef,
+}
+
+pub struct Tags<'a> {
+    tags: WebmTagsRef,
+}
+
+impl<'a> Tags<'a> {
+    pub fn count(&self) -> c_int {
+        unsafe {
+            WebmTagsGetTagCount(self.tags)
+        }
+    }
+
+    pub fn tag(&self, index: c_int) -> Tag<'a> {
+        assert!(index >= 0 && index < self.count());
+        Tag {
+            tag: unsafe {
+                WebmTagsGetTag(self.tags, index)
+            },
+        }
+    }
+}
+
+pub struct Tag<'a> {
+    tag: WebmTagRef,
+}
+
+impl<'a> Tag<'a> {
+    pub fn simple_tag_count(&self) -> c_int {
+        unsafe {
+            WebmTagGetSimpleTagCount(self.tag)
+        }
+    }
+
+    pub fn simple_tag(&self, index: c_int) -> SimpleTag<'a> {
+        assert!(index >= 0 && index < self.simple_tag_count());
+        SimpleTag {
+            simple_tag: unsafe {
+                WebmTagGetSimpleTag(self.tag, index)
+            },
+        }
+    }
+}
+
+pub struct SimpleTag<'a> {
+    simple_tag: WebmSimpleTagRef,
+}
+
+impl<'a> SimpleTag<'a> {
+    pub fn name<'b>(&'b self) -> &'b [u8] {
+        unsafe {
+            c_str_to_bytes_or_empty(WebmSimpleTagGetTagName(self.simple_tag))
+        }
+    }
+
+    /// Returns the string value of this tag, or the empty string if it has a binary value instead.
+    pub fn string<'b>(&'b self) -> &'b [u8] {
+        unsafe {
+            c_str_to_bytes_or_empty(WebmSimpleTagGetTagString(self.simple_tag))
+        }
+    }
+}
+
+pub struct Chapters {
+    chapters: WebmChaptersRef,
+}
+
+impl Drop for Chapters {
+    fn drop(&mut self) {
+        unsafe {
+            WebmChaptersDestroy(self.chapters)
+        }
+    }
+}
+
+impl Chapters {
+    pub fn edition_count(&self) -> c_int {
+        unsafe {
+            WebmChaptersGetEditionCount(self.chapters)
+        }
+    }
+
+    pub fn edition<'a>(&'a self, index: c_int) -> Edition<'a> {
+        assert!(index >= 0 && index < self.edition_count());
+        Edition {
+            edition: unsafe {
+                WebmChaptersGetEdition(self.chapters, index)
+            },
+        }
+    }
+}
+
+pub struct Edition<'a> {
+    edition: WebmEditionRef,
+}
+
+impl<'a> Edition<'a> {
+    /// Returns true if this is the edition to use when the user hasn't picked one.
+    pub fn is_default(&self) -> bool {
+        unsafe {
+            WebmEditionIsDefault(self.edition)
+        }
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        unsafe {
+            WebmEditionIsHidden(self.edition)
+        }
+    }
+
+    pub fn atom_count(&self) -> c_int {
+        unsafe {
+            WebmEditionGetAtomCount(self.edition)
+        }
+    }
+
+    pub fn atom(&self, index: c_int) -> ChapterAtom<'a> {
+        assert!(index >= 0 && index < self.atom_count());
+        ChapterAtom {
+            atom: unsafe {
+                WebmEditionGetAtom(self.edition, index)
+            },
+        }
+    }
+}
+
+pub struct ChapterAtom<'a> {
+    atom: WebmChapterAtomRef,
+}
+
+impl<'a> ChapterAtom<'a> {
+    /// Returns the start time of this chapter in nanoseconds.
+    pub fn start_time_code(&self) -> c_longlong {
+        unsafe {
+            WebmChapterAtomGetStartTimecode(self.atom)
+        }
+    }
+
+    /// Returns the end time of this chapter in nanoseconds, if it has one.
+    pub fn stop_time_code(&self) -> Option<c_longlong> {
+        match unsafe {
+            WebmChapterAtomGetStopTimecode(self.atom)
+        } {
+            time_code if time_code < 0 => None,
+            time_code => Some(time_code),
+        }
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        unsafe {
+            WebmChapterAtomIsHidden(self.atom)
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        unsafe {
+            WebmChapterAtomIsEnabled(self.atom)
+        }
+    }
+
+    pub fn display_count(&self) -> c_int {
+        unsafe {
+            WebmChapterAtomGetDisplayCount(self.atom)
+        }
+    }
+
+    pub fn display(&self, index: c_int) -> ChapterDisplay<'a> {
+        assert!(index >= 0 && index < self.display_count());
+        ChapterDisplay {
+            display: unsafe {
+                WebmChapterAtomGetDisplay(self.atom, index)
+            },
+        }
+    }
+
+    pub fn child_count(&self) -> c_int {
+        unsafe {
+            WebmChapterAtomGetChildCount(self.atom)
+        }
+    }
+
+    /// Returns the given subchapter of this chapter.
+    pub fn child(&self, index: c_int) -> ChapterAtom<'a> {
+        assert!(index >= 0 && index < self.child_count());
+        ChapterAtom {
+            atom: unsafe {
+                WebmChapterAtomGetChild(self.atom, index)
+            },
+        }
+    }
+}
+
+pub struct ChapterDisplay<'a> {
+    display: WebmChapterDisplayRef,
+}
+
+impl<'a> ChapterDisplay<'a> {
+    pub fn string<'b>(&'b self) -> &'b [u8] {
+        unsafe {
+            c_str_to_bytes_or_empty(WebmChapterDisplayGetString(self.display))
+        }
+    }
+
+    /// Returns the ISO 639-2 language code of this title, or the empty string if there is none.
+    pub fn language<'b>(&'b self) -> &'b [u8] {
+        unsafe {
+            c_str_to_bytes_or_empty(WebmChapterDisplayGetLanguage(self.display))
+        }
+    }
+
+    /// Returns the ISO 3166-1 country code of this title, or the empty string if there is none.
+    pub fn country<'b>(&'b self) -> &'b [u8] {
+        unsafe {
+            c_str_to_bytes_or_empty(WebmChapterDisplayGetCountry(self.display))
+        }
+    }
+}
+
+fn non_empty_string(bytes: &[u8]) -> Option<String> {
+    if bytes.is_empty() {
+        None
+    } else {
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+unsafe fn c_str_to_bytes_or_empty<'a>(ptr: *const c_char) -> &'a [u8] {
+    if ptr.is_null() {
+        return &[]
+    }
+    mem::transmute::<&[u8],&'a [u8]>(ffi::c_str_to_bytes(&ptr))
 }
 
 pub struct Tracks<'a> {
@@ -292,6 +814,38 @@ impl<'a> Track<'a> {
         }
     }
 
+    /// Returns the duration of each frame in nanoseconds, or zero if the track has no default
+    /// duration.
+    pub fn default_duration(&self) -> u64 {
+        unsafe {
+            WebmTrackGetDefaultDuration(self.track)
+        }
+    }
+
+    /// Returns the number of nanoseconds of decoder priming at the start of the track (for Opus,
+    /// the pre-skip), which the block timestamps include.
+    pub fn codec_delay(&self) -> u64 {
+        unsafe {
+            WebmTrackGetCodecDelay(self.track)
+        }
+    }
+
+    /// Finds the block at or before `time_ns` by searching the clusters directly. For video
+    /// tracks, this returns a keyframe.
+    pub fn seek(&self, time_ns: c_longlong) -> Result<BlockEntry<'a>,c_long> {
+        let mut err = 0;
+        let entry = unsafe {
+            WebmTrackSeek(self.track, time_ns, &mut err)
+        };
+        if err >= 0 && entry != ptr::null_mut() {
+            Ok(BlockEntry {
+                entry: entry,
+            })
+        } else {
+            Err(err)
+        }
+    }
+
     pub fn as_video_track(&self) -> VideoTrack<'a> {
         if self.track_type() != TrackType::Video {
             panic!("Track::as_video_track(): not a video track!")
@@ -428,6 +982,13 @@ impl<'a> Cluster<'a> {
         }
     }
 
+    /// Returns the index of this cluster in the segment.
+    pub fn index(&self) -> c_long {
+        unsafe {
+            WebmClusterGetIndex(self.cluster)
+        }
+    }
+
     pub fn entry_count(&self) -> c_long {
         unsafe {
             WebmClusterGetEntryCount(self.cluster)
@@ -494,6 +1055,42 @@ impl<'a> BlockEntry<'a> {
             WebmBlockEntryEos(self.entry)
         }
     }
+
+    pub fn cluster(&self) -> Cluster<'a> {
+        Cluster {
+            cluster: unsafe {
+                WebmBlockEntryGetCluster(self.entry)
+            },
+        }
+    }
+
+    /// Returns the index of this entry in its cluster.
+    pub fn index(&self) -> c_long {
+        unsafe {
+            WebmBlockEntryGetIndex(self.entry)
+        }
+    }
+
+    /// Returns `BLOCK_KIND_SIMPLE` or `BLOCK_KIND_GROUP`.
+    pub fn kind(&self) -> c_long {
+        unsafe {
+            WebmBlockEntryGetKind(self.entry)
+        }
+    }
+
+    /// Returns the BlockDuration of a BlockGroup, in timecode units, or `None` if this isn't a
+    /// BlockGroup or it has no duration.
+    pub fn duration_time_code(&self) -> Option<c_longlong> {
+        if self.kind() != BLOCK_KIND_GROUP {
+            return None
+        }
+        match unsafe {
+            WebmBlockGroupGetDurationTimeCode(self.entry)
+        } {
+            duration if duration > 0 => Some(duration),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -545,6 +1142,19 @@ impl<'a> Block<'a> {
             WebmBlockIsKey(self.block)
         }
     }
+
+    /// Reads the flags byte from the block header.
+    pub fn flags(&self, reader: &MkvReader) -> Result<u8,c_long> {
+        let mut flags = 0;
+        let result = unsafe {
+            WebmBlockGetFlags(self.block, reader.reader, &mut flags)
+        };
+        if result >= 0 {
+            Ok(flags)
+        } else {
+            Err(result)
+        }
+    }
 }
 
 pub struct BlockFrame<'a> {
@@ -593,19 +1203,30 @@ struct ContainerReaderImpl {
 }
 
 impl ContainerReaderImpl {
-    fn new(reader: Box<StreamReader>) -> Result<Box<container::ContainerReader + 'static>,()> {
+    fn new(reader: Box<StreamReader>)
+           -> Result<Box<container::ContainerReader + 'static>,MediaError> {
         let reader = MkvReader::new(reader);
         let (err, pos) = EbmlHeader::new().parse(&reader);
-        if err.is_err() {
-            return Err(())
+        if let Err(err) = err {
+            return Err(webm_error(err))
         }
         let segment = match Segment::new(&reader, pos) {
             Ok(segment) => segment,
-            Err(_) => return Err(()),
+            Err(err) => return Err(webm_error(err)),
         };
-        if segment.load().is_err() {
-            return Err(())
+        if let Err(err) = segment.parse_headers() {
+            return Err(webm_error(err))
+        }
+        if TimeBase::from_ratio(segment.info().time_code_scale() as u64, 1_000_000_000).is_none() {
+            return Err(MediaError::Malformed)
+        }
+
+        // Load the clusters that are available now. The rest are loaded as playback reaches them.
+        match load_clusters(&segment, !0) {
+            Ok(()) | Err(MediaError::NeedMoreData) => {}
+            Err(err) => return Err(err),
         }
+
         Ok(Box::new(ContainerReaderImpl {
             reader: reader,
             segment: segment,
@@ -633,8 +1254,92 @@ impl container::ContainerReader for ContainerReaderImpl {
             reader: &self.reader,
         }) as Box<container::Track + 'a>
     }
+
+    fn duration(&self) -> Option<Timestamp> {
+        get_duration(&self.segment)
+    }
+
+    fn metadata(&self) -> Vec<container::MetadataTag> {
+        let mut tags = Vec::new();
+        if let Some(segment_tags) = self.segment.tags() {
+            for tag_index in range(0, segment_tags.count()) {
+                let tag = segment_tags.tag(tag_index);
+                for simple_tag_index in range(0, tag.simple_tag_count()) {
+                    let simple_tag = tag.simple_tag(simple_tag_index);
+                    let name = String::from_utf8_lossy(simple_tag.name());
+                    let value = String::from_utf8_lossy(simple_tag.string());
+                    tags.push(container::MetadataTag::new(name.as_slice(), value.into_owned()))
+                }
+            }
+        }
+
+        // Vorbis tracks carry their own comment header. Use any tags from it that the container
+        // doesn't already have.
+        for track_index in range(0, self.track_count()) {
+            let track = self.track_by_index(track_index);
+            if track.codec() != Some(vec![b'v', b'o', b'r', b'b']) {
+                continue
+            }
+            let headers = track.as_audio_track().unwrap().headers();
+            let vorbis_tags = match headers.vorbis_headers().map(|headers| headers.tags()) {
+                Some(Ok(vorbis_tags)) => vorbis_tags,
+                _ => continue,
+            };
+            for vorbis_tag in vorbis_tags.into_iter() {
+                if !tags.iter().any(|tag| tag.key == vorbis_tag.key) {
+                    tags.push(vorbis_tag)
+                }
+            }
+        }
+        tags
+    }
+
+    fn chapters(&self) -> Vec<container::Chapter> {
+        let chapters = match self.segment.chapters() {
+            Some(chapters) => chapters,
+            None => return Vec::new(),
+        };
+
+        // Use the default edition, or the first one if none is marked as the default.
+        let editions: Vec<_> =
+            range(0, chapters.edition_count()).map(|index| chapters.edition(index)).collect();
+        let edition = match editions.iter().find(|edition| edition.is_default()) {
+            Some(edition) => edition,
+            None => match editions.first() {
+                Some(edition) => edition,
+                None => return Vec::new(),
+            },
+        };
+        range(0, edition.atom_count()).filter_map(|index| {
+            convert_chapter(&edition.atom(index))
+        }).collect()
+    }
+}
+
+/// Converts a chapter atom and its subchapters, skipping any that are hidden or disabled.
+fn convert_chapter(atom: &ChapterAtom) -> Option<container::Chapter> {
+    if atom.is_hidden() || !atom.is_enabled() {
+        return None
+    }
+    let titles = range(0, atom.display_count()).map(|index| {
+        let display = atom.display(index);
+        container::ChapterTitle {
+            title: String::from_utf8_lossy(display.string()).into_owned(),
+            language: non_empty_string(display.language()),
+            country: non_empty_string(display.country()),
+        }
+    }).collect();
+    Some(container::Chapter {
+        start: Timestamp::new(atom.start_time_code(), timing::NANOSECONDS),
+        end: atom.stop_time_code().map(|time_code| Timestamp::new(time_code, timing::NANOSECONDS)),
+        titles: titles,
+        children: range(0, atom.child_count()).filter_map(|index| {
+            convert_chapter(&atom.child(index))
+        }).collect(),
+    })
 }
 
+#[derive(Clone)]
 struct TrackImpl<'a> {
     track: Track<'a>,
     segment: &'a Segment,
@@ -646,12 +1351,17 @@ impl<'a> container::Track for TrackImpl<'a> {
         match self.track.track_type() {
             TrackType::Video => container::TrackType::Video,
             TrackType::Audio => container::TrackType::Audio,
+            TrackType::Subtitle => container::TrackType::Subtitle,
             _ => container::TrackType::Other,
         }
     }
 
     fn cluster_count(&self) -> Option<c_int> {
-        Some(self.segment.count() as c_int)
+        if self.segment.done_parsing() {
+            Some(self.segment.count() as c_int)
+        } else {
+            None
+        }
     }
 
     fn number(&self) -> c_long {
@@ -662,8 +1372,17 @@ impl<'a> container::Track for TrackImpl<'a> {
         codec_id_to_fourcc(self.track.codec_id())
     }
 
-    fn cluster<'b>(&'b self, cluster_index: i32) -> Result<Box<container::Cluster + 'b>,()> {
-        Ok(get_cluster(cluster_index, self.segment, self.reader))
+    fn duration(&self) -> Option<Timestamp> {
+        get_duration(self.segment)
+    }
+
+    fn cluster<'b>(&'b self, cluster_index: i32)
+                   -> Result<Box<container::Cluster + 'b>,MediaError> {
+        get_cluster(cluster_index, self.segment, self.reader)
+    }
+
+    fn seek(&self, time: &Timestamp) -> Result<container::SeekPosition,MediaError> {
+        seek(&self.track, time, self.segment)
     }
 
     fn as_video_track<'b>(&'b self) -> Result<Box<container::VideoTrack + 'b>,()> {
@@ -687,6 +1406,14 @@ impl<'a> container::Track for TrackImpl<'a> {
             reader: self.reader,
         }) as Box<container::AudioTrack + 'b>)
     }
+
+    fn as_subtitle_track<'b>(&'b self) -> Result<Box<container::SubtitleTrack + 'b>,()> {
+        if self.track.track_type() != TrackType::Subtitle ||
+                !is_text_subtitle_codec(self.track.codec_id()) {
+            return Err(())
+        }
+        Ok(Box::new((*self).clone()) as Box<container::SubtitleTrack + 'b>)
+    }
 }
 
 #[derive(Clone)]
@@ -702,21 +1429,34 @@ impl<'a> container::Track for VideoTrackImpl<'a> {
     }
 
     fn cluster_count(&self) -> Option<c_int> {
-        Some(self.segment.count() as c_int)
+        if self.segment.done_parsing() {
+            Some(self.segment.count() as c_int)
+        } else {
+            None
+        }
     }
 
     fn number(&self) -> c_long {
         self.track.as_track().number()
     }
 
-    fn cluster<'b>(&'b self, cluster_index: i32) -> Result<Box<container::Cluster + 'b>,()> {
-        Ok(get_cluster(cluster_index, self.segment, self.reader))
+    fn cluster<'b>(&'b self, cluster_index: i32)
+                   -> Result<Box<container::Cluster + 'b>,MediaError> {
+        get_cluster(cluster_index, self.segment, self.reader)
+    }
+
+    fn seek(&self, time: &Timestamp) -> Result<container::SeekPosition,MediaError> {
+        seek(&self.track.as_track(), time, self.segment)
     }
 
     fn codec(&self) -> Option<Vec<u8>> {
         codec_id_to_fourcc(self.track.as_track().codec_id())
     }
 
+    fn duration(&self) -> Option<Timestamp> {
+        get_duration(self.segment)
+    }
+
     fn as_video_track<'b>(&'b self) -> Result<Box<container::VideoTrack + 'b>,()> {
         Ok(Box::new((*self).clone()) as Box<container::VideoTrack + 'b>)
     }
@@ -744,7 +1484,12 @@ impl<'a> container::VideoTrack for VideoTrackImpl<'a> {
     }
 
 	fn headers(&self) -> Box<videodecoder::VideoHeaders> {
-		// TODO(pcwalton): Support H.264.
+        let track = self.track.as_track();
+        if track.codec_id() == b"V_MPEG4/ISO/AVC" {
+            if let Ok(headers) = AvccHeaders::parse(track.codec_private()) {
+                return Box::new(headers) as Box<videodecoder::VideoHeaders>
+            }
+        }
 		Box::new(videodecoder::EmptyVideoHeadersImpl) as Box<videodecoder::VideoHeaders>
 	}
 }
@@ -762,21 +1507,34 @@ impl<'a> container::Track for AudioTrackImpl<'a> {
     }
 
     fn cluster_count(&self) -> Option<c_int> {
-        Some(self.segment.count() as c_int)
+        if self.segment.done_parsing() {
+            Some(self.segment.count() as c_int)
+        } else {
+            None
+        }
     }
 
     fn number(&self) -> c_long {
         self.track.as_track().number()
     }
 
-    fn cluster<'b>(&'b self, cluster_index: i32) -> Result<Box<container::Cluster + 'b>,()> {
-        Ok(get_cluster(cluster_index, self.segment, self.reader))
+    fn cluster<'b>(&'b self, cluster_index: i32)
+                   -> Result<Box<container::Cluster + 'b>,MediaError> {
+        get_cluster(cluster_index, self.segment, self.reader)
+    }
+
+    fn seek(&self, time: &Timestamp) -> Result<container::SeekPosition,MediaError> {
+        seek(&self.track.as_track(), time, self.segment)
     }
 
     fn codec(&self) -> Option<Vec<u8>> {
         codec_id_to_fourcc(self.track.as_track().codec_id())
     }
 
+    fn duration(&self) -> Option<Timestamp> {
+        get_duration(self.segment)
+    }
+
     fn as_video_track<'b>(&'b self) -> Result<Box<container::VideoTrack + 'b>,()> {
         Err(())
     }
@@ -788,6 +1546,19 @@ impl<'a> container::Track for AudioTrackImpl<'a> {
 
 impl<'a> container::AudioTrack for AudioTrackImpl<'a> {
     fn sampling_rate(&self) -> c_double {
+        // Opus always decodes at 48 kHz. Muxers often record the rate of the original input here
+        // instead.
+        let track = self.track.as_track();
+        let codec_id = track.codec_id();
+        if codec_id == b"A_OPUS" {
+            return opus::SAMPLE_RATE as c_double
+        }
+
+        // Old-style HE-AAC tracks record the rate of the core AAC stream, which is half the
+        // output rate.
+        if let Some((_, true)) = aac_profile(codec_id) {
+            return self.track.sampling_rate() * 2.0
+        }
         self.track.sampling_rate()
     }
 
@@ -796,61 +1567,162 @@ impl<'a> container::AudioTrack for AudioTrackImpl<'a> {
     }
 
     fn headers(&self) -> Box<audiodecoder::AudioHeaders> {
-        // TODO(pcwalton): Support codecs other than Vorbis.
         let track = self.track.as_track();
+        let codec_id = track.codec_id();
+        if codec_id == b"A_OPUS" {
+            return Box::new(OpusHeaders {
+                id: track.codec_private().to_vec(),
+            })
+        }
+        if codec_id == b"A_AAC" || codec_id.starts_with(b"A_AAC/") {
+            // The codec private data is the AudioSpecificConfig. Older files name the profile in
+            // the codec ID instead and usually leave it out, so we have to build one.
+            let private = track.codec_private();
+            if !private.is_empty() {
+                return Box::new(AacHeaders {
+                    esds_chunk: private.to_vec(),
+                })
+            }
+            return match aac_profile(codec_id) {
+                Some((object_type, sbr)) => {
+                    Box::new(AacHeaders::from_parameters(object_type,
+                                                         self.track.sampling_rate() as u32,
+                                                         self.track.channels() as u16,
+                                                         sbr)) as Box<audiodecoder::AudioHeaders>
+                }
+                None => {
+                    Box::new(audiodecoder::EmptyAudioHeadersImpl) as Box<audiodecoder::AudioHeaders>
+                }
+            }
+        }
+        if codec_id != b"A_VORBIS" {
+            return Box::new(audiodecoder::EmptyAudioHeadersImpl)
+        }
+
+        // The three Vorbis headers are Xiph-laced together.
         let mut private = track.codec_private();
-        assert!(private[0] == 2);
+        if private.is_empty() || private[0] != 2 {
+            return Box::new(audiodecoder::EmptyAudioHeadersImpl)
+        }
         private = &private[1..private.len()];
 
         let id_size = read_lacing_size(&mut private);
         let comment_size = read_lacing_size(&mut private);
-        return Box::new(VorbisHeaders {
-            data: private.iter().map(|x| *x).collect(),
-            id_size: id_size,
-            comment_size: comment_size,
-        });
+        return match (id_size, comment_size) {
+            (Some(id_size), Some(comment_size)) if id_size + comment_size <= private.len() => {
+                Box::new(VorbisHeaders {
+                    data: private.iter().map(|x| *x).collect(),
+                    id_size: id_size,
+                    comment_size: comment_size,
+                }) as Box<audiodecoder::AudioHeaders>
+            }
+            _ => Box::new(audiodecoder::EmptyAudioHeadersImpl) as Box<audiodecoder::AudioHeaders>,
+        };
 
-        fn read_lacing_size(buffer: &mut &[u8]) -> usize {
+        fn read_lacing_size(buffer: &mut &[u8]) -> Option<usize> {
             let mut size = 0;
-            while buffer[0] == 255 {
+            while !buffer.is_empty() && buffer[0] == 255 {
                 size += 255;
                 *buffer = &(*buffer)[1..buffer.len()];
             }
+            if buffer.is_empty() {
+                return None
+            }
             size += buffer[0] as usize;
             *buffer = &(*buffer)[1..buffer.len()];
-            size
+            Some(size)
         }
     }
 }
 
+// Subtitle tracks have no type-specific parameters, so the generic track reads them directly.
+impl<'a> container::SubtitleTrack for TrackImpl<'a> {
+    fn header(&self) -> Option<String> {
+        let header = self.track.codec_private();
+        if header.is_empty() {
+            None
+        } else {
+            Some(subtitle_text(header))
+        }
+    }
+
+    fn cues(&self, cluster_index: i32) -> Result<Vec<container::SubtitleCue>,MediaError> {
+        let cluster = try!(get_cluster(cluster_index, self.segment, self.reader));
+        let track_number = self.track.number();
+        let mut cues = Vec::new();
+        let mut frame_index = 0;
+        loop {
+            let frame = match cluster.read_frame(frame_index, track_number) {
+                Ok(frame) => frame,
+                Err(MediaError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            };
+            let text = match frame.data() {
+                Some(data) => subtitle_text(data),
+                None => {
+                    let mut data: Vec<u8> = iter::repeat(0).take(frame.len() as usize).collect();
+                    try!(frame.read(data.as_mut_slice()));
+                    subtitle_text(data.as_slice())
+                }
+            };
+            let start = frame.time();
+            cues.push(container::SubtitleCue {
+                start: start,
+                end: frame.duration().map(|duration| start + duration),
+                payload: match self.track.codec_id() {
+                    b"S_TEXT/ASS" | b"S_TEXT/SSA" => container::SubtitlePayload::Ass(text),
+                    b"S_TEXT/WEBVTT" => container::SubtitlePayload::WebVtt(text),
+                    _ => container::SubtitlePayload::Text(text),
+                },
+            });
+            frame_index += 1
+        }
+        Ok(cues)
+    }
+}
+
+/// Returns true if the given codec is a text subtitle format that we understand.
+fn is_text_subtitle_codec(id: &[u8]) -> bool {
+    match id {
+        b"S_TEXT/UTF8" | b"S_TEXT/ASS" | b"S_TEXT/SSA" | b"S_TEXT/WEBVTT" => true,
+        _ => false,
+    }
+}
+
+/// Decodes subtitle text. Some muxers terminate it with a NUL even though Matroska doesn't call
+/// for one.
+fn subtitle_text(data: &[u8]) -> String {
+    let length = data.iter().position(|&byte| byte == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..length]).into_owned()
+}
+
 struct ClusterImpl<'a> {
     cluster: Cluster<'a>,
+    frame_index: Rc<FrameIndex>,
     segment: &'a Segment,
     reader: &'a MkvReader,
 }
 
 impl<'a> container::Cluster for ClusterImpl<'a> {
     fn read_frame<'b>(&'b self, frame_index: i32, track_number: c_long)
-                      -> Result<Box<container::Frame + 'b>,()> {
-        // FIXME(pcwalton): This is O(frames in this cluster); is this going to be a problem?
-        let (mut block_index, mut current_frame_index) = (0, 0);
-        loop {
-            let block = match self.cluster.entry(block_index as c_long) {
-                Ok(block_entry) => block_entry.block(),
-                Err(_) => return Err(()),
+                      -> Result<Box<container::Frame + 'b>,MediaError> {
+        // A laced block holds several frames, each of which counts separately.
+        let (block_index, lace_index) =
+            match self.frame_index.frame(track_number as c_longlong, frame_index) {
+                Some(frame) => frame,
+                None => return Err(MediaError::EndOfStream),
             };
-            if block.track_number() == track_number as i64 {
-                if current_frame_index == frame_index {
-                    break
-                } else {
-                    current_frame_index += 1
-                }
-            }
-            block_index += 1
-        }
+        let entry = match self.cluster.entry(block_index) {
+            Ok(entry) => entry,
+            Err(err) => return Err(webm_error(err as c_longlong)),
+        };
         Ok(Box::new(FrameImpl {
-            block: self.cluster.entry(block_index as c_long).unwrap().block(),
+            block: entry.block(),
+            block_index: block_index,
+            lace_index: lace_index,
+            entry: entry,
             cluster: &self.cluster,
+            frame_index: &*self.frame_index,
             segment: self.segment,
             reader: self.reader,
         }) as Box<container::Frame + 'b>)
@@ -859,72 +1731,304 @@ impl<'a> container::Cluster for ClusterImpl<'a> {
 
 struct FrameImpl<'a> {
     block: Block<'a>,
+    /// The position of the block in the cluster.
+    block_index: c_long,
+    /// The position of this frame among the laced frames of the block.
+    lace_index: c_int,
+    entry: BlockEntry<'a>,
     cluster: &'a Cluster<'a>,
+    frame_index: &'a FrameIndex,
     segment: &'a Segment,
     reader: &'a MkvReader,
 }
 
+impl<'a> FrameImpl<'a> {
+    fn track(&self) -> Option<Track<'a>> {
+        self.segment.tracks().map(|tracks| {
+            tracks.track_by_number(self.block.track_number() as c_long)
+        })
+    }
+
+    /// Returns the duration of the whole block in nanoseconds, if the file records it: either the
+    /// block's own duration or the track's default duration for each of its frames, in that
+    /// order of preference.
+    fn block_duration(&self) -> Option<i64> {
+        if let Some(duration) = self.entry.duration_time_code() {
+            return Some(duration * self.segment.info().time_code_scale())
+        }
+        match self.track().map(|track| track.default_duration()) {
+            None | Some(0) => None,
+            Some(default_duration) => {
+                Some(default_duration as i64 * self.block.frame_count() as i64)
+            }
+        }
+    }
+
+    /// Returns the time in nanoseconds from the start of this block to the start of the track's
+    /// next one. If this is the track's last block in the cluster, the start of the next cluster
+    /// stands in for that of the next block.
+    fn time_to_next_block(&self) -> Option<i64> {
+        let track_number = self.block.track_number();
+        let next_block_time = match self.frame_index.next_block(track_number, self.block_index) {
+            Some(next_block_index) => {
+                match self.cluster.entry(next_block_index) {
+                    Ok(entry) => entry.block().time(self.cluster),
+                    Err(_) => return None,
+                }
+            }
+            None => {
+                match self.segment.next(self.cluster.clone()) {
+                    Some(ref next_cluster) if !next_cluster.eos() => next_cluster.time(),
+                    _ => return None,
+                }
+            }
+        };
+        match next_block_time - self.block.time(self.cluster) {
+            gap if gap > 0 => Some(gap),
+            _ => None,
+        }
+    }
+}
+
 impl<'a> container::Frame for FrameImpl<'a> {
     fn len(&self) -> c_long {
-        self.block.frame(0).len()
+        self.block.frame(self.lace_index).len()
     }
 
-    fn read(&self, buffer: &mut [u8]) -> Result<(),()> {
-        match self.block.frame(0).read(self.reader, buffer) {
+    fn read(&self, buffer: &mut [u8]) -> Result<(),MediaError> {
+        match self.block.frame(self.lace_index).read(self.reader, buffer) {
             Ok(_) => Ok(()),
-            Err(_) => Err(()),
+            Err(err) => Err(webm_error(err as c_longlong)),
         }
     }
 
+    fn data<'b>(&'b self) -> Option<&'b [u8]> {
+        let bytes = match self.reader.bytes() {
+            Some(bytes) => bytes,
+            None => return None,
+        };
+        let frame = self.block.frame(self.lace_index);
+        let (start, length) = (frame.pos(), frame.len());
+        if start < 0 || length < 0 || (start as u64) + (length as u64) > bytes.len() as u64 {
+            return None
+        }
+        Some(&bytes[start as usize..(start as usize + length as usize)])
+    }
+
     fn track_number(&self) -> c_long {
         self.block.track_number() as c_long
     }
 
+    /// Laced frames share the timestamp of their block, so the later ones are placed by dividing
+    /// up the block's duration, as `duration()` does. Files often record no duration for laced
+    /// audio, in which case the frames are spread over the time until the track's next block.
     fn time(&self) -> Timestamp {
-        Timestamp {
-            ticks: self.block.time_code(self.cluster),
-            ticks_per_second: 1_000_000_000.0 / self.segment.info().time_code_scale() as f64,
+        let mut time = self.block.time(self.cluster);
+        if self.lace_index > 0 {
+            if let Some(duration) = self.block_duration().or_else(|| self.time_to_next_block()) {
+                time += duration * self.lace_index as i64 / self.block.frame_count() as i64
+            }
         }
+
+        // The samples that prime the decoder are dropped rather than played, so the timestamps
+        // start after them.
+        let codec_delay = self.track().map(|track| track.codec_delay() as i64).unwrap_or(0);
+        Timestamp::new(time - codec_delay, timing::NANOSECONDS)
     }
 
     fn rendering_offset(&self) -> i64 {
         0
     }
+
+    fn is_keyframe(&self) -> bool {
+        self.block.is_key()
+    }
+
+    fn duration(&self) -> Option<i64> {
+        self.block_duration().map(|duration| duration / self.block.frame_count() as i64)
+    }
+
+    fn is_discardable(&self) -> bool {
+        // Only SimpleBlocks carry the discardable flag.
+        if self.entry.kind() != BLOCK_KIND_SIMPLE {
+            return false
+        }
+        match self.block.flags(self.reader) {
+            Ok(flags) => (flags & SIMPLE_BLOCK_FLAG_DISCARDABLE) != 0,
+            Err(_) => false,
+        }
+    }
 }
 
 fn codec_id_to_fourcc(id: &[u8]) -> Option<Vec<u8>> {
-    const TABLE: [(&'static [u8], [u8; 4]); 2] = [
+    const TABLE: [(&'static [u8], [u8; 4]); 6] = [
         (b"V_VP8", [b'V', b'P', b'8', b'0']),
-        (b"A_VORBIS", [b'v', b'o', b'r', b'b'])
+        (b"V_VP9", [b'V', b'P', b'9', b'0']),
+        (b"V_MPEG4/ISO/AVC", [b'a', b'v', b'c', b' ']),
+        (b"A_VORBIS", [b'v', b'o', b'r', b'b']),
+        (b"A_OPUS", [b'o', b'p', b'u', b's']),
+        (b"A_AAC", [b'a', b'a', b'c', b' '])
     ];
     for &(key, value) in TABLE.iter() {
         if key == id {
             return Some(value.iter().map(|x| *x).collect())
         }
     }
+    if aac_profile(id).is_some() {
+        return Some(vec![b'a', b'a', b'c', b' '])
+    }
     None
 }
 
+/// Returns the AAC object type named by an old-style codec ID such as `A_AAC/MPEG4/LC/SBR`, and
+/// whether the stream uses spectral band replication.
+fn aac_profile(id: &[u8]) -> Option<(u8, bool)> {
+    if !id.starts_with(b"A_AAC/MPEG2/") && !id.starts_with(b"A_AAC/MPEG4/") {
+        return None
+    }
+    match &id[12..] {
+        b"MAIN" => Some((aac::OBJECT_TYPE_MAIN, false)),
+        b"LC" => Some((aac::OBJECT_TYPE_LC, false)),
+        b"LC/SBR" => Some((aac::OBJECT_TYPE_LC, true)),
+        b"SSR" => Some((aac::OBJECT_TYPE_SSR, false)),
+        b"LTP" => Some((aac::OBJECT_TYPE_LTP, false)),
+        _ => None,
+    }
+}
+
+/// Converts a libwebm error code to a `MediaError`. Positive values are the number of bytes
+/// libwebm needs in order to make progress.
+fn webm_error(code: c_longlong) -> MediaError {
+    if code > 0 || code == E_BUFFER_NOT_FULL as c_longlong {
+        MediaError::NeedMoreData
+    } else {
+        MediaError::backend(code as i64, "libwebm")
+    }
+}
+
+/// Loads clusters until at least `count` of them have been loaded or there are no more. Returns
+/// `MediaError::NeedMoreData` if the stream ends first.
+fn load_clusters(segment: &Segment, count: c_ulong) -> Result<(),MediaError> {
+    while segment.count() < count && !segment.done_parsing() {
+        match segment.load_cluster() {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => return Err(webm_error(err as c_longlong)),
+        }
+    }
+    Ok(())
+}
+
 fn get_cluster<'a>(cluster_index: i32, segment: &'a Segment, reader: &'a MkvReader)
-                   -> Box<container::Cluster + 'a> {
-    let mut cluster = segment.first().unwrap();
-    for _ in range(0, cluster_index) {
-        cluster = segment.next(cluster).unwrap();
+                   -> Result<Box<container::Cluster + 'a>,MediaError> {
+    if cluster_index < 0 {
+        return Err(MediaError::EndOfStream)
+    }
+    try!(load_clusters(segment, cluster_index as c_ulong + 1));
+    if segment.count() <= cluster_index as c_ulong {
+        return Err(MediaError::EndOfStream)
     }
 
+    let cluster = match segment.cluster(cluster_index as c_long) {
+        Some(cluster) => cluster,
+        None => return Err(MediaError::EndOfStream),
+    };
+
     // Parse all entries.
     loop {
-        let (err, _) = cluster.parse();
-        if !err.unwrap() {
-            break
+        match cluster.parse() {
+            (Ok(true), _) => {}
+            (Ok(false), _) => break,
+            (Err(err), _) => return Err(webm_error(err as c_longlong)),
         }
     }
 
-    Box::new(ClusterImpl {
+    let frame_index = try!(segment.frame_index(&cluster));
+    Ok(Box::new(ClusterImpl {
         cluster: cluster,
+        frame_index: frame_index,
         segment: segment,
         reader: reader,
-    }) as Box<container::Cluster + 'a>
+    }) as Box<container::Cluster + 'a>)
+}
+
+/// Matroska only records the duration of the segment as a whole, so this is used for the tracks
+/// too.
+fn get_duration(segment: &Segment) -> Option<Timestamp> {
+    let info = segment.info();
+    let duration = info.duration();
+    if duration < 0 {
+        return None
+    }
+    Some(Timestamp::new(duration / info.time_code_scale(), time_base(segment)))
+}
+
+/// Returns the time base of block timecodes, which are in units of the segment's timecode scale.
+/// Segments whose scale can't be represented are rejected when the file is opened.
+fn time_base(segment: &Segment) -> TimeBase {
+    TimeBase::from_ratio(segment.info().time_code_scale() as u64, 1_000_000_000).unwrap()
+}
+
+fn seek<'a>(track: &Track<'a>, time: &Timestamp, segment: &'a Segment)
+            -> Result<container::SeekPosition,MediaError> {
+    let time_ns = cmp::max(time.rescale(timing::NANOSECONDS).ticks, 0);
+
+    // Both the cues and libwebm's fallback search only consider loaded clusters, so load as many
+    // as are available.
+    match load_clusters(segment, !0) {
+        Ok(()) | Err(MediaError::NeedMoreData) => {}
+        Err(err) => return Err(err),
+    }
+
+    // Prefer the cues, which point directly at keyframes. Files without them fall back to
+    // libwebm's search over the clusters.
+    let cue_entry = match segment.cues() {
+        Some(cues) => {
+            cues.load();
+            match cues.find(time_ns, track) {
+                Some(position) => cues.block(&position),
+                None => None,
+            }
+        }
+        None => None,
+    };
+    let entry = match cue_entry {
+        Some(entry) => entry,
+        None => {
+            match track.seek(time_ns) {
+                Ok(entry) => entry,
+                Err(err) => return Err(webm_error(err as c_longlong)),
+            }
+        }
+    };
+    if entry.eos() {
+        return Err(MediaError::EndOfStream)
+    }
+
+    // `Cluster::read_frame()` counts only the frames of the given track, so count the frames in
+    // the blocks of this track that precede the keyframe.
+    let cluster = entry.cluster();
+    let track_number = track.number() as c_longlong;
+    let mut frame_index = 0;
+    for block_index in range(0, entry.index()) {
+        match cluster.entry(block_index) {
+            Ok(block_entry) => {
+                let block = block_entry.block();
+                if block.track_number() == track_number {
+                    frame_index += block.frame_count()
+                }
+            }
+            Err(err) => return Err(webm_error(err as c_longlong)),
+        }
+    }
+
+    // Report the time as `Frame::time()` does, after the codec delay.
+    let time = entry.block().time(&cluster) - track.codec_delay() as i64;
+    Ok(container::SeekPosition {
+        cluster_index: cluster.index() as i32,
+        frame_index: frame_index,
+        time: Timestamp::new(time, timing::NANOSECONDS),
+    })
 }
 
 pub const CONTAINER_READER: container::RegisteredContainerReader =
@@ -933,6 +2037,12 @@ pub const CONTAINER_READER: container::RegisteredContainerReader =
             "video/webm",
             "video/x-matroska",
         ],
+        signatures: &[
+            container::Signature {
+                offset: 0,
+                pattern: &[0x1a, 0x45, 0xdf, 0xa3],
+            },
+        ],
         read: ContainerReaderImpl::new,
     };
 
@@ -950,6 +2060,16 @@ type WebmClusterRef = *mut WebmCluster;
 type WebmBlockEntryRef = *mut WebmBlockEntry;
 type WebmBlockRef = *mut WebmBlock;
 type WebmBlockFrameRef = *mut WebmBlockFrame;
+type WebmCuesRef = *mut WebmCues;
+type WebmCuePointRef = *mut WebmCuePoint;
+type WebmCueTrackPositionRef = *mut WebmCueTrackPosition;
+type WebmTagsRef = *mut WebmTags;
+type WebmTagRef = *mut WebmTag;
+type WebmSimpleTagRef = *mut WebmSimpleTag;
+type WebmChaptersRef = *mut WebmChapters;
+type WebmEditionRef = *mut WebmEdition;
+type WebmChapterAtomRef = *mut WebmChapterAtom;
+type WebmChapterDisplayRef = *mut WebmChapterDisplay;
 
 #[repr(C)]
 struct WebmIMkvReader;
@@ -976,6 +2096,26 @@ struct WebmBlock;
 #[repr(C)]
 struct WebmBlockFrame;
 #[repr(C)]
+struct WebmCues;
+#[repr(C)]
+struct WebmCuePoint;
+#[repr(C)]
+struct WebmCueTrackPosition;
+#[repr(C)]
+struct WebmTags;
+#[repr(C)]
+struct WebmTag;
+#[repr(C)]
+struct WebmSimpleTag;
+#[repr(C)]
+struct WebmChapters;
+#[repr(C)]
+struct WebmEdition;
+#[repr(C)]
+struct WebmChapterAtom;
+#[repr(C)]
+struct WebmChapterDisplay;
+#[repr(C)]
 #[allow(non_snake_case)]
 struct WebmCustomMkvReaderCallbacks {
     Read: extern "C" fn(pos: c_longlong, len: c_long, buf: *mut c_uchar, userData: *mut c_void)
@@ -1009,13 +2149,21 @@ extern {
                          -> WebmSegmentRef;
     fn WebmSegmentDestroy(segment: WebmSegmentRef);
     fn WebmSegmentLoad(segment: WebmSegmentRef) -> c_long;
+    fn WebmSegmentParseHeaders(segment: WebmSegmentRef) -> c_longlong;
+    fn WebmSegmentLoadCluster(segment: WebmSegmentRef) -> c_long;
+    fn WebmSegmentDoneParsing(segment: WebmSegmentRef) -> bool;
     fn WebmSegmentGetTracks(segment: WebmSegmentRef) -> WebmTracksRef;
     fn WebmSegmentGetInfo(segment: WebmSegmentRef) -> WebmSegmentInfoRef;
     fn WebmSegmentGetCount(segment: WebmSegmentRef) -> c_ulong;
     fn WebmSegmentGetFirst(segment: WebmSegmentRef) -> WebmClusterRef;
     fn WebmSegmentGetNext(segment: WebmSegmentRef, cluster: WebmClusterRef) -> WebmClusterRef;
 
+    fn WebmSegmentGetCues(segment: WebmSegmentRef) -> WebmCuesRef;
+    fn WebmSegmentGetTags(segment: WebmSegmentRef) -> WebmTagsRef;
+    fn WebmSegmentParseChapters(segment: WebmSegmentRef) -> WebmChaptersRef;
+
     fn WebmSegmentInfoGetTimeCodeScale(segmentInfo: WebmSegmentInfoRef) -> c_longlong;
+    fn WebmSegmentInfoGetDuration(segmentInfo: WebmSegmentInfoRef) -> c_longlong;
 
     fn WebmTracksDestroy(tracks: WebmTracksRef);
     fn WebmTracksGetCount(tracks: WebmTracksRef) -> c_ulong;
@@ -1027,6 +2175,10 @@ extern {
     fn WebmTrackGetNumber(track: WebmTrackRef) -> c_long;
     fn WebmTrackGetCodecId(track: WebmTrackRef) -> *const c_char;
     fn WebmTrackGetCodecPrivate(track: WebmTrackRef, size: *mut size_t) -> *const c_uchar;
+    fn WebmTrackGetDefaultDuration(track: WebmTrackRef) -> u64;
+    fn WebmTrackGetCodecDelay(track: WebmTrackRef) -> u64;
+    fn WebmTrackSeek(track: WebmTrackRef, timeNs: c_longlong, err: *mut c_long)
+                     -> WebmBlockEntryRef;
 
     fn WebmVideoTrackDestroy(track: WebmVideoTrackRef);
     fn WebmVideoTrackGetWidth(track: WebmVideoTrackRef) -> c_longlong;
@@ -1044,6 +2196,7 @@ extern {
     fn WebmClusterGetFirst(cluster: WebmClusterRef, err: *mut c_long) -> WebmBlockEntryRef;
     fn WebmClusterGetNext(cluster: WebmClusterRef, entry: WebmBlockEntryRef, err: *mut c_long)
                           -> WebmBlockEntryRef;
+    fn WebmClusterGetIndex(cluster: WebmClusterRef) -> c_long;
     fn WebmClusterGetEntryCount(cluster: WebmClusterRef) -> c_long;
     fn WebmClusterGetEntry(cluster: WebmClusterRef, index: c_long, err: *mut c_long)
                            -> WebmBlockEntryRef;
@@ -1053,6 +2206,11 @@ extern {
     fn WebmBlockEntryDestroy(entry: WebmBlockEntryRef);
     fn WebmBlockEntryGetBlock(entry: WebmBlockEntryRef) -> WebmBlockRef;
     fn WebmBlockEntryEos(entry: WebmBlockEntryRef) -> bool;
+    fn WebmBlockEntryGetCluster(entry: WebmBlockEntryRef) -> WebmClusterRef;
+    fn WebmBlockEntryGetIndex(entry: WebmBlockEntryRef) -> c_long;
+    fn WebmBlockEntryGetKind(entry: WebmBlockEntryRef) -> c_long;
+
+    fn WebmBlockGroupGetDurationTimeCode(entry: WebmBlockEntryRef) -> c_longlong;
 
     fn WebmBlockDestroy(block: WebmBlockRef);
     fn WebmBlockGetFrameCount(block: WebmBlockRef) -> c_int;
@@ -1062,6 +2220,8 @@ extern {
     fn WebmBlockGetTimeCode(block: WebmBlockRef, cluster: WebmClusterRef) -> c_longlong;
     fn WebmBlockGetTime(block: WebmBlockRef, cluster: WebmClusterRef) -> c_longlong;
     fn WebmBlockIsKey(block: WebmBlockRef) -> bool;
+    fn WebmBlockGetFlags(block: WebmBlockRef, reader: WebmIMkvReaderRef, flags: *mut c_uchar)
+                         -> c_long;
 
     fn WebmBlockFrameDestroy(blockFrame: WebmBlockFrameRef);
     fn WebmBlockFrameGetPos(blockFrame: WebmBlockFrameRef) -> c_longlong;
@@ -1070,5 +2230,45 @@ extern {
                           reader: WebmIMkvReaderRef,
                           buffer: *mut c_uchar)
                           -> c_long;
+
+    fn WebmCuesDoneParsing(cues: WebmCuesRef) -> bool;
+    fn WebmCuesLoadCuePoint(cues: WebmCuesRef) -> bool;
+    fn WebmCuesFind(cues: WebmCuesRef,
+                    timeNs: c_longlong,
+                    track: WebmTrackRef,
+                    cuePoint: *mut WebmCuePointRef,
+                    trackPosition: *mut WebmCueTrackPositionRef)
+                    -> bool;
+    fn WebmCuesGetBlock(cues: WebmCuesRef,
+                        cuePoint: WebmCuePointRef,
+                        trackPosition: WebmCueTrackPositionRef)
+                        -> WebmBlockEntryRef;
+
+    fn WebmTagsGetTagCount(tags: WebmTagsRef) -> c_int;
+    fn WebmTagsGetTag(tags: WebmTagsRef, index: c_int) -> WebmTagRef;
+    fn WebmTagGetSimpleTagCount(tag: WebmTagRef) -> c_int;
+    fn WebmTagGetSimpleTag(tag: WebmTagRef, index: c_int) -> WebmSimpleTagRef;
+    fn WebmSimpleTagGetTagName(simpleTag: WebmSimpleTagRef) -> *const c_char;
+    fn WebmSimpleTagGetTagString(simpleTag: WebmSimpleTagRef) -> *const c_char;
+
+    fn WebmChaptersDestroy(chapters: WebmChaptersRef);
+    fn WebmChaptersGetEditionCount(chapters: WebmChaptersRef) -> c_int;
+    fn WebmChaptersGetEdition(chapters: WebmChaptersRef, index: c_int) -> WebmEditionRef;
+    fn WebmEditionIsDefault(edition: WebmEditionRef) -> bool;
+    fn WebmEditionIsHidden(edition: WebmEditionRef) -> bool;
+    fn WebmEditionGetAtomCount(edition: WebmEditionRef) -> c_int;
+    fn WebmEditionGetAtom(edition: WebmEditionRef, index: c_int) -> WebmChapterAtomRef;
+    fn WebmChapterAtomGetStartTimecode(atom: WebmChapterAtomRef) -> c_longlong;
+    fn WebmChapterAtomGetStopTimecode(atom: WebmChapterAtomRef) -> c_longlong;
+    fn WebmChapterAtomIsHidden(atom: WebmChapterAtomRef) -> bool;
+    fn WebmChapterAtomIsEnabled(atom: WebmChapterAtomRef) -> bool;
+    fn WebmChapterAtomGetDisplayCount(atom: WebmChapterAtomRef) -> c_int;
+    fn WebmChapterAtomGetDisplay(atom: WebmChapterAtomRef, index: c_int)
+                                 -> WebmChapterDisplayRef;
+    fn WebmChapterAtomGetChildCount(atom: WebmChapterAtomRef) -> c_int;
+    fn WebmChapterAtomGetChild(atom: WebmChapterAtomRef, index: c_int) -> WebmChapterAtomRef;
+    fn WebmChapterDisplayGetString(display: WebmChapterDisplayRef) -> *const c_char;
+    fn WebmChapterDisplayGetLanguage(display: WebmChapterDisplayRef) -> *const c_char;
+    fn WebmChapterDisplayGetCountry(display: WebmChapterDisplayRef) -> *const c_char;
 }
 
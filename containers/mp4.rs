@@ -10,16 +10,20 @@
 use audiodecoder;
 use codecs::aac::AacHeaders;
 use container;
+use error::MediaError;
 use pixelformat::PixelFormat;
-use streaming::StreamReader;
-use timing::Timestamp;
+use streaming::{self, StreamReader};
+use timing::{TimeBase, Timestamp};
 use videodecoder;
 
 use libc::{self, c_char, c_double, c_int, c_long, c_void};
+use std::cell::Cell;
+use std::cmp;
 use std::ffi::CString;
+use std::io::{ErrorKind, Seek, SeekFrom};
 use std::mem;
-use std::old_io::SeekStyle;
 use std::ptr;
+use std::rc::Rc;
 use std::slice::bytes;
 use std::slice;
 use std::str::{self, FromStr};
@@ -27,6 +31,14 @@ use std;
 
 pub struct Mp4FileHandle {
     handle: ffi::MP4FileHandle,
+    /// Set by the file provider when a read fails because the data hasn't arrived yet.
+    starved: Rc<Cell<bool>>,
+}
+
+/// The state passed to the file provider callbacks.
+struct FileProviderState {
+    reader: Box<StreamReader>,
+    starved: Rc<Cell<bool>>,
 }
 
 impl Drop for Mp4FileHandle {
@@ -38,12 +50,19 @@ impl Drop for Mp4FileHandle {
 }
 
 impl Mp4FileHandle {
-    pub fn read(reader: Box<StreamReader>) -> Result<Mp4FileHandle,()> {
+    /// Opens the file. If this fails, returns true if a read failed because the data hadn't
+    /// arrived yet, in which case it may succeed once more data arrives.
+    pub fn read(reader: Box<StreamReader>) -> Result<Mp4FileHandle,bool> {
         // Ugh. Just… ugh, ugh, ugh. The only thing we can pass to the open callback that
         // constructs the user data is a UTF-8 encoded string. So we encode the pointer to the
         // stream reader as a string and decode it in the callback.
+        let starved = Rc::new(Cell::new(false));
         let handle = unsafe {
-            let address = mem::transmute::<Box<Box<_>>,*mut c_void>(Box::new(reader));
+            let state = Box::new(FileProviderState {
+                reader: reader,
+                starved: starved.clone(),
+            });
+            let address = mem::transmute::<Box<_>,*mut c_void>(state);
             let fake_path = format!("{}", address as usize);
             let fake_path = CString::from_slice(fake_path.as_bytes());
             ffi::MP4ReadProvider(fake_path.as_ptr(), &FILE_PROVIDER)
@@ -51,9 +70,32 @@ impl Mp4FileHandle {
         if !handle.is_null() {
             Ok(Mp4FileHandle {
                 handle: handle,
+                starved: starved,
             })
         } else {
-            Err(())
+            // mp4v2 has closed the provider by now, but we still hold the starved flag.
+            Err(starved.get())
+        }
+    }
+
+    /// Returns true if the last read from the stream failed because the data hasn't arrived
+    /// yet.
+    pub fn is_starved(&self) -> bool {
+        self.starved.get()
+    }
+
+    /// Returns the duration of the movie from the `mvhd` atom, in units of
+    /// `movie_time_scale()`.
+    pub fn movie_duration(&self) -> ffi::MP4Duration {
+        unsafe {
+            ffi::MP4GetDuration(self.handle)
+        }
+    }
+
+    /// Returns the time scale of the movie from the `mvhd` atom.
+    pub fn movie_time_scale(&self) -> u32 {
+        unsafe {
+            ffi::MP4GetTimeScale(self.handle)
         }
     }
 
@@ -140,6 +182,13 @@ impl Mp4FileHandle {
         }
     }
 
+    /// Returns the duration of the track from its `mdhd` atom, in units of `time_scale()`.
+    pub fn track_duration(&self, track_id: ffi::MP4TrackId) -> ffi::MP4Duration {
+        unsafe {
+            ffi::MP4GetTrackDuration(self.handle, track_id)
+        }
+    }
+
     pub fn audio_channels(&self, track_id: ffi::MP4TrackId) -> c_int {
         unsafe {
             ffi::MP4GetTrackAudioChannels(self.handle, track_id)
@@ -183,6 +232,48 @@ impl Mp4FileHandle {
         }
     }
 
+    /// Returns the ID of the sample that is playing at `when`, or `MP4_INVALID_SAMPLE_ID` if
+    /// `when` is past the end of the track.
+    pub fn sample_id_from_time(&self, track_id: ffi::MP4TrackId, when: ffi::MP4Timestamp)
+                               -> ffi::MP4SampleId {
+        unsafe {
+            ffi::MP4GetSampleIdFromTime(self.handle, track_id, when, false)
+        }
+    }
+
+    pub fn sample_time(&self, track_id: ffi::MP4TrackId, sample_id: ffi::MP4SampleId)
+                       -> ffi::MP4Timestamp {
+        unsafe {
+            ffi::MP4GetSampleTime(self.handle, track_id, sample_id)
+        }
+    }
+
+    /// Returns true if the given sample is a sync sample (i.e. a keyframe), according to the sync
+    /// sample table.
+    pub fn is_sync_sample(&self, track_id: ffi::MP4TrackId, sample_id: ffi::MP4SampleId)
+                          -> Result<bool,()> {
+        match unsafe {
+            ffi::MP4GetSampleSync(self.handle, track_id, sample_id)
+        } {
+            result if result < 0 => Err(()),
+            result => Ok(result != 0),
+        }
+    }
+
+    /// Returns the `MP4_SDT_*` flags for the given sample from the sample dependency table, if
+    /// the track has one.
+    pub fn sample_dependency_flags(&self, track_id: ffi::MP4TrackId, sample_id: ffi::MP4SampleId)
+                                   -> Result<u32,()> {
+        let mut flags = 0;
+        unsafe {
+            if ffi::MP4GetSampleDependencyFlags(self.handle, track_id, sample_id, &mut flags) {
+                Ok(flags)
+            } else {
+                Err(())
+            }
+        }
+    }
+
     pub fn read_sample<'a>(&'a self, track_id: ffi::MP4TrackId, sample_id: ffi::MP4SampleId)
                            -> Result<Sample<'a>,()> {
         let mut bytes = ptr::null_mut();
@@ -191,6 +282,7 @@ impl Mp4FileHandle {
         let mut duration = 0;
         let mut rendering_offset = 0;
         let mut is_sync_sample = false;
+        self.starved.set(false);
         unsafe {
             if !ffi::MP4ReadSample(self.handle,
                                    track_id,
@@ -260,11 +352,67 @@ impl Mp4FileHandle {
 		}
 	}
 
-    fn time_to_timestamp(&self, ticks: i64, track_id: ffi::MP4TrackId) -> Timestamp {
-        Timestamp {
-            ticks: ticks,
-            ticks_per_second: self.time_scale(track_id) as f64,
+    /// Reads the iTunes-style metadata from the `ilst` atom.
+    pub fn tags(&self) -> Result<Mp4Tags,()> {
+        unsafe {
+            let tags = ffi::MP4TagsAlloc();
+            if tags.is_null() {
+                return Err(())
+            }
+            let tags = Mp4Tags {
+                tags: tags,
+            };
+            if !ffi::MP4TagsFetch(tags.tags, self.handle) {
+                return Err(())
+            }
+            Ok(tags)
+        }
+    }
+
+    /// Reads the chapter list, from a QuickTime chapter text track if there is one and otherwise
+    /// from a Nero `chpl` atom.
+    pub fn chapters(&self) -> Vec<container::Chapter> {
+        let mut result = Vec::new();
+        unsafe {
+            let (mut chapters, mut chapter_count) = (ptr::null_mut(), 0);
+            let chapter_type = ffi::MP4GetChapters(self.handle,
+                                                   &mut chapters,
+                                                   &mut chapter_count,
+                                                   ffi::MP4_CHAPTER_TYPE_ANY);
+            if chapter_type == ffi::MP4_CHAPTER_TYPE_NONE || chapters.is_null() {
+                return result
+            }
+
+            // Chapters are stored as consecutive durations in milliseconds.
+            let time_base = TimeBase::new(1, 1000);
+            let mut start = 0;
+            for chapter in slice::from_raw_mut_buf(&chapters, chapter_count as usize).iter() {
+                let end = start + chapter.duration as i64;
+                let title = std::ffi::c_str_to_bytes(&chapter.title.as_ptr());
+                result.push(container::Chapter {
+                    start: Timestamp::new(start, time_base),
+                    end: Some(Timestamp::new(end, time_base)),
+                    titles: vec![container::ChapterTitle {
+                        title: String::from_utf8_lossy(title).into_owned(),
+                        language: None,
+                        country: None,
+                    }],
+                    children: Vec::new(),
+                });
+                start = end
+            }
+            ffi::MP4Free(chapters as *mut c_void);
         }
+        result
+    }
+
+    fn time_to_timestamp(&self, ticks: i64, track_id: ffi::MP4TrackId) -> Timestamp {
+        Timestamp::new(ticks, self.time_base(track_id))
+    }
+
+    fn time_base(&self, track_id: ffi::MP4TrackId) -> TimeBase {
+        // A zero time scale is malformed; treat it as one tick per second rather than panicking.
+        TimeBase::new(1, cmp::max(self.time_scale(track_id), 1))
     }
 }
 
@@ -287,8 +435,8 @@ extern "C" fn file_provider_open(name: *const c_char, _: ffi::MP4FileMode) -> *m
 
 extern "C" fn file_provider_seek(mut handle: *mut c_void, pos: i64) -> c_int {
     unsafe {
-        let reader: &mut Box<Box<StreamReader>> = mem::transmute(&mut handle);
-        if reader.seek(pos, SeekStyle::SeekSet).is_ok() {
+        let state: &mut Box<FileProviderState> = mem::transmute(&mut handle);
+        if pos >= 0 && state.reader.seek(SeekFrom::Start(pos as u64)).is_ok() {
             0
         } else {
             1
@@ -307,13 +455,17 @@ extern "C" fn file_provider_read(mut handle: *mut c_void,
     }
 
     unsafe {
-        let reader: &mut Box<Box<StreamReader>> = mem::transmute(&mut handle);
-        match reader.read_at_least(size as usize,
-                                   slice::from_raw_mut_buf(&(buffer as *mut u8), size as usize)) {
-            Ok(number_read) => {
+        let state: &mut Box<FileProviderState> = mem::transmute(&mut handle);
+        let buffer = slice::from_raw_mut_buf(&(buffer as *mut u8), size as usize);
+        match streaming::read_fully(&mut *state.reader, buffer) {
+            Ok(number_read) if number_read == size as usize => {
                 *nin = number_read as i64;
                 0
             }
+            Err(ref err) if err.kind() == ErrorKind::ResourceUnavailable => {
+                state.starved.set(true);
+                1
+            }
             _ => 1,
         }
     }
@@ -326,15 +478,17 @@ extern "C" fn file_provider_write(_: *mut c_void, _: *const c_void, _: i64, _: *
 
 extern "C" fn file_provider_close(handle: *mut c_void) -> c_int {
     unsafe {
-        drop(mem::transmute::<_,Box<Box<StreamReader>>>(handle))
+        drop(mem::transmute::<_,Box<FileProviderState>>(handle))
     }
     0
 }
 
 extern "C" fn file_provider_get_size(mut handle: *mut c_void, nout: *mut i64) -> c_int {
     unsafe {
-        let reader: &mut Box<Box<StreamReader>> = mem::transmute(&mut handle);
-        *nout = reader.total_size() as i64;
+        let state: &mut Box<FileProviderState> = mem::transmute(&mut handle);
+        // Streams of unknown length report `u64::MAX`, which would come out as -1. Report the
+        // largest size instead; reads past the data that has arrived set the starved flag.
+        *nout = cmp::min(state.reader.total_size(), std::i64::MAX as u64) as i64;
     }
     0
 }
@@ -347,6 +501,57 @@ pub struct Sample<'a> {
     pub is_sync_sample: bool,
 }
 
+pub struct Mp4Tags {
+    tags: *const ffi::MP4Tags,
+}
+
+impl Drop for Mp4Tags {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::MP4TagsFree(self.tags)
+        }
+    }
+}
+
+impl Mp4Tags {
+    pub fn to_metadata(&self) -> Vec<container::MetadataTag> {
+        let mut result = Vec::new();
+        unsafe {
+            let tags = &*self.tags;
+            let strings = [
+                ("title", tags.name),
+                ("artist", tags.artist),
+                ("album_artist", tags.albumArtist),
+                ("album", tags.album),
+                ("grouping", tags.grouping),
+                ("composer", tags.composer),
+                ("comment", tags.comments),
+                ("genre", tags.genre),
+                ("date", tags.releaseDate),
+                ("copyright", tags.copyright),
+                ("encoder", tags.encodingTool),
+                ("encoded_by", tags.encodedBy),
+            ];
+            for &(key, value) in strings.iter() {
+                if value.is_null() {
+                    continue
+                }
+                let value = String::from_utf8_lossy(std::ffi::c_str_to_bytes(&value));
+                result.push(container::MetadataTag::new(key, value.into_owned()))
+            }
+            if !tags.track.is_null() {
+                result.push(container::MetadataTag::new("track_number",
+                                                        format!("{}", (*tags.track).index)))
+            }
+            if !tags.disk.is_null() {
+                result.push(container::MetadataTag::new("disc_number",
+                                                        format!("{}", (*tags.disk).index)))
+            }
+        }
+        result
+    }
+}
+
 pub struct H264Headers {
 	seq_headers: *mut *mut u8,
 	seq_header_size: *mut u32,
@@ -400,10 +605,12 @@ struct ContainerReaderImpl {
 }
 
 impl ContainerReaderImpl {
-    fn new(reader: Box<StreamReader>) -> Result<Box<container::ContainerReader + 'static>,()> {
+    fn new(reader: Box<StreamReader>)
+           -> Result<Box<container::ContainerReader + 'static>,MediaError> {
         let handle = match Mp4FileHandle::read(reader) {
             Ok(handle) => handle,
-            Err(_) => return Err(()),
+            Err(true) => return Err(MediaError::NeedMoreData),
+            Err(false) => return Err(MediaError::Malformed),
         };
 
         Ok(Box::new(ContainerReaderImpl {
@@ -430,6 +637,26 @@ impl container::ContainerReader for ContainerReaderImpl {
             handle: &self.handle,
         }) as Box<container::Track + 'a>
     }
+
+    fn metadata(&self) -> Vec<container::MetadataTag> {
+        match self.handle.tags() {
+            Ok(tags) => tags.to_metadata(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn chapters(&self) -> Vec<container::Chapter> {
+        self.handle.chapters()
+    }
+
+    fn duration(&self) -> Option<Timestamp> {
+        match (self.handle.movie_duration(), self.handle.movie_time_scale()) {
+            (0, _) | (_, 0) => None,
+            (duration, time_scale) => {
+                Some(Timestamp::new(duration as i64, TimeBase::new(1, time_scale)))
+            }
+        }
+    }
 }
 
 pub struct TrackImpl<'a> {
@@ -461,13 +688,27 @@ impl<'a> container::Track for TrackImpl<'a> {
         get_codec(self.handle, self.id)
     }
 
-    fn cluster<'b>(&'b self, cluster_index: i32) -> Result<Box<container::Cluster + 'b>,()> {
-        assert!(cluster_index == 0);
+    fn duration(&self) -> Option<Timestamp> {
+        match self.handle.track_duration(self.id) {
+            0 => None,
+            duration => Some(self.handle.time_to_timestamp(duration as i64, self.id)),
+        }
+    }
+
+    fn cluster<'b>(&'b self, cluster_index: i32)
+                   -> Result<Box<container::Cluster + 'b>,MediaError> {
+        if cluster_index != 0 {
+            return Err(MediaError::EndOfStream)
+        }
         Ok(Box::new(ClusterImpl {
             handle: self.handle,
         }) as Box<container::Cluster + 'a>)
     }
 
+    fn seek(&self, time: &Timestamp) -> Result<container::SeekPosition,MediaError> {
+        seek(self.handle, self.id, time)
+    }
+
     fn as_video_track<'b>(&'b self) -> Result<Box<container::VideoTrack + 'b>,()> {
         if self.handle.track_type(self.id) != ffi::MP4_VIDEO_TRACK_TYPE {
             return Err(())
@@ -512,15 +753,27 @@ impl<'a> container::Track for VideoTrackImpl<'a> {
         get_codec(self.handle, self.id)
     }
 
-    fn cluster<'b>(&'b self, cluster_index: i32) -> Result<Box<container::Cluster + 'b>,()> {
+    fn duration(&self) -> Option<Timestamp> {
+        match self.handle.track_duration(self.id) {
+            0 => None,
+            duration => Some(self.handle.time_to_timestamp(duration as i64, self.id)),
+        }
+    }
+
+    fn cluster<'b>(&'b self, cluster_index: i32)
+                   -> Result<Box<container::Cluster + 'b>,MediaError> {
         if cluster_index != 0 {
-            return Err(())
+            return Err(MediaError::EndOfStream)
         }
         Ok(Box::new(ClusterImpl {
             handle: self.handle,
         }) as Box<container::Cluster + 'a>)
     }
 
+    fn seek(&self, time: &Timestamp) -> Result<container::SeekPosition,MediaError> {
+        seek(self.handle, self.id, time)
+    }
+
     fn as_video_track<'b>(&'b self) -> Result<Box<container::VideoTrack + 'b>,()> {
         Ok(Box::new((*self).clone()) as Box<container::VideoTrack + 'b>)
     }
@@ -584,13 +837,27 @@ impl<'a> container::Track for AudioTrackImpl<'a> {
         get_codec(self.handle, self.id)
     }
 
-    fn cluster<'b>(&'b self, cluster_index: i32) -> Result<Box<container::Cluster + 'b>,()> {
-        assert!(cluster_index == 0);
+    fn duration(&self) -> Option<Timestamp> {
+        match self.handle.track_duration(self.id) {
+            0 => None,
+            duration => Some(self.handle.time_to_timestamp(duration as i64, self.id)),
+        }
+    }
+
+    fn cluster<'b>(&'b self, cluster_index: i32)
+                   -> Result<Box<container::Cluster + 'b>,MediaError> {
+        if cluster_index != 0 {
+            return Err(MediaError::EndOfStream)
+        }
         Ok(Box::new(ClusterImpl {
             handle: self.handle,
         }) as Box<container::Cluster + 'a>)
     }
 
+    fn seek(&self, time: &Timestamp) -> Result<container::SeekPosition,MediaError> {
+        seek(self.handle, self.id, time)
+    }
+
     fn as_video_track<'b>(&'b self) -> Result<Box<container::VideoTrack + 'b>,()> {
         Err(())
     }
@@ -629,11 +896,19 @@ pub struct ClusterImpl<'a> {
 
 impl<'a> container::Cluster for ClusterImpl<'a> {
     fn read_frame<'b>(&'b self, frame_index: i32, track_number: c_long)
-                      -> Result<Box<container::Frame + 'b>,()> {
-        let sample = try!(self.handle.read_sample(track_number as ffi::MP4TrackId,
-                                                  frame_index as u32 + 1));
+                      -> Result<Box<container::Frame + 'b>,MediaError> {
+        let track_id = track_number as ffi::MP4TrackId;
+        if frame_index < 0 || frame_index as u32 >= self.handle.number_of_samples(track_id) {
+            return Err(MediaError::EndOfStream)
+        }
+        let sample = match self.handle.read_sample(track_id, frame_index as u32 + 1) {
+            Ok(sample) => sample,
+            Err(_) if self.handle.is_starved() => return Err(MediaError::NeedMoreData),
+            Err(_) => return Err(MediaError::Malformed),
+        };
         Ok(Box::new(FrameImpl {
             track_id: track_number as ffi::MP4TrackId,
+            sample_id: frame_index as u32 + 1,
             sample: sample,
             handle: self.handle,
         }) as Box<container::Frame + 'b>)
@@ -644,6 +919,7 @@ pub struct FrameImpl<'a> {
     sample: Sample<'a>,
     handle: &'a Mp4FileHandle,
     track_id: ffi::MP4TrackId,
+    sample_id: ffi::MP4SampleId,
 }
 
 impl<'a> container::Frame for FrameImpl<'a> {
@@ -651,11 +927,17 @@ impl<'a> container::Frame for FrameImpl<'a> {
         self.sample.bytes.len() as c_long
     }
 
-    fn read(&self, buffer: &mut [u8]) -> Result<(),()> {
+    fn read(&self, buffer: &mut [u8]) -> Result<(),MediaError> {
         bytes::copy_memory(buffer, self.sample.bytes);
         Ok(())
     }
 
+    /// `mp4v2` only reads samples into buffers of its own, so the sample can be handed out as is,
+    /// but it can't be sliced out of an in-memory stream.
+    fn data<'b>(&'b self) -> Option<&'b [u8]> {
+        Some(self.sample.bytes)
+    }
+
     fn track_number(&self) -> c_long {
         self.track_id as c_long
     }
@@ -669,6 +951,21 @@ impl<'a> container::Frame for FrameImpl<'a> {
         // 32-bit integer. Work around this oversight.
         self.sample.rendering_offset as i32 as i64
     }
+
+    fn is_keyframe(&self) -> bool {
+        self.sample.is_sync_sample
+    }
+
+    fn duration(&self) -> Option<i64> {
+        Some(self.sample.duration as i64)
+    }
+
+    fn is_discardable(&self) -> bool {
+        match self.handle.sample_dependency_flags(self.track_id, self.sample_id) {
+            Ok(flags) => (flags & ffi::MP4_SDT_HAS_NO_DEPENDENTS) != 0,
+            Err(_) => false,
+        }
+    }
 }
 
 pub struct VideoHeadersH264Impl {
@@ -685,10 +982,36 @@ impl videodecoder::VideoHeaders for VideoHeadersH264Impl {
 	}
 }
 
+fn seek(handle: &Mp4FileHandle, id: ffi::MP4TrackId, time: &Timestamp)
+        -> Result<container::SeekPosition,MediaError> {
+    let ticks = cmp::max(time.rescale(handle.time_base(id)).ticks, 0) as ffi::MP4Timestamp;
+    let mut sample_id = handle.sample_id_from_time(id, ticks);
+    if sample_id == ffi::MP4_INVALID_SAMPLE_ID {
+        return Err(MediaError::EndOfStream)
+    }
+
+    // Walk back through the sync sample table to the preceding keyframe. (Asking mp4v2 for a sync
+    // sample directly finds the *next* one, not the previous one.)
+    while sample_id > 1 {
+        match handle.is_sync_sample(id, sample_id) {
+            Ok(true) => break,
+            Ok(false) => sample_id -= 1,
+            Err(_) => return Err(MediaError::Malformed),
+        }
+    }
+
+    Ok(container::SeekPosition {
+        cluster_index: 0,
+        frame_index: sample_id as i32 - 1,
+        time: handle.time_to_timestamp(handle.sample_time(id, sample_id) as i64, id),
+    })
+}
+
 fn get_codec(handle: &Mp4FileHandle, id: ffi::MP4TrackId) -> Option<Vec<u8>> {
-    static TABLE: [(&'static [u8], [u8; 4]); 3] = [
+    static TABLE: [(&'static [u8], [u8; 4]); 4] = [
         (b"avc1", [b'a', b'v', b'c', b' ']),
         (b"mp4v", [b'a', b'v', b'c', b' ']),
+        (b"vp09", [b'V', b'P', b'9', b'0']),
         (b"mp4a", [b'a', b'a', b'c', b' ']),
     ];
     for &(key, value) in TABLE.iter() {
@@ -709,6 +1032,16 @@ pub const CONTAINER_READER: container::RegisteredContainerReader =
             "video/mp4",
             "video/quicktime",
         ],
+        signatures: &[
+            container::Signature {
+                offset: 4,
+                pattern: b"ftyp",
+            },
+            container::Signature {
+                offset: 4,
+                pattern: b"moov",
+            },
+        ],
         read: ContainerReaderImpl::new,
     };
 
@@ -739,6 +1072,67 @@ pub mod ffi {
         pub getSize: extern "C" fn(handle: *mut c_void, nout: *mut i64) -> c_int,
     }
 
+    #[repr(C)]
+    pub struct MP4TagTrack {
+        pub index: u16,
+        pub total: u16,
+    }
+    #[repr(C)]
+    pub struct MP4TagDisk {
+        pub index: u16,
+        pub total: u16,
+    }
+    #[repr(C)]
+    pub struct MP4TagArtwork;
+
+    /// Only the leading fields of this structure are declared here. It must only be accessed
+    /// through pointers returned from `MP4TagsAlloc()`.
+    #[repr(C)]
+    pub struct MP4Tags {
+        pub __handle: *mut c_void,
+        pub name: *const c_char,
+        pub artist: *const c_char,
+        pub albumArtist: *const c_char,
+        pub album: *const c_char,
+        pub grouping: *const c_char,
+        pub composer: *const c_char,
+        pub comments: *const c_char,
+        pub genre: *const c_char,
+        pub genreType: *const u16,
+        pub releaseDate: *const c_char,
+        pub track: *const MP4TagTrack,
+        pub disk: *const MP4TagDisk,
+        pub tempo: *const u16,
+        pub compilation: *const u8,
+        pub tvShow: *const c_char,
+        pub tvNetwork: *const c_char,
+        pub tvEpisodeID: *const c_char,
+        pub tvSeason: *const u32,
+        pub tvEpisode: *const u32,
+        pub description: *const c_char,
+        pub longDescription: *const c_char,
+        pub lyrics: *const c_char,
+        pub sortName: *const c_char,
+        pub sortArtist: *const c_char,
+        pub sortAlbumArtist: *const c_char,
+        pub sortAlbum: *const c_char,
+        pub sortComposer: *const c_char,
+        pub sortTVShow: *const c_char,
+        pub artwork: *const MP4TagArtwork,
+        pub artworkCount: u32,
+        pub copyright: *const c_char,
+        pub encodingTool: *const c_char,
+        pub encodedBy: *const c_char,
+    }
+
+    pub const MP4V2_CHAPTER_TITLE_MAX: usize = 1023;
+
+    #[repr(C)]
+    pub struct MP4Chapter {
+        pub duration: MP4Duration,
+        pub title: [c_char; MP4V2_CHAPTER_TITLE_MAX + 1],
+    }
+
     pub type MP4FileHandle = *mut MP4FileHandleStruct;
     pub type MP4FileMode = c_int;
     pub type MP4TrackId = u32;
@@ -746,6 +1140,22 @@ pub mod ffi {
     pub type MP4Timestamp = u64;
     pub type MP4Duration = u64;
     pub type MP4EditId = u32;
+    pub type MP4ChapterType = c_int;
+
+    pub const MP4_INVALID_SAMPLE_ID: MP4SampleId = 0;
+
+    pub const MP4_SDT_HAS_REDUNDANT_CODING: u32 = 0x01;
+    pub const MP4_SDT_HAS_NO_REDUNDANT_CODING: u32 = 0x02;
+    pub const MP4_SDT_HAS_DEPENDENTS: u32 = 0x04;
+    pub const MP4_SDT_HAS_NO_DEPENDENTS: u32 = 0x08;
+    pub const MP4_SDT_IS_DEPENDENT: u32 = 0x10;
+    pub const MP4_SDT_IS_INDEPENDENT: u32 = 0x20;
+    pub const MP4_SDT_EARLIER_DISPLAY_TIMES_ALLOWED: u32 = 0x40;
+
+    pub const MP4_CHAPTER_TYPE_NONE: MP4ChapterType = 0;
+    pub const MP4_CHAPTER_TYPE_ANY: MP4ChapterType = 1;
+    pub const MP4_CHAPTER_TYPE_QT: MP4ChapterType = 2;
+    pub const MP4_CHAPTER_TYPE_NERO: MP4ChapterType = 4;
 
     pub const MP4_OD_TRACK_TYPE: &'static [u8] = b"odsm";
     pub const MP4_SCENE_TRACK_TYPE: &'static [u8] = b"sdsm";
@@ -775,6 +1185,36 @@ pub mod ffi {
                              pIsSyncSample: *mut bool)
                              -> bool;
 
+        pub fn MP4GetSampleIdFromTime(hFile: MP4FileHandle,
+                                      trackId: MP4TrackId,
+                                      when: MP4Timestamp,
+                                      wantSyncSample: bool)
+                                      -> MP4SampleId;
+        pub fn MP4GetSampleTime(hFile: MP4FileHandle, trackId: MP4TrackId, sampleId: MP4SampleId)
+                                -> MP4Timestamp;
+        pub fn MP4GetSampleSync(hFile: MP4FileHandle, trackId: MP4TrackId, sampleId: MP4SampleId)
+                                -> i8;
+
+        pub fn MP4GetSampleDependencyFlags(hFile: MP4FileHandle,
+                                           trackId: MP4TrackId,
+                                           sampleId: MP4SampleId,
+                                           dependencyFlags: *mut u32)
+                                           -> bool;
+
+        pub fn MP4TagsAlloc() -> *const MP4Tags;
+        pub fn MP4TagsFetch(tags: *const MP4Tags, hFile: MP4FileHandle) -> bool;
+        pub fn MP4TagsFree(tags: *const MP4Tags);
+
+        pub fn MP4GetChapters(hFile: MP4FileHandle,
+                              chapterList: *mut *mut MP4Chapter,
+                              chapterCount: *mut u32,
+                              fromChapterType: MP4ChapterType)
+                              -> MP4ChapterType;
+        pub fn MP4Free(p: *mut c_void);
+
+        pub fn MP4GetDuration(hFile: MP4FileHandle) -> MP4Duration;
+        pub fn MP4GetTimeScale(hFile: MP4FileHandle) -> u32;
+
         pub fn MP4GetNumberOfTracks(hFile: MP4FileHandle, trackType: *const c_char, subType: u8)
                                     -> u32;
         pub fn MP4FindTrackId(hFile: MP4FileHandle,
@@ -791,6 +1231,7 @@ pub mod ffi {
                                           -> MP4SampleId;
         pub fn MP4GetTrackBitRate(hFile: MP4FileHandle, trackId: MP4TrackId) -> u32;
         pub fn MP4GetTrackTimeScale(hFile: MP4FileHandle, trackId: MP4TrackId) -> u32;
+        pub fn MP4GetTrackDuration(hFile: MP4FileHandle, trackId: MP4TrackId) -> MP4Duration;
         pub fn MP4GetTrackVideoWidth(hFile: MP4FileHandle, trackId: MP4TrackId) -> u16;
         pub fn MP4GetTrackVideoHeight(hFile: MP4FileHandle, trackId: MP4TrackId) -> u16;
         pub fn MP4GetTrackVideoFrameRate(hFile: MP4FileHandle, trackId: MP4TrackId) -> c_double;
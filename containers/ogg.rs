@@ -7,14 +7,28 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-//! Basic support for Ogg, Vorbis audio only at present.
+//! Ogg support, Vorbis and Opus audio only at present.
 //!
 //! TODO(pcwalton): Support video and other codecs.
 
-use libc::{c_char, c_int, c_long};
+use audiodecoder;
+use codecs::opus::{self, OpusHead, OpusHeaders};
+use codecs::vorbis::{VorbisComment, VorbisHeaders, VorbisInfo};
+use container;
+use error::MediaError;
+use streaming::{self, StreamReader};
+use timing::{TimeBase, Timestamp};
+
+use libc::{c_char, c_double, c_int, c_long};
+use std::cell::{Cell, RefCell};
+use std::cmp;
 use std::i32;
+use std::io::{ErrorKind, Seek, SeekFrom};
 use std::mem;
+use std::rc::Rc;
+use std::slice::bytes;
 use std::slice;
+use std::u64;
 
 pub struct SyncState {
     state: ffi::ogg_sync_state,
@@ -61,7 +75,25 @@ impl SyncState {
             page = mem::uninitialized();
             ffi::ogg_sync_pageout(&mut self.state, &mut page)
         };
-        if result == 0 {
+        if result == 1 {
+            Ok(Page {
+                page: page,
+            })
+        } else {
+            Err(result)
+        }
+    }
+
+    /// Like `pageout()`, but reports skipped bytes so that the caller can keep track of where each
+    /// page is. Returns the next page, or else the number of bytes skipped as a negative number, or
+    /// zero if more data is needed.
+    pub fn pageseek(&mut self) -> Result<Page,c_long> {
+        let mut page;
+        let result = unsafe {
+            page = mem::uninitialized();
+            ffi::ogg_sync_pageseek(&mut self.state, &mut page)
+        };
+        if result > 0 {
             Ok(Page {
                 page: page,
             })
@@ -71,6 +103,8 @@ impl SyncState {
     }
 }
 
+/// A page. This points into the buffer of the `SyncState` it came from, so it's only valid until
+/// more data is written to that.
 pub struct Page {
     page: ffi::ogg_page,
 }
@@ -87,6 +121,25 @@ impl Page {
             ffi::ogg_page_eos(&self.page) != 0
         }
     }
+
+    pub fn bos(&self) -> bool {
+        unsafe {
+            ffi::ogg_page_bos(&self.page) != 0
+        }
+    }
+
+    /// Returns the granule position at the end of the last packet that ends on this page, or -1
+    /// if none does.
+    pub fn granulepos(&self) -> i64 {
+        unsafe {
+            ffi::ogg_page_granulepos(&self.page)
+        }
+    }
+
+    /// Returns the size of this page in bytes, including the header.
+    pub fn len(&self) -> u64 {
+        (self.page.header_len + self.page.body_len) as u64
+    }
 }
 
 pub struct StreamState {
@@ -113,22 +166,39 @@ impl StreamState {
         }
     }
 
-    pub fn pagein(&mut self, page: &mut Page) {
-        unsafe {
-            assert!(ffi::ogg_stream_pagein(&mut self.state, &mut page.page) == 0)
+    pub fn pagein(&mut self, page: &mut Page) -> Result<(),c_int> {
+        match unsafe {
+            ffi::ogg_stream_pagein(&mut self.state, &mut page.page)
+        } {
+            0 => Ok(()),
+            err => Err(err),
         }
     }
 
-    pub fn packetout(&mut self) -> Packet {
+    /// Returns the next complete packet, or `None` if the rest of it is on a later page. Returns
+    /// an error once if data was lost before the next packet.
+    pub fn packetout<'a>(&'a mut self) -> Result<Option<Packet<'a>>,c_int> {
         let mut packet;
-        unsafe {
+        let result = unsafe {
             packet = mem::uninitialized();
-            assert!(ffi::ogg_stream_packetout(&mut self.state, &mut packet) == 0)
-        }
-        Packet {
-            packet: packet,
+            ffi::ogg_stream_packetout(&mut self.state, &mut packet)
+        };
+        match result {
+            1 => {
+                Ok(Some(Packet {
+                    packet: packet,
+                }))
+            }
+            0 => Ok(None),
+            err => Err(err),
         }
     }
+
+    /// Returns true if the pages submitted so far end partway through a packet. Call this after
+    /// taking out all the complete packets.
+    pub fn has_partial_packet(&self) -> bool {
+        self.state.lacing_fill > self.state.lacing_returned
+    }
 }
 
 pub struct Packet<'a> {
@@ -156,11 +226,1178 @@ impl<'a> Packet<'a> {
         }
     }
 
+    pub fn data(&self) -> &'a [u8] {
+        unsafe {
+            slice::from_raw_buf(&(self.packet.packet as *const u8), self.packet.bytes as usize)
+        }
+    }
+
     pub fn raw_packet<'b>(&'b mut self) -> &'b mut ffi::ogg_packet {
         &mut self.packet
     }
 }
 
+// Implementation of the abstract `ContainerReader` interface
+
+/// The number of bytes of the stream that each cluster covers. Ogg has no index, so clusters are
+/// simply fixed-size spans of the stream. A packet belongs to the cluster in which its first page
+/// begins.
+const CLUSTER_SIZE: u64 = 64 * 1024;
+
+/// The number of bytes to hand to `libogg` at a time.
+const READ_SIZE: c_long = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Codec {
+    Vorbis,
+    Opus,
+}
+
+impl Codec {
+    /// Identifies the codec of a logical bitstream from its first packet.
+    fn identify(packet: &[u8]) -> Option<Codec> {
+        if packet.starts_with(b"\x01vorbis") {
+            Some(Codec::Vorbis)
+        } else if packet.starts_with(b"OpusHead") {
+            Some(Codec::Opus)
+        } else {
+            None
+        }
+    }
+
+    fn header_count(&self) -> usize {
+        match *self {
+            Codec::Vorbis => 3,
+            Codec::Opus => 2,
+        }
+    }
+
+    /// Returns true if `packet` is a header rather than media data.
+    fn is_header(&self, packet: &[u8]) -> bool {
+        match *self {
+            // Header packet types are odd; audio packets start with a zero bit.
+            Codec::Vorbis => !packet.is_empty() && (packet[0] & 1) != 0,
+            Codec::Opus => packet.starts_with(b"OpusHead") || packet.starts_with(b"OpusTags"),
+        }
+    }
+
+    fn fourcc(&self) -> Vec<u8> {
+        match *self {
+            Codec::Vorbis => vec![b'v', b'o', b'r', b'b'],
+            Codec::Opus => vec![b'o', b'p', b'u', b's'],
+        }
+    }
+}
+
+/// A logical bitstream in a codec we support.
+struct OggTrack {
+    serial: c_int,
+    codec: Codec,
+    /// The header packets, in order.
+    headers: Vec<Vec<u8>>,
+    sampling_rate: u32,
+    channels: u16,
+    /// The parsed Vorbis headers, needed to find the block size of each packet. This is `None`
+    /// for other codecs.
+    vorbis_info: Option<RefCell<VorbisInfo>>,
+    /// The number of samples at the start of the stream that aren't part of the output.
+    pre_skip: i64,
+}
+
+impl OggTrack {
+    fn new(serial: c_int, codec: Codec, headers: Vec<Vec<u8>>) -> Result<OggTrack,MediaError> {
+        let (sampling_rate, channels, vorbis_info, pre_skip) = match codec {
+            Codec::Vorbis => {
+                let mut info = VorbisInfo::new();
+                let mut comment = VorbisComment::new();
+                for (packet_index, header) in headers.iter().enumerate() {
+                    let mut packet = Packet::new(header.as_slice(), packet_index as i64);
+                    if info.header_in(&mut comment, &mut packet).is_err() {
+                        return Err(MediaError::Malformed)
+                    }
+                }
+                if info.rate() <= 0 || info.channels() <= 0 {
+                    return Err(MediaError::Malformed)
+                }
+                (info.rate() as u32, info.channels() as u16, Some(RefCell::new(info)), 0)
+            }
+            Codec::Opus => {
+                // Opus granule positions always count samples at 48 kHz.
+                let head = try!(OpusHead::parse(headers[0].as_slice()));
+                (opus::SAMPLE_RATE, head.channels as u16, None, head.pre_skip as i64)
+            }
+        };
+        Ok(OggTrack {
+            serial: serial,
+            codec: codec,
+            headers: headers,
+            sampling_rate: sampling_rate,
+            channels: channels,
+            vorbis_info: vorbis_info,
+            pre_skip: pre_skip,
+        })
+    }
+
+    /// Granule positions count samples, so they're in units of the sampling period.
+    fn time_base(&self) -> TimeBase {
+        TimeBase::new(1, self.sampling_rate)
+    }
+
+    /// Converts a granule position to a time. The meaning of granule positions is up to the codec;
+    /// for Vorbis and Opus, it's the number of samples so far, including any pre-skip.
+    ///
+    /// TODO: Video codecs such as Theora pack the number of the last keyframe into the upper bits
+    /// ("keyframe shift"), which would have to be split out here.
+    fn granule_time(&self, granule_position: i64) -> Timestamp {
+        match self.codec {
+            Codec::Vorbis | Codec::Opus => {
+                Timestamp::new(granule_position - self.pre_skip, self.time_base())
+            }
+        }
+    }
+
+    /// The inverse of `granule_time()`.
+    fn granule_position(&self, time: &Timestamp) -> i64 {
+        match self.codec {
+            Codec::Vorbis | Codec::Opus => time.rescale(self.time_base()).ticks + self.pre_skip,
+        }
+    }
+
+    fn audio_headers(&self) -> Box<audiodecoder::AudioHeaders> {
+        match self.codec {
+            Codec::Vorbis => Box::new(self.vorbis_headers()) as Box<audiodecoder::AudioHeaders>,
+            Codec::Opus => {
+                Box::new(OpusHeaders {
+                    id: self.headers[0].clone(),
+                }) as Box<audiodecoder::AudioHeaders>
+            }
+        }
+    }
+
+    fn vorbis_headers(&self) -> VorbisHeaders {
+        let mut data = Vec::new();
+        for header in self.headers.iter() {
+            data.push_all(header.as_slice())
+        }
+        VorbisHeaders {
+            data: data,
+            id_size: self.headers[0].len(),
+            comment_size: self.headers[1].len(),
+        }
+    }
+
+    /// Returns the tags in the comment header, if it can be parsed.
+    fn tags(&self) -> Option<Vec<container::MetadataTag>> {
+        match self.codec {
+            Codec::Vorbis => self.vorbis_headers().tags().ok(),
+            Codec::Opus => opus::comment_tags(self.headers[1].as_slice()),
+        }
+    }
+
+    /// Returns the number of samples that an audio packet produces, and updates `stream` with its
+    /// block size.
+    fn packet_duration(&self, stream: &mut StreamProgress, packet: &[u8]) -> i64 {
+        let vorbis_info = match self.vorbis_info {
+            Some(ref vorbis_info) => vorbis_info,
+            None => {
+                // Opus packets say how long they are in their first byte.
+                return opus::packet_sample_count(packet).unwrap_or(0) as i64
+            }
+        };
+        let mut vorbis_packet = Packet::new(packet, 3);
+        let block_size = match vorbis_info.borrow_mut().packet_blocksize(&mut vorbis_packet) {
+            Ok(block_size) => block_size,
+            Err(_) => return 0,
+        };
+
+        // Each block overlaps half of the one before it, and the first produces no samples at
+        // all. If we started partway through the stream, assume the previous block was the same
+        // size as this one.
+        let duration = if stream.after_headers {
+            0
+        } else {
+            match stream.previous_block_size {
+                Some(previous_block_size) => (previous_block_size + block_size) / 4,
+                None => block_size / 2,
+            }
+        };
+        stream.previous_block_size = Some(block_size);
+        stream.after_headers = false;
+        duration as i64
+    }
+}
+
+/// A packet read out of a cluster.
+struct OggFrame {
+    /// The number of the track, which is the serial number of its stream in the first link.
+    track_number: c_long,
+    track_index: usize,
+    /// The link that the packet belongs to.
+    link_index: usize,
+    data: Vec<u8>,
+    time: Timestamp,
+    /// The number of samples in this packet.
+    duration: i64,
+    /// True if this is the track's first packet in a new link.
+    discontinuity: bool,
+}
+
+/// The state of one logical bitstream while a cluster is being read.
+struct StreamProgress {
+    state: StreamState,
+    /// The block size of the last audio packet, if known.
+    previous_block_size: Option<c_long>,
+    /// True if the last packet was a header, so the next audio packet produces no samples.
+    after_headers: bool,
+    /// The granule position at the end of the last packet, if known.
+    granule_position: Option<i64>,
+    /// True once all of this stream's packets in the cluster have been read.
+    finished: bool,
+    /// True if the next packet is the first of a new link.
+    discontinuity: bool,
+}
+
+impl StreamProgress {
+    fn new(serial: c_int, discontinuity: bool) -> StreamProgress {
+        StreamProgress {
+            state: StreamState::new(serial),
+            previous_block_size: None,
+            after_headers: false,
+            granule_position: None,
+            finished: false,
+            discontinuity: discontinuity,
+        }
+    }
+
+    fn for_link(link: &Link, discontinuity: bool) -> Vec<StreamProgress> {
+        link.tracks.iter().map(|track| StreamProgress::new(track.serial, discontinuity)).collect()
+    }
+}
+
+/// Reads pages one by one from some position in the stream, keeping track of where each one is.
+struct PageReader<'a> {
+    reader: &'a mut StreamReader,
+    sync: SyncState,
+    /// The position in the stream of the next byte that `sync` will return.
+    position: u64,
+}
+
+impl<'a> PageReader<'a> {
+    fn new(reader: &'a mut StreamReader, position: u64) -> Result<PageReader<'a>,MediaError> {
+        if reader.seek(SeekFrom::Start(position)).is_err() {
+            return Err(stream_error(reader))
+        }
+        Ok(PageReader {
+            reader: reader,
+            sync: SyncState::new(),
+            position: position,
+        })
+    }
+
+    /// Returns the next page and its position in the stream, or `None` at the end of the stream.
+    fn next_page(&mut self) -> Result<Option<(u64, Page)>,MediaError> {
+        loop {
+            match self.sync.pageseek() {
+                Ok(page) => {
+                    let position = self.position;
+                    self.position += page.len();
+                    return Ok(Some((position, page)))
+                }
+                Err(0) => {}
+                Err(skipped) => {
+                    self.position += (-skipped) as u64;
+                    continue
+                }
+            }
+
+            let result = {
+                let buffer = self.sync.buffer(READ_SIZE);
+                self.reader.read(buffer)
+            };
+            match result {
+                Ok(0) => return Ok(None),
+                Ok(length) => self.sync.wrote(length as c_long),
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return Err(stream_error(&*self.reader)),
+            }
+        }
+    }
+}
+
+/// Returns the error to report when reading from the stream fails.
+fn stream_error(reader: &StreamReader) -> MediaError {
+    if streaming::is_waiting_for_data(reader) {
+        MediaError::NeedMoreData
+    } else {
+        MediaError::Malformed
+    }
+}
+
+/// One link of a chained stream: a group of logical bitstreams that begin together. Most files
+/// have only one, but internet radio recordings and concatenated files start a new link, with new
+/// serial numbers and headers, at every boundary.
+struct Link {
+    /// The position in the stream of the link's first page.
+    position: u64,
+    /// The position in the stream just past the link's headers, where its packets begin.
+    data_position: u64,
+    /// The serial numbers of all of the link's streams, including those we can't decode.
+    serials: Vec<c_int>,
+    /// The streams we can decode. Each one continues the track at the same index in the first
+    /// link.
+    tracks: Vec<OggTrack>,
+    /// The time at which each track begins, in the time base of the same track in the first link.
+    start_ticks: Vec<i64>,
+}
+
+impl Link {
+    /// Converts a time relative to the start of this link to one relative to the start of the
+    /// whole stream.
+    fn absolute_time(&self, first_link: &Link, track_index: usize, time: &Timestamp)
+                     -> Timestamp {
+        let time_base = first_link.tracks[track_index].time_base();
+        time.rescale(time_base) + self.start_ticks[track_index]
+    }
+
+    /// The inverse of `absolute_time()`.
+    fn relative_time(&self, first_link: &Link, track_index: usize, time: &Timestamp)
+                     -> Timestamp {
+        let time_base = first_link.tracks[track_index].time_base();
+        time.rescale(time_base) - self.start_ticks[track_index]
+    }
+}
+
+/// Returns the index of the last link that begins at or before `position`.
+fn link_at(links: &[Link], position: u64) -> usize {
+    links.iter().rposition(|link| link.position <= position).unwrap()
+}
+
+/// Adds a newly found link to the list, keeping it sorted by position, and returns its index.
+fn insert_link(links: &mut Vec<Link>, link: Link) -> usize {
+    let link_index = match links.iter().position(|other| other.position > link.position) {
+        Some(link_index) => link_index,
+        None => links.len(),
+    };
+    links.insert(link_index, link);
+    link_index
+}
+
+/// A stream whose headers are still being read.
+struct PendingStream {
+    state: StreamState,
+    serial: c_int,
+    codec: Option<Codec>,
+    headers: Vec<Vec<u8>>,
+}
+
+impl PendingStream {
+    fn is_complete(&self) -> bool {
+        match self.codec {
+            Some(codec) => self.headers.len() >= codec.header_count(),
+            None => !self.headers.is_empty(),
+        }
+    }
+}
+
+/// Reads the beginning-of-stream pages of a link and the headers that follow them.
+struct LinkParser {
+    streams: Vec<PendingStream>,
+    /// All beginning-of-stream pages come first, so once we see another page, we know about every
+    /// stream.
+    seen_all_streams: bool,
+}
+
+impl LinkParser {
+    fn new() -> LinkParser {
+        LinkParser {
+            streams: Vec::new(),
+            seen_all_streams: false,
+        }
+    }
+
+    fn has_all_headers(&self) -> bool {
+        self.streams.iter().all(|stream| stream.is_complete())
+    }
+
+    /// Feeds the next page of the link to the parser. Returns true once every header of every
+    /// stream has been read.
+    fn submit(&mut self, page: &mut Page) -> Result<bool,MediaError> {
+        let serial = page.serialno();
+        if !page.bos() {
+            self.seen_all_streams = true
+        }
+        let existing_stream_index = self.streams.iter().position(|stream| stream.serial == serial);
+        let stream_index = match existing_stream_index {
+            Some(stream_index) => stream_index,
+            None if page.bos() && !self.seen_all_streams => {
+                self.streams.push(PendingStream {
+                    state: StreamState::new(serial),
+                    serial: serial,
+                    codec: None,
+                    headers: Vec::new(),
+                });
+                self.streams.len() - 1
+            }
+            None => return Ok(self.seen_all_streams && self.has_all_headers()),
+        };
+
+        {
+            let stream = &mut self.streams[stream_index];
+            if !stream.is_complete() {
+                if stream.state.pagein(page).is_err() {
+                    return Err(MediaError::Malformed)
+                }
+                loop {
+                    let header = match stream.state.packetout() {
+                        Ok(Some(packet)) => packet.data().to_vec(),
+                        Ok(None) => break,
+                        Err(_) => return Err(MediaError::Malformed),
+                    };
+                    if stream.headers.is_empty() {
+                        stream.codec = Codec::identify(header.as_slice())
+                    }
+                    if stream.is_complete() {
+                        break
+                    }
+                    stream.headers.push(header)
+                }
+            }
+        }
+        Ok(self.seen_all_streams && self.has_all_headers())
+    }
+
+    /// Returns the link described by the headers read so far. Streams in codecs we don't support
+    /// are left out of its tracks.
+    fn into_link(self, position: u64, data_position: u64) -> Result<Link,MediaError> {
+        let serials = self.streams.iter().map(|stream| stream.serial).collect();
+        let mut tracks = Vec::new();
+        for stream in self.streams.into_iter() {
+            let codec = match stream.codec {
+                Some(codec) => codec,
+                None => continue,
+            };
+            tracks.push(try!(OggTrack::new(stream.serial, codec, stream.headers)))
+        }
+        let start_ticks = tracks.iter().map(|_| 0).collect();
+        Ok(Link {
+            position: position,
+            data_position: data_position,
+            serials: serials,
+            tracks: tracks,
+            start_ticks: start_ticks,
+        })
+    }
+}
+
+/// Reads the headers at the start of the stream and returns the first link.
+fn read_first_link(reader: &mut StreamReader) -> Result<Link,MediaError> {
+    let mut parser = LinkParser::new();
+    let mut data_position = 0;
+    {
+        let mut pages = try!(PageReader::new(reader, 0));
+        while let Some((position, mut page)) = try!(pages.next_page()) {
+            if try!(parser.submit(&mut page)) {
+                data_position = position + page.len();
+                break
+            }
+        }
+    }
+    if !parser.has_all_headers() {
+        return Err(stream_error(reader))
+    }
+    let link = try!(parser.into_link(0, data_position));
+    if link.tracks.is_empty() {
+        return Err(MediaError::UnsupportedCodec)
+    }
+    Ok(link)
+}
+
+/// Returns the granule position of the first page of the given stream that begins at or after
+/// `position` and ends a packet, or `None` if the stream or its link ends first. `serials` are
+/// the serial numbers of the streams in the link.
+fn next_granule_position(reader: &mut StreamReader,
+                         serial: c_int,
+                         serials: &[c_int],
+                         position: u64)
+                         -> Result<Option<i64>,MediaError> {
+    let mut pages = try!(PageReader::new(reader, position));
+    loop {
+        match try!(pages.next_page()) {
+            Some((_, ref page)) if page.serialno() == serial && page.granulepos() >= 0 => {
+                return Ok(Some(page.granulepos()))
+            }
+            Some((_, ref page)) if page.bos() && !serials.contains(&page.serialno()) => {
+                return Ok(None)
+            }
+            Some(_) => {}
+            None => return Ok(None),
+        }
+    }
+}
+
+/// Returns the granule position of the last page of each track before `end`, by reading backward
+/// until every track has been found. Returns `None` for tracks with no such page.
+fn last_granule_positions(reader: &mut StreamReader, tracks: &[OggTrack], end: u64)
+                          -> Result<Vec<Option<i64>>,MediaError> {
+    let mut granule_positions: Vec<Option<i64>> = tracks.iter().map(|_| None).collect();
+    let mut window_size = CLUSTER_SIZE;
+    loop {
+        let start = if end > window_size {
+            end - window_size
+        } else {
+            0
+        };
+        let mut found_track_pages = false;
+        {
+            let mut pages = try!(PageReader::new(reader, start));
+            while let Some((position, page)) = try!(pages.next_page()) {
+                if position >= end {
+                    break
+                }
+                let serial = page.serialno();
+                let track_index = match tracks.iter().position(|track| track.serial == serial) {
+                    Some(track_index) => track_index,
+                    None => continue,
+                };
+                found_track_pages = true;
+                if page.granulepos() >= 0 {
+                    granule_positions[track_index] = Some(page.granulepos())
+                }
+            }
+        }
+
+        // If none of the pages belong to these tracks, we're looking at a later link, which
+        // hasn't been found yet.
+        if start == 0 || !found_track_pages ||
+                granule_positions.iter().all(|position| position.is_some()) {
+            return Ok(granule_positions)
+        }
+        window_size *= 2
+    }
+}
+
+struct ContainerReaderImpl {
+    reader: RefCell<Box<StreamReader>>,
+    /// The links found so far, in order of position. The first is read when the stream is
+    /// opened; the rest are found as playback reaches them.
+    links: RefCell<Vec<Link>>,
+    /// The link that the most recently read frame belongs to, which the tracks describe.
+    current_link: Cell<usize>,
+    /// The most recently read cluster, since playback asks for the same one repeatedly.
+    cached_cluster: RefCell<Option<(i32, Rc<Vec<OggFrame>>)>>,
+    /// The number of links known and the granule position of the last page of each track in the
+    /// last of them, once read.
+    last_granule_positions: RefCell<Option<(usize, Vec<Option<i64>>)>>,
+}
+
+impl ContainerReaderImpl {
+    fn new(mut reader: Box<StreamReader>)
+           -> Result<Box<container::ContainerReader + 'static>,MediaError> {
+        let link = try!(read_first_link(&mut *reader));
+        Ok(Box::new(ContainerReaderImpl {
+            reader: RefCell::new(reader),
+            links: RefCell::new(vec![link]),
+            current_link: Cell::new(0),
+            cached_cluster: RefCell::new(None),
+            last_granule_positions: RefCell::new(None),
+        }) as Box<container::ContainerReader + 'static>)
+    }
+
+    fn track_count(&self) -> usize {
+        self.links.borrow()[0].tracks.len()
+    }
+
+    /// Returns the result of `f` applied to the given track as the current link describes it, or
+    /// as the first link does if the current one doesn't have that track.
+    fn current_track<T,F>(&self, track_index: usize, f: F) -> T where F: Fn(&OggTrack) -> T {
+        let links = self.links.borrow();
+        match links[self.current_link.get()].tracks.get(track_index) {
+            Some(track) => f(track),
+            None => f(&links[0].tracks[track_index]),
+        }
+    }
+
+    fn cluster(&self, cluster_index: i32) -> Result<Rc<Vec<OggFrame>>,MediaError> {
+        if let Some((cached_index, ref frames)) = *self.cached_cluster.borrow() {
+            if cached_index == cluster_index {
+                return Ok(frames.clone())
+            }
+        }
+        let frames = Rc::new(try!(self.read_cluster(cluster_index)));
+        *self.cached_cluster.borrow_mut() = Some((cluster_index, frames.clone()));
+        Ok(frames)
+    }
+
+    /// Reads the packets that belong to the given cluster, finding any links that begin in it.
+    fn read_cluster(&self, cluster_index: i32) -> Result<Vec<OggFrame>,MediaError> {
+        if cluster_index < 0 {
+            return Err(MediaError::EndOfStream)
+        }
+        let start = cluster_index as u64 * CLUSTER_SIZE;
+        let end = start + CLUSTER_SIZE;
+        let mut reader = self.reader.borrow_mut();
+        if start >= reader.total_size() {
+            return Err(MediaError::EndOfStream)
+        }
+
+        let mut links = self.links.borrow_mut();
+        let mut link_index = link_at(links.as_slice(), start);
+        let mut new_link_positions = Vec::new();
+        let mut frames = Vec::new();
+        {
+            let mut pages = try!(PageReader::new(&mut **reader, start));
+
+            // If the headers of the link ran past the end of the cluster in which it begins, its
+            // first packets are in this one.
+            let mut streams = {
+                let link = &links[link_index];
+                let after_headers = link.position < start && link.data_position >= start;
+                let mut streams = StreamProgress::for_link(link, after_headers);
+                for stream in streams.iter_mut() {
+                    stream.after_headers = after_headers
+                }
+                streams
+            };
+            let mut link_parser: Option<(u64, LinkParser)> = None;
+            loop {
+                // Past the end of the cluster, we only need to finish the packets begun inside
+                // it, and the headers of a link begun inside it.
+                let (position, mut page) = match try!(pages.next_page()) {
+                    Some(page) => page,
+                    None => break,
+                };
+                if position >= end && link_parser.is_none() && !streams.iter().any(|stream| {
+                    !stream.finished && stream.state.has_partial_packet()
+                }) {
+                    break
+                }
+
+                // A beginning-of-stream page of a stream from outside this link starts the next
+                // one.
+                if link_parser.is_none() && page.bos() &&
+                        !links[link_index].serials.contains(&page.serialno()) {
+                    if position >= end {
+                        break
+                    }
+                    match links.iter().position(|link| link.position == position) {
+                        Some(next_link_index) => {
+                            link_index = next_link_index;
+                            streams = StreamProgress::for_link(&links[link_index], true);
+                        }
+                        None => link_parser = Some((position, LinkParser::new())),
+                    }
+                }
+
+                // Read the headers of a new link before going on to its packets.
+                if link_parser.is_some() {
+                    if !try!(link_parser.as_mut().unwrap().1.submit(&mut page)) {
+                        continue
+                    }
+                    let (link_position, parser) = link_parser.take().unwrap();
+                    let link = try!(parser.into_link(link_position, position + page.len()));
+                    link_index = insert_link(&mut *links, link);
+                    new_link_positions.push(link_position);
+                    streams = StreamProgress::for_link(&links[link_index], true);
+                    for stream in streams.iter_mut() {
+                        stream.after_headers = true
+                    }
+                    continue
+                }
+
+                let serial = page.serialno();
+                let track_index = match links[link_index].tracks.iter().position(|track| {
+                    track.serial == serial
+                }) {
+                    Some(track_index) if track_index < links[0].tracks.len() => track_index,
+                    _ => continue,
+                };
+                let track = &links[link_index].tracks[track_index];
+                let stream = &mut streams[track_index];
+                if stream.finished {
+                    continue
+                }
+                let packet_limit = if position >= end {
+                    stream.finished = true;
+                    if !stream.state.has_partial_packet() {
+                        continue
+                    }
+                    1
+                } else {
+                    i32::MAX as usize
+                };
+
+                // Packets that began before the cluster are dropped here, since the stream state
+                // has never seen their beginnings.
+                if stream.state.pagein(&mut page).is_err() {
+                    return Err(MediaError::Malformed)
+                }
+                let mut packets = Vec::new();
+                loop {
+                    match stream.state.packetout() {
+                        Ok(Some(packet)) => packets.push(packet.data().to_vec()),
+                        Ok(None) => break,
+                        Err(_) => {}
+                    }
+                }
+
+                // Work out the duration of each packet. The granule position marks the end of
+                // the last one, so count backward from there to find when each begins.
+                let mut durations = Vec::new();
+                for packet in packets.iter() {
+                    if track.codec.is_header(packet.as_slice()) {
+                        stream.previous_block_size = None;
+                        stream.after_headers = true;
+                        durations.push(None)
+                    } else {
+                        durations.push(Some(track.packet_duration(stream, packet.as_slice())))
+                    }
+                }
+                let total_duration = durations.iter().fold(0, |sum, duration| {
+                    sum + duration.unwrap_or(0)
+                });
+                let mut time = match (page.granulepos(), stream.granule_position) {
+                    (granule_position, _) if granule_position >= 0 => {
+                        granule_position - total_duration
+                    }
+                    (_, Some(granule_position)) => granule_position,
+                    (_, None) => 0,
+                };
+
+                let packets = packets.into_iter().zip(durations.into_iter()).take(packet_limit);
+                for (packet, duration) in packets {
+                    let duration = match duration {
+                        Some(duration) => duration,
+                        None => continue,
+                    };
+                    frames.push(OggFrame {
+                        track_number: links[0].tracks[track_index].serial as c_long,
+                        track_index: track_index,
+                        link_index: link_index,
+                        data: packet,
+                        time: track.granule_time(time),
+                        duration: duration,
+                        discontinuity: mem::replace(&mut stream.discontinuity, false),
+                    });
+                    time += duration
+                }
+                stream.granule_position = Some(time);
+            }
+        }
+
+        // Each new link begins where the one before it ends.
+        new_link_positions.sort();
+        for link_position in new_link_positions.iter() {
+            let link_index = links.iter().position(|link| link.position == *link_position).unwrap();
+            let start_ticks = {
+                let (previous_link, link) = (&links[link_index - 1], &links[link_index]);
+                let granule_positions = try!(last_granule_positions(&mut **reader,
+                                                                    previous_link.tracks
+                                                                                 .as_slice(),
+                                                                    link.position));
+                let mut start_ticks = Vec::new();
+                for track_index in range(0, link.tracks.len()) {
+                    if track_index >= links[0].tracks.len() ||
+                            track_index >= previous_link.tracks.len() {
+                        start_ticks.push(0);
+                        continue
+                    }
+                    let previous_track = &previous_link.tracks[track_index];
+                    let granule_position = granule_positions[track_index].unwrap_or(0);
+                    let end_time = previous_track.granule_time(granule_position);
+                    start_ticks.push(previous_link.absolute_time(&links[0],
+                                                                 track_index,
+                                                                 &end_time).ticks)
+                }
+                start_ticks
+            };
+            links[link_index].start_ticks = start_ticks
+        }
+
+        // Times so far are relative to the start of each packet's link, and in its time base.
+        for frame in frames.iter_mut() {
+            let link = &links[frame.link_index];
+            let time_base = links[0].tracks[frame.track_index].time_base();
+            let duration = Timestamp::new(frame.duration, frame.time.time_base);
+            frame.duration = duration.rescale(time_base).ticks;
+            frame.time = link.absolute_time(&links[0], frame.track_index, &frame.time);
+        }
+        Ok(frames)
+    }
+
+    fn cluster_count(&self) -> Option<c_int> {
+        let total_size = self.reader.borrow().total_size();
+        if total_size == u64::MAX {
+            return None
+        }
+        Some(((total_size + CLUSTER_SIZE - 1) / CLUSTER_SIZE) as c_int)
+    }
+
+    /// Returns an estimate of the running time of the track, from the granule position of its
+    /// last page. This has to read the end of the stream, so it returns `None` if that hasn't
+    /// arrived yet, or if the end is in a link that playback hasn't reached.
+    fn track_duration(&self, track_index: usize) -> Option<Timestamp> {
+        let link_count = self.links.borrow().len();
+        let up_to_date = match *self.last_granule_positions.borrow() {
+            Some((cached_link_count, _)) => cached_link_count == link_count,
+            None => false,
+        };
+        if !up_to_date {
+            let links = self.links.borrow();
+            let mut reader = self.reader.borrow_mut();
+            let total_size = reader.total_size();
+            if total_size == u64::MAX {
+                return None
+            }
+            match last_granule_positions(&mut **reader,
+                                         links[link_count - 1].tracks.as_slice(),
+                                         total_size) {
+                Ok(positions) => {
+                    *self.last_granule_positions.borrow_mut() = Some((link_count, positions))
+                }
+                Err(_) => return None,
+            }
+        }
+
+        let links = self.links.borrow();
+        let link = &links[link_count - 1];
+        let granule_position = match self.last_granule_positions
+                                         .borrow()
+                                         .as_ref()
+                                         .unwrap()
+                                         .1
+                                         .get(track_index) {
+            Some(&Some(granule_position)) => granule_position,
+            _ => return None,
+        };
+        let end_time = link.tracks[track_index].granule_time(granule_position);
+        Some(link.absolute_time(&links[0], track_index, &end_time))
+    }
+
+    /// Finds the last packet of the track that begins at or before `time`, and makes its link the
+    /// current one.
+    fn seek(&self, track_index: usize, time: &Timestamp)
+            -> Result<container::SeekPosition,MediaError> {
+        let (position, link_index) = try!(self.find_seek_position(track_index, time));
+        self.current_link.set(link_index);
+        Ok(position)
+    }
+
+    /// Finds the last packet of the track that begins at or before `time`, and returns it along
+    /// with the index of its link. Every audio packet can be decoded on its own, so this is where
+    /// playback restarts.
+    ///
+    /// Ogg has no index, so this bisects the clusters of the link containing `time`, using the
+    /// granule position of the first page of the track in each to tell which side of `time` it's
+    /// on.
+    ///
+    /// FIXME: Links are only found as playback reaches them, so seeking past the last one found
+    /// lands at its end.
+    fn find_seek_position(&self, track_index: usize, time: &Timestamp)
+                          -> Result<(container::SeekPosition, usize),MediaError> {
+        let cluster_count = match self.cluster_count() {
+            Some(cluster_count) if cluster_count > 0 => cluster_count,
+            _ => return self.seek_linearly(track_index, time),
+        };
+
+        // Find the last cluster whose first page ends at or before the target. Every packet in
+        // the clusters after it begins after the target.
+        let (mut low, mut high, serial, serials, target) = {
+            let links = self.links.borrow();
+            let base_time = time.rescale(links[0].tracks[track_index].time_base());
+            let link_index = links.iter().rposition(|link| {
+                track_index < link.tracks.len() && link.start_ticks[track_index] <= base_time.ticks
+            }).unwrap_or(0);
+            let link = &links[link_index];
+            let track = &link.tracks[track_index];
+            let target = track.granule_position(&link.relative_time(&links[0], track_index, time));
+            let low = (link.position / CLUSTER_SIZE) as c_int;
+            let high = match links.get(link_index + 1) {
+                Some(next_link) => cmp::min((next_link.position / CLUSTER_SIZE) as c_int,
+                                            cluster_count - 1),
+                None => cluster_count - 1,
+            };
+            (low, high, track.serial, link.serials.clone(), target)
+        };
+        while low < high {
+            let middle = low + (high - low + 1) / 2;
+            let granule_position = try!(next_granule_position(&mut **self.reader.borrow_mut(),
+                                                              serial,
+                                                              serials.as_slice(),
+                                                              middle as u64 * CLUSTER_SIZE));
+            match granule_position {
+                Some(granule_position) if granule_position <= target => low = middle,
+                _ => high = middle - 1,
+            }
+        }
+
+        // The packet we want is usually in that cluster, but it can be in an earlier one if the
+        // first packets of this cluster begin after the target.
+        let mut cluster_index = low;
+        let mut first_frame = None;
+        while cluster_index >= 0 {
+            let frames = try!(self.cluster(cluster_index));
+            let track_frames = frames.iter().filter(|frame| frame.track_index == track_index);
+            let mut position = None;
+            for (frame_index, frame) in track_frames.enumerate() {
+                let frame_position = (container::SeekPosition {
+                    cluster_index: cluster_index,
+                    frame_index: frame_index as i32,
+                    time: frame.time,
+                }, frame.link_index);
+                if frame.time > *time {
+                    first_frame = Some(frame_position);
+                    break
+                }
+                position = Some(frame_position)
+            }
+            if let Some(position) = position {
+                return Ok(position)
+            }
+            cluster_index -= 1
+        }
+
+        // The target is before the first packet.
+        match first_frame {
+            Some(position) => Ok(position),
+            None => Err(MediaError::EndOfStream),
+        }
+    }
+
+    /// Finds the packet to restart from by reading every cluster up to `time`, for streams of
+    /// unknown length.
+    fn seek_linearly(&self, track_index: usize, time: &Timestamp)
+                     -> Result<(container::SeekPosition, usize),MediaError> {
+        let mut position = None;
+        let mut cluster_index = 0;
+        'clusterloop: loop {
+            let frames = match self.cluster(cluster_index) {
+                Ok(frames) => frames,
+                Err(MediaError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            };
+            let track_frames = frames.iter().filter(|frame| frame.track_index == track_index);
+            for (frame_index, frame) in track_frames.enumerate() {
+                if frame.time > *time && position.is_some() {
+                    break 'clusterloop
+                }
+                position = Some((container::SeekPosition {
+                    cluster_index: cluster_index,
+                    frame_index: frame_index as i32,
+                    time: frame.time,
+                }, frame.link_index))
+            }
+            cluster_index += 1
+        }
+        match position {
+            Some(position) => Ok(position),
+            None => Err(MediaError::EndOfStream),
+        }
+    }
+}
+
+impl container::ContainerReader for ContainerReaderImpl {
+    fn track_count(&self) -> u16 {
+        ContainerReaderImpl::track_count(self) as u16
+    }
+
+    fn track_by_index<'a>(&'a self, index: u16) -> Box<container::Track + 'a> {
+        Box::new(TrackImpl {
+            reader: self,
+            track_index: index as usize,
+        }) as Box<container::Track + 'a>
+    }
+
+    fn track_by_number<'a>(&'a self, number: c_long) -> Box<container::Track + 'a> {
+        let index = self.links.borrow()[0].tracks
+                                          .iter()
+                                          .position(|track| track.serial as c_long == number)
+                                          .unwrap();
+        self.track_by_index(index as u16)
+    }
+
+    /// Returns the running time of the longest track.
+    fn duration(&self) -> Option<Timestamp> {
+        let mut duration = None;
+        for track_index in range(0, ContainerReaderImpl::track_count(self)) {
+            let track_duration = self.track_duration(track_index);
+            if track_duration > duration {
+                duration = track_duration
+            }
+        }
+        duration
+    }
+
+    /// Returns the tags of the current link, which in a radio recording describe the song now
+    /// playing.
+    fn metadata(&self) -> Vec<container::MetadataTag> {
+        let links = self.links.borrow();
+        for track in links[self.current_link.get()].tracks.iter() {
+            if let Some(tags) = track.tags() {
+                return tags
+            }
+        }
+        Vec::new()
+    }
+}
+
+#[derive(Clone)]
+struct TrackImpl<'a> {
+    reader: &'a ContainerReaderImpl,
+    track_index: usize,
+}
+
+impl<'a> container::Track for TrackImpl<'a> {
+    fn track_type(&self) -> container::TrackType {
+        container::TrackType::Audio
+    }
+
+    fn cluster_count(&self) -> Option<c_int> {
+        self.reader.cluster_count()
+    }
+
+    fn number(&self) -> c_long {
+        self.reader.links.borrow()[0].tracks[self.track_index].serial as c_long
+    }
+
+    fn codec(&self) -> Option<Vec<u8>> {
+        Some(self.reader.current_track(self.track_index, |track| track.codec.fourcc()))
+    }
+
+    fn duration(&self) -> Option<Timestamp> {
+        self.reader.track_duration(self.track_index)
+    }
+
+    fn cluster<'b>(&'b self, cluster_index: i32)
+                   -> Result<Box<container::Cluster + 'b>,MediaError> {
+        Ok(Box::new(ClusterImpl {
+            reader: self.reader,
+            frames: try!(self.reader.cluster(cluster_index)),
+        }) as Box<container::Cluster + 'b>)
+    }
+
+    fn seek(&self, time: &Timestamp) -> Result<container::SeekPosition,MediaError> {
+        self.reader.seek(self.track_index, time)
+    }
+
+    fn as_video_track<'b>(&'b self) -> Result<Box<container::VideoTrack + 'b>,()> {
+        Err(())
+    }
+
+    fn as_audio_track<'b>(&'b self) -> Result<Box<container::AudioTrack + 'b>,()> {
+        Ok(Box::new((*self).clone()) as Box<container::AudioTrack + 'b>)
+    }
+}
+
+/// These describe the current link, since the sampling rate, channel count and headers can all
+/// change from one link to the next.
+impl<'a> container::AudioTrack for TrackImpl<'a> {
+    fn sampling_rate(&self) -> c_double {
+        self.reader.current_track(self.track_index, |track| track.sampling_rate as c_double)
+    }
+
+    fn channels(&self) -> u16 {
+        self.reader.current_track(self.track_index, |track| track.channels)
+    }
+
+    fn headers(&self) -> Box<audiodecoder::AudioHeaders> {
+        self.reader.current_track(self.track_index, |track| track.audio_headers())
+    }
+}
+
+struct ClusterImpl<'a> {
+    reader: &'a ContainerReaderImpl,
+    frames: Rc<Vec<OggFrame>>,
+}
+
+impl<'a> container::Cluster for ClusterImpl<'a> {
+    fn read_frame<'b>(&'b self, frame_index: i32, track_number: c_long)
+                      -> Result<Box<container::Frame + 'b>,MediaError> {
+        if frame_index < 0 {
+            return Err(MediaError::EndOfStream)
+        }
+        match self.frames
+                  .iter()
+                  .filter(|frame| frame.track_number == track_number)
+                  .nth(frame_index as usize) {
+            Some(frame) => {
+                // The tracks describe the link that the player is now reading.
+                self.reader.current_link.set(frame.link_index);
+                Ok(Box::new(FrameImpl {
+                    frame: frame,
+                }) as Box<container::Frame + 'b>)
+            }
+            None => Err(MediaError::EndOfStream),
+        }
+    }
+}
+
+struct FrameImpl<'a> {
+    frame: &'a OggFrame,
+}
+
+impl<'a> container::Frame for FrameImpl<'a> {
+    fn len(&self) -> c_long {
+        self.frame.data.len() as c_long
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> Result<(),MediaError> {
+        let length = self.frame.data.len();
+        if buffer.len() < length {
+            return Err(MediaError::Malformed)
+        }
+        bytes::copy_memory(&mut buffer[0..length], self.frame.data.as_slice());
+        Ok(())
+    }
+
+    fn data<'b>(&'b self) -> Option<&'b [u8]> {
+        Some(self.frame.data.as_slice())
+    }
+
+    fn track_number(&self) -> c_long {
+        self.frame.track_number
+    }
+
+    fn time(&self) -> Timestamp {
+        self.frame.time
+    }
+
+    fn rendering_offset(&self) -> i64 {
+        0
+    }
+
+    fn is_keyframe(&self) -> bool {
+        true
+    }
+
+    fn duration(&self) -> Option<i64> {
+        Some(self.frame.duration)
+    }
+
+    fn is_discardable(&self) -> bool {
+        false
+    }
+
+    fn discontinuity(&self) -> bool {
+        self.frame.discontinuity
+    }
+}
+
+pub const CONTAINER_READER: container::RegisteredContainerReader =
+    container::RegisteredContainerReader {
+        mime_types: &[
+            "audio/ogg",
+            "video/ogg",
+            "application/ogg",
+        ],
+        signatures: &[
+            container::Signature {
+                offset: 0,
+                pattern: b"OggS",
+            },
+        ],
+        read: ContainerReaderImpl::new,
+    };
+
 #[allow(missing_copy_implementations)]
 pub mod ffi {
     use libc::{c_char, c_int, c_long, c_uchar};
@@ -224,6 +1461,7 @@ pub mod ffi {
         pub fn ogg_sync_buffer(oy: *mut ogg_sync_state, size: c_long) -> *mut c_char;
         pub fn ogg_sync_wrote(oy: *mut ogg_sync_state, bytes: c_long) -> c_int;
         pub fn ogg_sync_pageout(oy: *mut ogg_sync_state, og: *mut ogg_page) -> c_int;
+        pub fn ogg_sync_pageseek(oy: *mut ogg_sync_state, og: *mut ogg_page) -> c_long;
 
         pub fn ogg_stream_init(os: *mut ogg_stream_state, serialno: c_int) -> c_int;
         pub fn ogg_stream_clear(os: *mut ogg_stream_state) -> c_int;
@@ -232,6 +1470,164 @@ pub mod ffi {
 
         pub fn ogg_page_serialno(og: *const ogg_page) -> c_int;
         pub fn ogg_page_eos(og: *const ogg_page) -> c_int;
+        pub fn ogg_page_bos(og: *const ogg_page) -> c_int;
+        pub fn ogg_page_granulepos(og: *const ogg_page) -> i64;
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::{CLUSTER_SIZE, ContainerReaderImpl};
+    use container::{Cluster, ContainerReader, Frame, Track};
+    use error::MediaError;
+    use streaming::StreamReader;
+
+    use std::io::Cursor;
+    use std::iter;
+
+    /// The number of audio packets in each link.
+    const PACKET_COUNT: usize = 40;
+
+    /// Computes the checksum of an Ogg page, whose own checksum field must be zero.
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = 0u32;
+        for &byte in data.iter() {
+            crc ^= (byte as u32) << 24;
+            for _ in range(0, 8) {
+                crc = if (crc & 0x80000000) != 0 {
+                    (crc << 1) ^ 0x04c11db7
+                } else {
+                    crc << 1
+                }
+            }
+        }
+        crc
+    }
+
+    fn push_u32_le(data: &mut Vec<u8>, value: u32) {
+        data.push_all(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8])
+    }
+
+    /// Writes `packet` as one or more pages. `flags` are the beginning- and end-of-stream flags
+    /// for its first and last pages respectively, and `granule_position` is that of the last page.
+    fn write_packet(data: &mut Vec<u8>,
+                    serial: u32,
+                    sequence_number: &mut u32,
+                    flags: u8,
+                    granule_position: i64,
+                    packet: &[u8]) {
+        let mut lacing_values: Vec<u8> = iter::repeat(255).take(packet.len() / 255).collect();
+        lacing_values.push((packet.len() % 255) as u8);
+
+        let (mut packet, page_count) = (packet, (lacing_values.len() + 254) / 255);
+        for (page_index, lacing_values) in lacing_values.chunks(255).enumerate() {
+            let last = page_index == page_count - 1;
+            let mut header_type = 0;
+            if page_index > 0 {
+                header_type |= 0x01
+            }
+            if page_index == 0 {
+                header_type |= flags & 0x02
+            }
+            if last {
+                header_type |= flags & 0x04
+            }
+            let granule_position = if last {
+                granule_position
+            } else {
+                -1
+            };
+
+            let mut page = b"OggS".to_vec();
+            page.push_all(&[0, header_type]);
+            push_u32_le(&mut page, granule_position as u32);
+            push_u32_le(&mut page, (granule_position >> 32) as u32);
+            push_u32_le(&mut page, serial);
+            push_u32_le(&mut page, *sequence_number);
+            push_u32_le(&mut page, 0);
+            page.push(lacing_values.len() as u8);
+            page.push_all(lacing_values);
+            let body_size = lacing_values.iter().fold(0, |sum, &value| sum + value as usize);
+            page.push_all(&packet[0..body_size]);
+            packet = &packet[body_size..];
+
+            let crc = crc32(page.as_slice());
+            for byte_index in range(0, 4) {
+                page[22 + byte_index] = (crc >> (byte_index * 8)) as u8
+            }
+            data.push_all(page.as_slice());
+            *sequence_number += 1
+        }
+    }
+
+    /// Writes one link of a chained Opus stream, with a single comment in its comment header.
+    fn write_link(data: &mut Vec<u8>, serial: u32, comment: &[u8]) {
+        let mut sequence_number = 0;
+
+        // Version 1, mono, no pre-skip, 48 kHz, no gain, and no channel mapping.
+        let mut head = b"OpusHead".to_vec();
+        head.push_all(&[1, 1, 0, 0, 0x80, 0xbb, 0, 0, 0, 0, 0]);
+        write_packet(data, serial, &mut sequence_number, 0x02, 0, head.as_slice());
+
+        let mut tags = b"OpusTags".to_vec();
+        push_u32_le(&mut tags, 0);
+        push_u32_le(&mut tags, 1);
+        push_u32_le(&mut tags, comment.len() as u32);
+        tags.push_all(comment);
+        write_packet(data, serial, &mut sequence_number, 0, 0, tags.as_slice());
+
+        // Each packet is a single 20 ms CELT frame.
+        let mut packet = vec![0xf8];
+        packet.extend(iter::repeat(0).take(999));
+        for packet_index in range(0, PACKET_COUNT) {
+            let flags = if packet_index == PACKET_COUNT - 1 {
+                0x04
+            } else {
+                0
+            };
+            write_packet(data,
+                         serial,
+                         &mut sequence_number,
+                         flags,
+                         960 * (packet_index as i64 + 1),
+                         packet.as_slice())
+        }
+    }
+
+    #[test]
+    fn link_with_headers_past_the_end_of_its_cluster_is_read() {
+        // Cover art in the second link's comment header carries its headers across clusters.
+        let mut comment = b"METADATA_BLOCK_PICTURE=".to_vec();
+        comment.extend(iter::repeat(b'A').take(100000));
+        let mut data = Vec::new();
+        write_link(&mut data, 1, b"TITLE=First");
+        let second_link_position = data.len() as u64;
+        write_link(&mut data, 2, comment.as_slice());
+        assert!(second_link_position < CLUSTER_SIZE);
+
+        let reader = ContainerReaderImpl::new(Box::new(Cursor::new(data)) as Box<StreamReader>)
+            .ok()
+            .unwrap();
+        let track = reader.track_by_index(0);
+        let mut discontinuities = Vec::new();
+        let mut cluster_index = 0;
+        loop {
+            let cluster = match track.cluster(cluster_index) {
+                Ok(cluster) => cluster,
+                Err(MediaError::EndOfStream) => break,
+                Err(err) => panic!("failed to read cluster {}: {:?}", cluster_index, err),
+            };
+            let mut frame_index = 0;
+            while let Ok(frame) = cluster.read_frame(frame_index, track.number()) {
+                discontinuities.push(frame.discontinuity());
+                frame_index += 1
+            }
+            cluster_index += 1
+        }
+
+        assert_eq!(discontinuities.len(), PACKET_COUNT * 2);
+        assert!(discontinuities[PACKET_COUNT]);
+        assert_eq!(discontinuities.iter().filter(|&&discontinuity| discontinuity).count(), 1);
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Errors shared by the container readers, codecs, and the player.
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MediaError {
+    /// The end of the stream was reached. `Cluster::read_frame()` also returns this when there are
+    /// no more frames for the track in that cluster.
+    EndOfStream,
+    /// More data is needed before this operation can succeed. For containers, this means that the
+    /// stream isn't available yet; for decoders, it means that the input was accepted but hasn't
+    /// produced any output yet.
+    NeedMoreData,
+    /// No container reader is registered that can handle this stream.
+    UnsupportedContainer,
+    /// No decoder is registered for this codec, or the decoder can't handle this variant of it.
+    UnsupportedCodec,
+    /// The stream is corrupt or doesn't conform to its format.
+    Malformed,
+    /// The sampling rate or number of channels of an audio track changed partway through the
+    /// stream. Players configure their audio output once, so they can't follow the change.
+    AudioFormatChanged,
+    /// A native library reported an error. `code` is the library's own error code, and `detail`
+    /// names the library and describes the failure, if it told us how.
+    Backend {
+        code: i64,
+        detail: String,
+    },
+}
+
+impl MediaError {
+    pub fn backend(code: i64, detail: &str) -> MediaError {
+        MediaError::Backend {
+            code: code,
+            detail: detail.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for MediaError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MediaError::Backend {
+                code,
+                ref detail,
+            } => write!(formatter, "{} (error {})", detail, code),
+            _ => write!(formatter, "{}", self.description()),
+        }
+    }
+}
+
+impl Error for MediaError {
+    fn description(&self) -> &str {
+        match *self {
+            MediaError::EndOfStream => "end of stream",
+            MediaError::NeedMoreData => "more data is needed",
+            MediaError::UnsupportedContainer => "unsupported container format",
+            MediaError::UnsupportedCodec => "unsupported codec",
+            MediaError::Malformed => "malformed stream",
+            MediaError::AudioFormatChanged => "audio format changed mid-stream",
+            MediaError::Backend {
+                ..
+            } => "error in native media library",
+        }
+    }
+}
+
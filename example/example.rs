@@ -7,7 +7,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-#![feature(collections, core, env, io, libc, os, path, rustc_private, std_misc)]
+#![feature(collections, core, env, fs, io, libc, os, path, rustc_private, std_misc)]
 
 extern crate clock_ticks;
 extern crate libc;
@@ -22,6 +22,7 @@ use media::audioformat::{ConvertAudioFormat, Float32Interleaved, Float32Planar};
 use media::container::{AudioTrack, ContainerReader, Frame, Track, VideoTrack};
 use media::pixelformat::{ConvertPixelFormat, PixelFormat, Rgb24};
 use media::playback::Player;
+use media::streaming::BufferedStreamReader;
 use media::videodecoder::{DecodedVideoFrame, VideoDecoder};
 use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
 use sdl2::event::{self, Event, WindowEventId};
@@ -34,8 +35,8 @@ use sdl2::video::{OPENGL, RESIZABLE, Window, WindowPos};
 use sdl2::{INIT_AUDIO, INIT_VIDEO, init};
 use std::cmp;
 use std::env;
+use std::fs::File;
 use std::mem;
-use std::old_io::fs::File;
 use std::old_io::timer;
 use std::slice;
 use std::time::duration::Duration;
@@ -173,7 +174,10 @@ struct SdlVideoFormat {
 impl SdlVideoFormat {
     fn from_video_track(video_track: &VideoTrack) -> SdlVideoFormat {
         let (media_pixel_format, sdl_pixel_format) = match video_track.pixel_format() {
-            PixelFormat::I420 | PixelFormat::NV12 => (PixelFormat::I420, PixelFormatEnum::IYUV),
+            PixelFormat::I420 | PixelFormat::I422 | PixelFormat::I444 | PixelFormat::I42016(_) |
+            PixelFormat::I42216(_) | PixelFormat::I44416(_) | PixelFormat::NV12 => {
+                (PixelFormat::I420, PixelFormatEnum::IYUV)
+            }
             PixelFormat::Indexed(_) | PixelFormat::Rgb24 => {
                 (PixelFormat::Rgb24, PixelFormatEnum::RGB24)
             }
@@ -293,15 +297,21 @@ fn upload_image(video_track: &VideoTrack,
 
 fn main() {
     let args: Vec<String> = env::args().map(|arg| arg.into_string().unwrap()).collect();
-    if args.len() < 3 {
-        println!("usage: example path-to-video-or-audio-file mime-type");
+    if args.len() < 2 {
+        println!("usage: example path-to-video-or-audio-file [mime-type]");
         return
     }
 
     sdl2::init(INIT_VIDEO | INIT_AUDIO);
-    let file = Box::new(File::open(&Path::new(args[1].as_slice())).unwrap());
+    let file = File::open(args[1].as_slice()).unwrap();
+    let file = Box::new(BufferedStreamReader::new(file));
 
-    let mut player = Player::new(file, args[2].as_slice());
+    let mime_type = if args.len() >= 3 {
+        Some(args[2].as_slice())
+    } else {
+        None
+    };
+    let mut player = Player::new(file, mime_type).unwrap();
     let mut media_player = ExampleMediaPlayer::new();
 
     let renderer = player.video_track_number().map(|video_track_number| {
@@ -333,6 +343,9 @@ fn main() {
         renderer
     });
 
+    // Captions are printed to the terminal as they change.
+    let mut caption = Vec::new();
+
     loop {
         if player.decode_frame().is_err() {
             break
@@ -355,6 +368,15 @@ fn main() {
         if let Some(ref mut audio_renderer) = audio_renderer {
             enqueue_audio_samples(audio_renderer, frame.audio_samples.unwrap().as_slice());
         }
+        if let Ok(cues) = player.subtitle_cues() {
+            let new_caption: Vec<String> = cues.iter().map(|cue| cue.text()).collect();
+            if new_caption != caption {
+                for line in new_caption.iter() {
+                    println!("{}", line)
+                }
+                caption = new_caption
+            }
+        }
 
         if !media_player.poll_events(&mut player) {
             break
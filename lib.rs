@@ -7,7 +7,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-#![feature(alloc, collections, core, io, libc, std_misc, unsafe_destructor)]
+#![feature(alloc, collections, core, fs, io, libc, std_misc, unsafe_destructor)]
 
 extern crate alloc;
 extern crate libc;
@@ -18,8 +18,10 @@ extern crate core_foundation;
 pub mod audiodecoder;
 pub mod audioformat;
 pub mod container;
+pub mod error;
 pub mod pixelformat;
 pub mod playback;
+pub mod registry;
 pub mod streaming;
 pub mod timing;
 pub mod videodecoder;
@@ -27,6 +29,7 @@ pub mod videodecoder;
 pub mod codecs {
     pub mod aac;
     pub mod h264;
+    pub mod opus;
     pub mod vorbis;
     pub mod vpx;
 
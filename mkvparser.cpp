@@ -9,6 +9,8 @@
 
 #include <mkvparser.hpp>
 #include <mkvreader.hpp>
+#include <string>
+#include <vector>
 
 using namespace mkvparser;
 
@@ -25,6 +27,12 @@ typedef AudioTrack* WebmAudioTrackRef;
 typedef BlockEntry* WebmBlockEntryRef;
 typedef Block* WebmBlockRef;
 typedef Block::Frame* WebmBlockFrameRef;
+typedef Cues* WebmCuesRef;
+typedef CuePoint* WebmCuePointRef;
+typedef CuePoint::TrackPosition* WebmCueTrackPositionRef;
+typedef Tags* WebmTagsRef;
+typedef Tags::Tag* WebmTagRef;
+typedef Tags::SimpleTag* WebmSimpleTagRef;
 
 struct WebmCustomMkvReaderCallbacks {
     int (*Read)(long long pos, long len, unsigned char* buf, void* userData);
@@ -106,6 +114,20 @@ extern "C" long WebmSegmentLoad(WebmSegmentRef segment) {
     return segment->Load();
 }
 
+extern "C" long long WebmSegmentParseHeaders(WebmSegmentRef segment) {
+    return segment->ParseHeaders();
+}
+
+extern "C" long WebmSegmentLoadCluster(WebmSegmentRef segment) {
+    long long pos = 0;
+    long len = 0;
+    return segment->LoadCluster(pos, len);
+}
+
+extern "C" bool WebmSegmentDoneParsing(WebmSegmentRef segment) {
+    return segment->DoneParsing();
+}
+
 extern "C" WebmTracksRef WebmSegmentGetTracks(WebmSegmentRef segment) {
     return const_cast<WebmTracksRef>(segment->GetTracks());
 }
@@ -126,10 +148,22 @@ extern "C" WebmClusterRef WebmSegmentGetNext(WebmSegmentRef segment, WebmCluster
     return const_cast<WebmClusterRef>(segment->GetNext(const_cast<const Cluster*>(cluster)));
 }
 
+extern "C" WebmCuesRef WebmSegmentGetCues(WebmSegmentRef segment) {
+    return const_cast<WebmCuesRef>(segment->GetCues());
+}
+
+extern "C" WebmTagsRef WebmSegmentGetTags(WebmSegmentRef segment) {
+    return const_cast<WebmTagsRef>(segment->GetTags());
+}
+
 extern "C" long long WebmSegmentInfoGetTimeCodeScale(WebmSegmentInfoRef segmentInfo) {
     return segmentInfo->GetTimeCodeScale();
 }
 
+extern "C" long long WebmSegmentInfoGetDuration(WebmSegmentInfoRef segmentInfo) {
+    return segmentInfo->GetDuration();
+}
+
 extern "C" void WebmTracksDestroy(WebmTracksRef tracks) {
     delete tracks;
 }
@@ -166,6 +200,20 @@ extern "C" const unsigned char* WebmTrackGetCodecPrivate(WebmTrackRef track, siz
     return track->GetCodecPrivate(*size);
 }
 
+extern "C" unsigned long long WebmTrackGetDefaultDuration(WebmTrackRef track) {
+    return track->GetDefaultDuration();
+}
+
+extern "C" unsigned long long WebmTrackGetCodecDelay(WebmTrackRef track) {
+    return track->GetCodecDelay();
+}
+
+extern "C" WebmBlockEntryRef WebmTrackSeek(WebmTrackRef track, long long timeNs, long* err) {
+    const BlockEntry* result = nullptr;
+    *err = track->Seek(timeNs, result);
+    return const_cast<WebmBlockEntryRef>(result);
+}
+
 extern "C" void WebmVideoTrackDestroy(WebmVideoTrackRef track) {
     delete track;
 }
@@ -224,6 +272,10 @@ extern "C" WebmBlockEntryRef WebmClusterGetNext(WebmClusterRef cluster,
     return const_cast<WebmBlockEntryRef>(result);
 }
 
+extern "C" long WebmClusterGetIndex(WebmClusterRef cluster) {
+    return cluster->GetIndex();
+}
+
 extern "C" long WebmClusterGetEntryCount(WebmClusterRef cluster) {
     return cluster->GetEntryCount();
 }
@@ -250,6 +302,22 @@ extern "C" bool WebmBlockEntryEos(WebmBlockEntryRef entry) {
     return entry->EOS();
 }
 
+extern "C" WebmClusterRef WebmBlockEntryGetCluster(WebmBlockEntryRef entry) {
+    return const_cast<WebmClusterRef>(entry->GetCluster());
+}
+
+extern "C" long WebmBlockEntryGetIndex(WebmBlockEntryRef entry) {
+    return entry->GetIndex();
+}
+
+extern "C" long WebmBlockEntryGetKind(WebmBlockEntryRef entry) {
+    return entry->GetKind();
+}
+
+extern "C" long long WebmBlockGroupGetDurationTimeCode(WebmBlockEntryRef entry) {
+    return static_cast<const BlockGroup*>(entry)->GetDurationTimeCode();
+}
+
 extern "C" void WebmBlockDestroy(WebmBlockRef block) {
     delete block;
 }
@@ -282,6 +350,17 @@ extern "C" bool WebmBlockIsKey(WebmBlockRef block) {
     return block->IsKey();
 }
 
+// libwebm doesn't expose the block flags, so read them straight out of the file. They follow the
+// track number and the 16-bit relative timecode.
+extern "C" long WebmBlockGetFlags(WebmBlockRef block,
+                                  WebmIMkvReaderRef reader,
+                                  unsigned char* flags) {
+    long trackNumberSize = 0;
+    if (ReadUInt(reader, block->m_start, trackNumberSize) < 0)
+        return E_FILE_FORMAT_INVALID;
+    return reader->Read(block->m_start + trackNumberSize + 2, 1, flags);
+}
+
 extern "C" void WebmBlockFrameDestroy(WebmBlockFrameRef blockFrame) {
     delete blockFrame;
 }
@@ -300,3 +379,313 @@ extern "C" long WebmBlockFrameRead(WebmBlockFrameRef blockFrame,
     return blockFrame->Read(reader, buffer);
 }
 
+extern "C" bool WebmCuesDoneParsing(WebmCuesRef cues) {
+    return cues->DoneParsing();
+}
+
+extern "C" bool WebmCuesLoadCuePoint(WebmCuesRef cues) {
+    return cues->LoadCuePoint();
+}
+
+extern "C" bool WebmCuesFind(WebmCuesRef cues,
+                             long long timeNs,
+                             WebmTrackRef track,
+                             WebmCuePointRef* cuePoint,
+                             WebmCueTrackPositionRef* trackPosition) {
+    const CuePoint* resultCuePoint = nullptr;
+    const CuePoint::TrackPosition* resultTrackPosition = nullptr;
+    bool found = cues->Find(timeNs, track, resultCuePoint, resultTrackPosition);
+    *cuePoint = const_cast<WebmCuePointRef>(resultCuePoint);
+    *trackPosition = const_cast<WebmCueTrackPositionRef>(resultTrackPosition);
+    return found;
+}
+
+extern "C" WebmBlockEntryRef WebmCuesGetBlock(WebmCuesRef cues,
+                                              WebmCuePointRef cuePoint,
+                                              WebmCueTrackPositionRef trackPosition) {
+    return const_cast<WebmBlockEntryRef>(cues->GetBlock(cuePoint, trackPosition));
+}
+
+extern "C" int WebmTagsGetTagCount(WebmTagsRef tags) {
+    return tags->GetTagCount();
+}
+
+extern "C" WebmTagRef WebmTagsGetTag(WebmTagsRef tags, int index) {
+    return const_cast<WebmTagRef>(tags->GetTag(index));
+}
+
+extern "C" int WebmTagGetSimpleTagCount(WebmTagRef tag) {
+    return tag->GetSimpleTagCount();
+}
+
+extern "C" WebmSimpleTagRef WebmTagGetSimpleTag(WebmTagRef tag, int index) {
+    return const_cast<WebmSimpleTagRef>(tag->GetSimpleTag(index));
+}
+
+extern "C" const char* WebmSimpleTagGetTagName(WebmSimpleTagRef simpleTag) {
+    return simpleTag->GetTagName();
+}
+
+extern "C" const char* WebmSimpleTagGetTagString(WebmSimpleTagRef simpleTag) {
+    return simpleTag->GetTagString();
+}
+
+// libwebm's `Chapters` skips nested chapter atoms and the edition and chapter flags, so we parse
+// the Chapters element ourselves.
+
+struct WebmChapterDisplay {
+    std::string string;
+    std::string language;
+    std::string country;
+
+    WebmChapterDisplay() : language("eng") {}
+};
+
+struct WebmChapterAtom {
+    long long startTimecode;
+    long long stopTimecode;
+    bool hidden;
+    bool enabled;
+    std::vector<WebmChapterDisplay> displays;
+    std::vector<WebmChapterAtom> children;
+
+    WebmChapterAtom() : startTimecode(0), stopTimecode(-1), hidden(false), enabled(true) {}
+};
+
+struct WebmEdition {
+    bool isDefault;
+    bool hidden;
+    std::vector<WebmChapterAtom> atoms;
+
+    WebmEdition() : isDefault(false), hidden(false) {}
+};
+
+struct WebmChapters {
+    std::vector<WebmEdition> editions;
+};
+
+typedef WebmChapters* WebmChaptersRef;
+typedef WebmEdition* WebmEditionRef;
+typedef WebmChapterAtom* WebmChapterAtomRef;
+typedef WebmChapterDisplay* WebmChapterDisplayRef;
+
+static const long long kMkvEditionEntry = 0x45B9;
+static const long long kMkvEditionFlagHidden = 0x45BD;
+static const long long kMkvEditionFlagDefault = 0x45DB;
+static const long long kMkvChapterAtom = 0xB6;
+static const long long kMkvChapterTimeStart = 0x91;
+static const long long kMkvChapterTimeEnd = 0x92;
+static const long long kMkvChapterFlagHidden = 0x98;
+static const long long kMkvChapterFlagEnabled = 0x4598;
+static const long long kMkvChapterDisplay = 0x80;
+static const long long kMkvChapString = 0x85;
+static const long long kMkvChapLanguage = 0x437C;
+static const long long kMkvChapCountry = 0x437E;
+
+// Chapters nest by recursion, so a crafted file could otherwise exhaust the stack. Real files
+// rarely go more than a few levels deep.
+static const int kMaxChapterDepth = 32;
+
+static bool WebmParseUInt(IMkvReader* reader, long long pos, long long size, long long& value) {
+    value = UnserializeUInt(reader, pos, size);
+    return value >= 0;
+}
+
+static bool WebmParseString(IMkvReader* reader,
+                            long long pos,
+                            long long size,
+                            std::string& value) {
+    char* str = nullptr;
+    if (UnserializeString(reader, pos, size, str) < 0)
+        return false;
+    value = str != nullptr ? str : "";
+    delete[] str;
+    return true;
+}
+
+static bool WebmParseChapterDisplay(IMkvReader* reader,
+                                    long long pos,
+                                    long long stop,
+                                    WebmChapterDisplay& display) {
+    bool sawLanguage = false;
+    while (pos < stop) {
+        long long id, size;
+        if (ParseElementHeader(reader, pos, stop, id, size) != 0)
+            return false;
+        bool ok = true;
+        if (id == kMkvChapString) {
+            ok = WebmParseString(reader, pos, size, display.string);
+        } else if (id == kMkvChapLanguage && !sawLanguage) {
+            // A title can list several languages; we keep the first.
+            ok = WebmParseString(reader, pos, size, display.language);
+            sawLanguage = true;
+        } else if (id == kMkvChapCountry) {
+            ok = WebmParseString(reader, pos, size, display.country);
+        }
+        if (!ok)
+            return false;
+        pos += size;
+    }
+    return true;
+}
+
+static bool WebmParseChapterAtom(IMkvReader* reader,
+                                 long long pos,
+                                 long long stop,
+                                 int depth,
+                                 WebmChapterAtom& atom) {
+    if (depth >= kMaxChapterDepth)
+        return false;
+    while (pos < stop) {
+        long long id, size, value;
+        if (ParseElementHeader(reader, pos, stop, id, size) != 0)
+            return false;
+        bool ok = true;
+        if (id == kMkvChapterTimeStart) {
+            ok = WebmParseUInt(reader, pos, size, atom.startTimecode);
+        } else if (id == kMkvChapterTimeEnd) {
+            ok = WebmParseUInt(reader, pos, size, atom.stopTimecode);
+        } else if (id == kMkvChapterFlagHidden) {
+            ok = WebmParseUInt(reader, pos, size, value);
+            atom.hidden = value != 0;
+        } else if (id == kMkvChapterFlagEnabled) {
+            ok = WebmParseUInt(reader, pos, size, value);
+            atom.enabled = value != 0;
+        } else if (id == kMkvChapterDisplay) {
+            atom.displays.push_back(WebmChapterDisplay());
+            ok = WebmParseChapterDisplay(reader, pos, pos + size, atom.displays.back());
+        } else if (id == kMkvChapterAtom) {
+            atom.children.push_back(WebmChapterAtom());
+            ok = WebmParseChapterAtom(reader, pos, pos + size, depth + 1, atom.children.back());
+        }
+        if (!ok)
+            return false;
+        pos += size;
+    }
+    return true;
+}
+
+static bool WebmParseEdition(IMkvReader* reader,
+                             long long pos,
+                             long long stop,
+                             WebmEdition& edition) {
+    while (pos < stop) {
+        long long id, size, value;
+        if (ParseElementHeader(reader, pos, stop, id, size) != 0)
+            return false;
+        bool ok = true;
+        if (id == kMkvEditionFlagDefault) {
+            ok = WebmParseUInt(reader, pos, size, value);
+            edition.isDefault = value != 0;
+        } else if (id == kMkvEditionFlagHidden) {
+            ok = WebmParseUInt(reader, pos, size, value);
+            edition.hidden = value != 0;
+        } else if (id == kMkvChapterAtom) {
+            edition.atoms.push_back(WebmChapterAtom());
+            ok = WebmParseChapterAtom(reader, pos, pos + size, 0, edition.atoms.back());
+        }
+        if (!ok)
+            return false;
+        pos += size;
+    }
+    return true;
+}
+
+// Returns null if the segment has no chapters or they are malformed. The result must be freed with
+// `WebmChaptersDestroy()`.
+extern "C" WebmChaptersRef WebmSegmentParseChapters(WebmSegmentRef segment) {
+    const Chapters* chapters = segment->GetChapters();
+    if (chapters == nullptr)
+        return nullptr;
+
+    IMkvReader* reader = segment->m_pReader;
+    WebmChapters* result = new WebmChapters;
+    long long pos = chapters->m_start;
+    const long long stop = chapters->m_start + chapters->m_size;
+    while (pos < stop) {
+        long long id, size;
+        bool ok = ParseElementHeader(reader, pos, stop, id, size) == 0;
+        if (ok && id == kMkvEditionEntry) {
+            result->editions.push_back(WebmEdition());
+            ok = WebmParseEdition(reader, pos, pos + size, result->editions.back());
+        }
+        if (!ok) {
+            delete result;
+            return nullptr;
+        }
+        pos += size;
+    }
+    return result;
+}
+
+extern "C" void WebmChaptersDestroy(WebmChaptersRef chapters) {
+    delete chapters;
+}
+
+extern "C" int WebmChaptersGetEditionCount(WebmChaptersRef chapters) {
+    return static_cast<int>(chapters->editions.size());
+}
+
+extern "C" WebmEditionRef WebmChaptersGetEdition(WebmChaptersRef chapters, int index) {
+    return &chapters->editions[index];
+}
+
+extern "C" bool WebmEditionIsDefault(WebmEditionRef edition) {
+    return edition->isDefault;
+}
+
+extern "C" bool WebmEditionIsHidden(WebmEditionRef edition) {
+    return edition->hidden;
+}
+
+extern "C" int WebmEditionGetAtomCount(WebmEditionRef edition) {
+    return static_cast<int>(edition->atoms.size());
+}
+
+extern "C" WebmChapterAtomRef WebmEditionGetAtom(WebmEditionRef edition, int index) {
+    return &edition->atoms[index];
+}
+
+extern "C" long long WebmChapterAtomGetStartTimecode(WebmChapterAtomRef atom) {
+    return atom->startTimecode;
+}
+
+extern "C" long long WebmChapterAtomGetStopTimecode(WebmChapterAtomRef atom) {
+    return atom->stopTimecode;
+}
+
+extern "C" bool WebmChapterAtomIsHidden(WebmChapterAtomRef atom) {
+    return atom->hidden;
+}
+
+extern "C" bool WebmChapterAtomIsEnabled(WebmChapterAtomRef atom) {
+    return atom->enabled;
+}
+
+extern "C" int WebmChapterAtomGetDisplayCount(WebmChapterAtomRef atom) {
+    return static_cast<int>(atom->displays.size());
+}
+
+extern "C" WebmChapterDisplayRef WebmChapterAtomGetDisplay(WebmChapterAtomRef atom, int index) {
+    return &atom->displays[index];
+}
+
+extern "C" int WebmChapterAtomGetChildCount(WebmChapterAtomRef atom) {
+    return static_cast<int>(atom->children.size());
+}
+
+extern "C" WebmChapterAtomRef WebmChapterAtomGetChild(WebmChapterAtomRef atom, int index) {
+    return &atom->children[index];
+}
+
+extern "C" const char* WebmChapterDisplayGetString(WebmChapterDisplayRef display) {
+    return display->string.c_str();
+}
+
+extern "C" const char* WebmChapterDisplayGetLanguage(WebmChapterDisplayRef display) {
+    return display->language.c_str();
+}
+
+extern "C" const char* WebmChapterDisplayGetCountry(WebmChapterDisplayRef display) {
+    return display->country.c_str();
+}
@@ -17,6 +17,24 @@ use std::slice::bytes;
 #[derive(Copy, Debug)]
 pub struct I420;
 
+/// 8-bit Y plane followed by 8-bit U and V planes subsampled 2x1 (horizontally only).
+#[derive(Copy, Debug)]
+pub struct I422;
+
+/// 8-bit Y, U and V planes, all at full resolution.
+#[derive(Copy, Debug)]
+pub struct I444;
+
+/// Y, U and V planes laid out as in `I420`, `I422` or `I444`, but with each sample stored as a
+/// 16-bit little-endian value of which only the low `bit_depth` bits are used.
+#[derive(Copy, Debug)]
+pub struct HighBitDepth {
+    /// How many times the chroma planes are subsampled horizontally and vertically, as powers of
+    /// two: (1, 1) for 4:2:0, (1, 0) for 4:2:2, and (0, 0) for 4:4:4.
+    pub chroma_shift: (usize, usize),
+    pub bit_depth: u8,
+}
+
 /// 8-bit Y plane followed by an interleaved U/V plane containing 2x2 subsampled color difference
 /// samples.
 #[derive(Copy, Debug)]
@@ -103,6 +121,136 @@ impl ConvertPixelFormat<I420> for I420 {
     }
 }
 
+impl ConvertPixelFormat<I420> for I422 {
+    fn convert(&self,
+               _: &I420,
+               output_pixels: &mut [&mut [u8]],
+               output_strides: &[usize],
+               input_pixels: &[&[u8]],
+               input_strides: &[usize],
+               width: usize,
+               height: usize)
+               -> Result<(),()> {
+        planar_to_i420((1, 0),
+                       None,
+                       output_pixels,
+                       output_strides,
+                       input_pixels,
+                       input_strides,
+                       width,
+                       height)
+    }
+}
+
+impl ConvertPixelFormat<I420> for I444 {
+    fn convert(&self,
+               _: &I420,
+               output_pixels: &mut [&mut [u8]],
+               output_strides: &[usize],
+               input_pixels: &[&[u8]],
+               input_strides: &[usize],
+               width: usize,
+               height: usize)
+               -> Result<(),()> {
+        planar_to_i420((0, 0),
+                       None,
+                       output_pixels,
+                       output_strides,
+                       input_pixels,
+                       input_strides,
+                       width,
+                       height)
+    }
+}
+
+impl ConvertPixelFormat<I420> for HighBitDepth {
+    fn convert(&self,
+               _: &I420,
+               output_pixels: &mut [&mut [u8]],
+               output_strides: &[usize],
+               input_pixels: &[&[u8]],
+               input_strides: &[usize],
+               width: usize,
+               height: usize)
+               -> Result<(),()> {
+        if self.bit_depth < 8 || self.bit_depth > 16 {
+            return Err(())
+        }
+        planar_to_i420(self.chroma_shift,
+                       Some(self.bit_depth),
+                       output_pixels,
+                       output_strides,
+                       input_pixels,
+                       input_strides,
+                       width,
+                       height)
+    }
+}
+
+/// Converts planar YUV with any chroma subsampling to I420, by averaging chroma samples and
+/// dropping the low bits of high-bit-depth samples. `chroma_shift` is as in `HighBitDepth`, and
+/// `bit_depth` is `None` for 8-bit samples.
+fn planar_to_i420(chroma_shift: (usize, usize),
+                  bit_depth: Option<u8>,
+                  output_pixels: &mut [&mut [u8]],
+                  output_strides: &[usize],
+                  input_pixels: &[&[u8]],
+                  input_strides: &[usize],
+                  width: usize,
+                  height: usize)
+                  -> Result<(),()> {
+    let (x_shift, y_shift) = chroma_shift;
+    if x_shift > 1 || y_shift > 1 {
+        return Err(())
+    }
+    let extra_bits = match bit_depth {
+        None => 0,
+        Some(bit_depth) => bit_depth as usize - 8,
+    };
+    for plane in range(0, 3) {
+        // The number of input samples that each output sample covers in each direction. Chroma
+        // planes of frames with odd sizes have an extra, partly covered, sample at the edges.
+        let (output_width, output_height, x_step, y_step) = if plane == 0 {
+            (width, height, 1, 1)
+        } else {
+            ((width + 1) / 2, (height + 1) / 2, 2 >> x_shift, 2 >> y_shift)
+        };
+        let (input_width, input_height) = if plane == 0 {
+            (width, height)
+        } else {
+            ((width + (1 << x_shift) - 1) >> x_shift, (height + (1 << y_shift) - 1) >> y_shift)
+        };
+        let (input_plane, input_stride) = (input_pixels[plane], input_strides[plane]);
+        let output_plane = &mut *output_pixels[plane];
+        let output_stride = output_strides[plane];
+
+        for y in range(0, output_height) {
+            let output_start = y * output_stride;
+            let output_row = &mut output_plane[output_start..output_start + output_width];
+            let input_rows = range(y * y_step, cmp::min((y + 1) * y_step, input_height));
+            for x in range(0, output_width) {
+                let input_columns = range(x * x_step, cmp::min((x + 1) * x_step, input_width));
+                let (mut sum, mut count) = (0u32, 0u32);
+                for input_y in input_rows.clone() {
+                    let input_row = &input_plane[input_y * input_stride..];
+                    for input_x in input_columns.clone() {
+                        sum += match bit_depth {
+                            None => input_row[input_x] as u32,
+                            Some(_) => {
+                                (input_row[input_x * 2] as u32) |
+                                    ((input_row[input_x * 2 + 1] as u32) << 8)
+                            }
+                        };
+                        count += 1
+                    }
+                }
+                output_row[x] = (((sum + count / 2) / count) >> extra_bits) as u8
+            }
+        }
+    }
+    Ok(())
+}
+
 impl ConvertPixelFormat<I420> for NV12 {
     fn convert(&self,
                _: &I420,
@@ -265,6 +413,14 @@ impl ConvertColorFormat<RgbColor> for YuvColor {
 #[derive(Copy, Debug)]
 pub enum PixelFormat<'a> {
     I420,
+    I422,
+    I444,
+    /// 4:2:0 planar with 16-bit samples, of which the given number of bits are significant.
+    I42016(u8),
+    /// 4:2:2 planar with 16-bit samples, of which the given number of bits are significant.
+    I42216(u8),
+    /// 4:4:4 planar with 16-bit samples, of which the given number of bits are significant.
+    I44416(u8),
     NV12,
     Indexed(Palette<'a>),
     Rgb24,
@@ -290,6 +446,39 @@ impl<'a> ConvertPixelFormat<PixelFormat<'a>> for PixelFormat<'a> {
                              width,
                              height)
             }
+            (PixelFormat::I422, PixelFormat::I420) => {
+                I422.convert(&I420,
+                             output_pixels,
+                             output_strides,
+                             input_pixels,
+                             input_strides,
+                             width,
+                             height)
+            }
+            (PixelFormat::I444, PixelFormat::I420) => {
+                I444.convert(&I420,
+                             output_pixels,
+                             output_strides,
+                             input_pixels,
+                             input_strides,
+                             width,
+                             height)
+            }
+            (PixelFormat::I42016(bit_depth), PixelFormat::I420) |
+            (PixelFormat::I42216(bit_depth), PixelFormat::I420) |
+            (PixelFormat::I44416(bit_depth), PixelFormat::I420) => {
+                let source = HighBitDepth {
+                    chroma_shift: self.chroma_shift().unwrap(),
+                    bit_depth: bit_depth,
+                };
+                source.convert(&I420,
+                               output_pixels,
+                               output_strides,
+                               input_pixels,
+                               input_strides,
+                               width,
+                               height)
+            }
             (PixelFormat::NV12, PixelFormat::I420) => {
                 NV12.convert(&I420,
                              output_pixels,
@@ -335,10 +524,22 @@ impl<'a> PixelFormat<'a> {
     /// Returns the number of planes in this pixel format.
     pub fn planes(&self) -> usize {
         match *self {
-            PixelFormat::I420 => 3,
+            PixelFormat::I420 | PixelFormat::I422 | PixelFormat::I444 |
+            PixelFormat::I42016(_) | PixelFormat::I42216(_) | PixelFormat::I44416(_) => 3,
             PixelFormat::NV12 => 2,
             PixelFormat::Indexed(_) | PixelFormat::Rgb24 => 1,
         }
     }
+
+    /// Returns how many times the chroma planes of this pixel format are subsampled horizontally
+    /// and vertically, as powers of two, or `None` if it isn't planar YUV.
+    pub fn chroma_shift(&self) -> Option<(usize, usize)> {
+        match *self {
+            PixelFormat::I420 | PixelFormat::I42016(_) => Some((1, 1)),
+            PixelFormat::I422 | PixelFormat::I42216(_) => Some((1, 0)),
+            PixelFormat::I444 | PixelFormat::I44416(_) => Some((0, 0)),
+            PixelFormat::NV12 | PixelFormat::Indexed(_) | PixelFormat::Rgb24 => None,
+        }
+    }
 }
 
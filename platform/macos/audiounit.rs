@@ -10,6 +10,7 @@
 #![allow(missing_copy_implementations)]
 
 use audiodecoder;
+use error::MediaError;
 use platform::macos::coreaudio::{kAudioFormatFlagIsFloat, kAudioFormatFlagIsPacked};
 use platform::macos::coreaudio::{kLinearPCMFormatFlagIsNonInterleaved, AudioBuffer};
 use platform::macos::coreaudio::{AudioBufferList, AudioBufferListRef, AudioStreamBasicDescription};
@@ -337,17 +338,20 @@ struct AudioDecoderInfoImpl {
 
 impl AudioDecoderInfoImpl {
     fn new(headers: &audiodecoder::AudioHeaders, _: f64, _: u16)
-           -> Box<audiodecoder::AudioDecoderInfo + 'static> {
-        let headers = headers.aac_headers().unwrap();
-        Box::new(AudioDecoderInfoImpl {
+           -> Result<Box<audiodecoder::AudioDecoderInfo + 'static>,MediaError> {
+        let headers = match headers.aac_headers() {
+            Some(headers) => headers,
+            None => return Err(MediaError::Malformed),
+        };
+        Ok(Box::new(AudioDecoderInfoImpl {
             esds_chunk: headers.esds_chunk.iter().map(|x| *x).collect(),
-        }) as Box<audiodecoder::AudioDecoderInfo + 'static>
+        }) as Box<audiodecoder::AudioDecoderInfo + 'static>)
     }
 }
 
 impl audiodecoder::AudioDecoderInfo for AudioDecoderInfoImpl {
     fn create_decoder(mut self: Box<AudioDecoderInfoImpl>)
-                      -> Box<audiodecoder::AudioDecoder + 'static> {
+                      -> Result<Box<audiodecoder::AudioDecoder + 'static>,MediaError> {
         let description = AudioComponentDescription {
             component_type: fourcc(b"adec"),
             component_subtype: fourcc(b"aac "),
@@ -355,24 +359,30 @@ impl audiodecoder::AudioDecoderInfo for AudioDecoderInfoImpl {
             component_flags: 0,
             component_flags_mask: 0,
         };
-        let component = AudioComponent::find_next(None, &description).unwrap();
-        let codec = AudioCodec::new(&component).unwrap();
+        let component = match AudioComponent::find_next(None, &description) {
+            Some(component) => component,
+            None => return Err(MediaError::UnsupportedCodec),
+        };
+        let codec = match AudioCodec::new(&component) {
+            Ok(codec) => codec,
+            Err(err) => return Err(audio_toolbox_error(err)),
+        };
         let mut input_formats =
-            if let Ok(AudioCodecProperty::SupportedInputFormats(formats)) =
-                    codec.get_property(AudioCodecPropertyId::SupportedInputFormats) {
-                formats
-            } else {
-                panic!("failed to request input formats")
+            match codec.get_property(AudioCodecPropertyId::SupportedInputFormats) {
+                Ok(AudioCodecProperty::SupportedInputFormats(formats)) => formats,
+                Ok(_) => return Err(MediaError::UnsupportedCodec),
+                Err(err) => return Err(audio_toolbox_error(err)),
             };
         let mut output_formats =
-            if let Ok(AudioCodecProperty::SupportedOutputFormats(formats)) =
-                    codec.get_property(AudioCodecPropertyId::SupportedOutputFormats) {
-                formats
-            } else {
-                panic!("failed to request output formats")
+            match codec.get_property(AudioCodecPropertyId::SupportedOutputFormats) {
+                Ok(AudioCodecProperty::SupportedOutputFormats(formats)) => formats,
+                Ok(_) => return Err(MediaError::UnsupportedCodec),
+                Err(err) => return Err(audio_toolbox_error(err)),
             };
-        codec.set_property(AudioCodecProperty::MagicCookie(mem::replace(&mut self.esds_chunk,
-                                                                        Vec::new()))).unwrap();
+        let magic_cookie = mem::replace(&mut self.esds_chunk, Vec::new());
+        if let Err(err) = codec.set_property(AudioCodecProperty::MagicCookie(magic_cookie)) {
+            return Err(audio_toolbox_error(err))
+        }
         input_formats = input_formats.into_iter().filter(|input_format| {
             input_format.channels_per_frame == 6
         }).collect();
@@ -382,10 +392,15 @@ impl audiodecoder::AudioDecoderInfo for AudioDecoderInfoImpl {
             (output_format.format_flags & flags) == flags &&
                 output_format.channels_per_frame == 6
         }).collect();
-        codec.initialize(&input_formats[0], &output_formats[0], &[]).unwrap();
-        Box::new(AudioDecoderImpl {
+        if input_formats.is_empty() || output_formats.is_empty() {
+            return Err(MediaError::UnsupportedCodec)
+        }
+        if let Err(err) = codec.initialize(&input_formats[0], &output_formats[0], &[]) {
+            return Err(audio_toolbox_error(err))
+        }
+        Ok(Box::new(AudioDecoderImpl {
             codec: codec,
-        }) as Box<audiodecoder::AudioDecoder + 'static>
+        }) as Box<audiodecoder::AudioDecoder + 'static>)
     }
 }
 
@@ -394,7 +409,7 @@ pub struct AudioDecoderImpl {
 }
 
 impl audiodecoder::AudioDecoder for AudioDecoderImpl {
-    fn decode(&mut self, data: &[u8]) -> Result<(),()> {
+    fn decode(&mut self, data: &[u8]) -> Result<(),MediaError> {
         let length = data.len();
         assert!(length <= (u32::MAX as usize));
         let data: Vec<u8> = data.iter().map(|x| *x).collect();
@@ -406,13 +421,13 @@ impl audiodecoder::AudioDecoder for AudioDecoderImpl {
             },
         ]).result {
             Ok(_) => Ok(()),
-            Err(_) => Err(()),
+            Err(err) => Err(audio_toolbox_error(err)),
         };
         result
     }
 
     fn decoded_samples<'a>(&'a mut self)
-                           -> Result<Box<audiodecoder::DecodedAudioSamples + 'a>,()> {
+                           -> Result<Box<audiodecoder::DecodedAudioSamples + 'a>,MediaError> {
         let packet_frame_size =
             if let Ok(AudioCodecProperty::PacketFrameSize(packet_frame_size)) =
                     self.codec.get_property(AudioCodecPropertyId::PacketFrameSize) {
@@ -436,8 +451,8 @@ impl audiodecoder::AudioDecoder for AudioDecoderImpl {
         ];
         let mut output_buffer_list = AudioBufferList::new(&mut output_buffers);
         let result = self.codec.produce_output_buffer_list(&mut output_buffer_list, 1024);
-        if result.result.is_err() {
-            return Err(())
+        if let Err(err) = result.result {
+            return Err(audio_toolbox_error(err))
         }
         Ok(Box::new(DecodedAudioSamplesImpl {
             output_buffer_list: output_buffer_list,
@@ -462,6 +477,10 @@ impl audiodecoder::DecodedAudioSamples for DecodedAudioSamplesImpl {
     }
 }
 
+fn audio_toolbox_error(err: OSStatus) -> MediaError {
+    MediaError::backend(err as i64, "AudioToolbox")
+}
+
 fn fourcc(id: &[u8]) -> OSType {
     ((id[0] as u32) << 24) | ((id[1] as u32) << 16) | ((id[2] as u32) << 8) | (id[3] as u32)
 }
@@ -9,13 +9,15 @@
 
 #![allow(non_upper_case_globals)]
 
-use timing::Timestamp;
+use timing::{self, TimeBase, Timestamp};
 
 use core_foundation::base::{Boolean, CFRelease, CFRetain, CFTypeID, CFTypeRef, TCFType};
 use core_foundation::base::{kCFAllocatorDefault};
 use core_foundation::dictionary::CFDictionary;
 use core_foundation::string::CFString;
 use libc::{c_long, c_void, size_t};
+use std::cmp;
+use std::i32;
 use std::mem;
 use std::ptr;
 
@@ -49,19 +51,24 @@ impl CMTime {
     }
 
     pub fn from_timestamp(timestamp: &Timestamp) -> CMTime {
+        // Core Media time bases are always one tick over a signed 32-bit time scale.
+        let denominator = timestamp.time_base.denominator();
+        let time_base = if denominator <= i32::MAX as u32 {
+            TimeBase::new(1, denominator)
+        } else {
+            timing::NANOSECONDS
+        };
         CMTime {
-            value: timestamp.ticks,
-            timescale: timestamp.ticks_per_second as i32,
+            value: timestamp.rescale(time_base).ticks,
+            timescale: time_base.denominator() as i32,
             flags: kCMTimeFlags_Valid,
             epoch: 0,
         }
     }
 
     pub fn as_timestamp(&self) -> Timestamp {
-        Timestamp {
-            ticks: self.value as i64,
-            ticks_per_second: self.timescale as f64,
-        }
+        // Invalid times have a zero time scale; treat it as one tick per second.
+        Timestamp::new(self.value as i64, TimeBase::new(1, cmp::max(self.timescale, 1) as u32))
     }
 
     pub fn nanoseconds(&self) -> i64 {
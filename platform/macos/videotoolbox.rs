@@ -8,6 +8,7 @@
 // except according to those terms.
 
 use codecs::h264;
+use error::MediaError;
 use platform::macos::coremedia::{self, CMBlockBuffer, CMFormatDescription, CMSampleBuffer};
 use platform::macos::coremedia::{CMSampleTimingInfo, CMTime, OSStatus, kCMVideoCodecType_H264};
 use platform::macos::corevideo::{CVBuffer, DecodedFrameImpl};
@@ -164,7 +165,7 @@ struct VideoDecoderImpl {
 
 impl VideoDecoderImpl {
     fn new(headers: &videodecoder::VideoHeaders, width: i32, height: i32)
-           -> Result<Box<videodecoder::VideoDecoder + 'static>,()> {
+           -> Result<Box<videodecoder::VideoDecoder + 'static>,MediaError> {
         // Create the video format description.
         let avcc = h264::create_avcc_chunk(headers);
         let avcc = CFData::from_buffer(avcc.as_slice());
@@ -183,7 +184,7 @@ impl VideoDecoderImpl {
                                                                     height,
                                                                     &extensions) {
                 Ok(format_description) => format_description,
-                Err(_) => return Err(()),
+                Err(err) => return Err(MediaError::backend(err as i64, "CoreMedia")),
             };
 
         // Create a decompression session.
@@ -199,20 +200,20 @@ impl VideoDecoderImpl {
                     output_buffer: output_buffer,
                 }) as Box<videodecoder::VideoDecoder + 'static>)
             }
-            Err(_) => Err(()),
+            Err(err) => Err(MediaError::backend(err as i64, "VideoToolbox")),
         }
     }
 }
 
 impl videodecoder::VideoDecoder for VideoDecoderImpl {
     fn decode_frame(&self, data: &[u8], presentation_time: &Timestamp)
-                    -> Result<Box<videodecoder::DecodedVideoFrame + 'static>,()> {
+                    -> Result<Box<videodecoder::DecodedVideoFrame + 'static>,MediaError> {
         let block_buffer = match CMBlockBuffer::from_memory_block(data.len() as u64) {
             Ok(block_buffer) => block_buffer,
-            Err(_) => return Err(()),
+            Err(err) => return Err(MediaError::backend(err as i64, "CoreMedia")),
         };
-        if block_buffer.replace_data_bytes(data, 0).is_err() {
-            return Err(())
+        if let Err(err) = block_buffer.replace_data_bytes(data, 0) {
+            return Err(MediaError::backend(err as i64, "CoreMedia"))
         }
 
         let sample_timing_info = CMSampleTimingInfo {
@@ -227,16 +228,19 @@ impl videodecoder::VideoDecoder for VideoDecoderImpl {
                                                       1,
                                                       &[sample_timing_info]) {
             Ok(sample_buffer) => sample_buffer,
-            Err(_) => return Err(()),
+            Err(err) => return Err(MediaError::backend(err as i64, "CoreMedia")),
         };
 
-        if self.session.decode_frame(&sample_buffer, 0).is_err() {
-            return Err(())
+        if let Err(err) = self.session.decode_frame(&sample_buffer, 0) {
+            return Err(MediaError::backend(err as i64, "VideoToolbox"))
         }
         let output_buffer = self.output_buffer.borrow();
-        let output_buffer = output_buffer.as_ref().unwrap();
+        let output_buffer = match output_buffer.as_ref() {
+            Some(output_buffer) => output_buffer,
+            None => return Err(MediaError::NeedMoreData),
+        };
         if output_buffer.status != 0 {
-            return Err(())
+            return Err(MediaError::backend(output_buffer.status as i64, "VideoToolbox"))
         }
         Ok(Box::new(DecodedFrameImpl::new(output_buffer.buffer.clone(),
                                           output_buffer.presentation_timestamp)) as
@@ -7,31 +7,42 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use audiodecoder::{AudioDecoder, AudioDecoderInfo, RegisteredAudioDecoder};
-use container::{AudioTrack, ContainerReader, Frame, RegisteredContainerReader, Track, TrackType};
-use container::{VideoTrack};
+use audiodecoder::{AudioDecoder, RegisteredAudioDecoder};
+use container::{AudioTrack, ContainerReader, Frame, RegisteredContainerReader, SubtitleCue};
+use container::{Track, TrackType, VideoTrack};
+use error::MediaError;
+use registry::Registry;
 use streaming::StreamReader;
 use timing::Timestamp;
 use videodecoder::{DecodedVideoFrame, RegisteredVideoDecoder, VideoDecoder};
 
-use libc::{c_int, c_long};
+use libc::{c_double, c_int, c_long};
 use std::iter;
 use std::mem;
-use std::num::SignedInt;
+use std::rc::Rc;
+use std::time::duration::Duration;
 
 /// A simple video/audio player.
 pub struct Player<'a> {
     /// The container.
     pub reader: Box<ContainerReader + 'static>,
+    /// The container readers and decoders available to this player.
+    registry: Rc<Registry>,
     /// Information about the video track that's playing.
     video: Option<VideoPlayerInfo>,
     /// Information about the audio track that's playing.
     audio: Option<AudioPlayerInfo>,
+    /// The number of the subtitle track to show, if there is one that we can read.
+    subtitle_track_number: Option<i64>,
+    /// Subtitle cues that are showing or have yet to start, in start order.
+    subtitle_cues: Vec<SubtitleCue>,
+    /// The index of the next cluster to read subtitle cues from.
+    subtitle_cluster_index: i32,
     /// The index of the current cluster.
     cluster_index: i32,
     /// The calculated delay between video frames (if the track contains video) or audio frames (if
     /// the track contains only audio).
-    frame_delay: Option<i64>,
+    frame_delay: Option<Duration>,
     /// The time at which the last frame was played.
     last_frame_presentation_time: Option<Timestamp>,
     /// The time at which the next frame is to be played.
@@ -39,54 +50,106 @@ pub struct Player<'a> {
 }
 
 impl<'a> Player<'a> {
-    pub fn new<'b>(reader: Box<StreamReader>, mime_type: &str) -> Player<'b> {
-        let mut reader = RegisteredContainerReader::get(mime_type.as_slice()).unwrap()
-                                                                             .new(reader)
-                                                                             .unwrap();
+    /// Creates a player for the given stream using the built-in container readers and decoders.
+    /// The container format is determined by the MIME type, if supplied; if there is none or no
+    /// container reader handles it, the first few bytes of the stream are sniffed instead.
+    pub fn new<'b>(reader: Box<StreamReader>, mime_type: Option<&str>)
+                   -> Result<Player<'b>,MediaError> {
+        Player::with_registry(Rc::new(Registry::new()), reader, mime_type)
+    }
 
-        let (video_player_info, audio_player_info) = {
-            let (video_codec, audio_codec) =
-                read_track_metadata_and_initialize_codecs(&mut *reader);
+    /// Creates a player for the given stream, looking up container readers and decoders in
+    /// `registry`.
+    pub fn with_registry<'b>(registry: Rc<Registry>,
+                             mut reader: Box<StreamReader>,
+                             mime_type: Option<&str>)
+                             -> Result<Player<'b>,MediaError> {
+        let reader = {
+            let container_reader = match mime_type.map(|mime_type| {
+                RegisteredContainerReader::get(&*registry, mime_type)
+            }) {
+                Some(Ok(container_reader)) => container_reader,
+                Some(Err(_)) | None => {
+                    try!(RegisteredContainerReader::sniff(&*registry, &mut *reader))
+                }
+            };
+            try!(container_reader.new(reader))
+        };
 
-            let (mut video_track, mut audio_track) = (None, None);
+        let (video_player_info, audio_player_info, subtitle_track_number) = {
+            let (mut video_track, mut audio_track, mut subtitle_track_number) = (None, None, None);
             for track_index in 0..reader.track_count() {
                 let track = reader.track_by_index(track_index);
                 if track.track_type() == TrackType::Video && video_track.is_none() {
                     video_track = Some(track)
                 } else if track.track_type() == TrackType::Audio && audio_track.is_none() {
                     audio_track = Some(track)
+                } else if track.track_type() == TrackType::Subtitle &&
+                        subtitle_track_number.is_none() && track.as_subtitle_track().is_ok() {
+                    subtitle_track_number = Some(track.number() as i64)
                 }
             }
 
-            (video_track.map(|video_track| {
-                VideoPlayerInfo {
-                    codec: video_codec.unwrap(),
-                    track_number: video_track.number() as i64,
-                    frames: Vec::new(),
-                    frame_index: 0,
+            let video_player_info = match video_track {
+                None => None,
+                Some(video_track) => {
+                    let video_track = video_track.as_video_track().unwrap();
+                    let (decoder_index, codec) =
+                        try!(create_video_decoder(&*registry, &*video_track, 0));
+                    Some(VideoPlayerInfo {
+                        codec: codec,
+                        decoder_index: decoder_index,
+                        fallback_allowed: true,
+                        track_number: video_track.number() as i64,
+                        frames: Vec::new(),
+                        frame_index: 0,
+                    })
                 }
-            }), audio_track.map(|audio_track| {
-                AudioPlayerInfo {
-                    codec: audio_codec.unwrap(),
-                    track_number: audio_track.number() as i64,
-                    samples: None,
-                    frame_index: 0,
+            };
+            let audio_player_info = match audio_track {
+                None => None,
+                Some(audio_track) => {
+                    let audio_track = audio_track.as_audio_track().unwrap();
+                    let (decoder_index, codec) =
+                        try!(create_audio_decoder(&*registry, &*audio_track, 0, false));
+                    Some(AudioPlayerInfo {
+                        codec: codec,
+                        decoder_index: decoder_index,
+                        fallback_allowed: true,
+                        mid_stream: false,
+                        sampling_rate: audio_track.sampling_rate(),
+                        channels: audio_track.channels(),
+                        track_number: audio_track.number() as i64,
+                        samples: None,
+                        frame_index: 0,
+                    })
                 }
-            }))
+            };
+            (video_player_info, audio_player_info, subtitle_track_number)
         };
 
-        Player {
+        Ok(Player {
             reader: reader,
+            registry: registry,
             video: video_player_info,
             audio: audio_player_info,
+            subtitle_track_number: subtitle_track_number,
+            subtitle_cues: Vec::new(),
+            subtitle_cluster_index: 0,
             cluster_index: 0,
             frame_delay: None,
             last_frame_presentation_time: None,
             next_frame_presentation_time: None,
-        }
+        })
     }
 
-    pub fn decode_frame(&mut self) -> Result<(),()> {
+    /// Decodes up to the next frame to be shown.
+    ///
+    /// If the stream is still downloading, this may return `MediaError::NeedMoreData`. Everything
+    /// decoded so far is kept, so call this again once more of the stream has arrived. If the audio
+    /// format changes partway through the stream, this returns `MediaError::AudioFormatChanged`.
+    pub fn decode_frame(&mut self) -> Result<(),MediaError> {
+        let registry = &*self.registry;
         let reader = &mut *self.reader;
         let video_track = self.video.as_ref().map(|video| {
             reader.track_by_number(video.track_number as c_long)
@@ -98,19 +161,9 @@ impl<'a> Player<'a> {
         let audio_track = audio_track.as_ref().map(|track| track.as_audio_track().unwrap());
         'clusterloop: loop {
             let cluster = match (&video_track, &audio_track) {
-                (&Some(ref video_track), _) => {
-                    match video_track.cluster(self.cluster_index) {
-                        Ok(cluster) => cluster,
-                        Err(_) => return Err(()),
-                    }
-                }
-                (&None, &Some(ref audio_track)) => {
-                    match audio_track.cluster(self.cluster_index) {
-                        Ok(cluster) => cluster,
-                        Err(_) => return Err(()),
-                    }
-                }
-                (&None, &None) => return Err(()),
+                (&Some(ref video_track), _) => try!(video_track.cluster(self.cluster_index)),
+                (&None, &Some(ref audio_track)) => try!(audio_track.cluster(self.cluster_index)),
+                (&None, &None) => return Err(MediaError::EndOfStream),
             };
 
             // Read the video frame or frames.
@@ -123,13 +176,16 @@ impl<'a> Player<'a> {
                             }
                         }
                         Some(frame_delay) => {
+                            // Stop once we have the frame one frame delay after the last one (give
+                            // or take half a frame), or one so far ahead that the stream must have
+                            // a gap in it.
                             let last_frame_time = self.last_frame_presentation_time.unwrap();
+                            let tolerance = frame_delay / 2;
                             if video.frames.iter().any(|frame| {
-                                let last_frame_time = last_frame_time.ticks;
-                                let delta = frame.presentation_time().ticks - (last_frame_time +
-                                                                               frame_delay);
-                                let is_next_frame = delta.abs() < 5;
-                                let is_in_far_future = delta > 1000;
+                                let delta = (frame.presentation_time() - last_frame_time) -
+                                    frame_delay;
+                                let is_next_frame = -tolerance <= delta && delta <= tolerance;
+                                let is_in_far_future = delta > Duration::seconds(1);
                                 is_next_frame || is_in_far_future
                             }) {
                                 break
@@ -140,9 +196,12 @@ impl<'a> Player<'a> {
                     // Read a video frame.
                     match cluster.read_frame(video.frame_index, video.track_number as c_long) {
                         Ok(frame) => {
-                            decode_video_frame(&mut *video.codec, &*frame, &mut video.frames)
+                            try!(decode_video_frame(registry,
+                                                    &**video_track.as_ref().unwrap(),
+                                                    video,
+                                                    &*frame))
                         }
-                        Err(_) => {
+                        Err(MediaError::EndOfStream) => {
                             self.cluster_index += 1;
                             video.frame_index = 0;
                             if let Some(ref mut audio) = self.audio {
@@ -150,6 +209,7 @@ impl<'a> Player<'a> {
                             }
                             continue 'clusterloop
                         }
+                        Err(err) => return Err(err),
                     }
 
                     video.frame_index += 1;
@@ -160,7 +220,7 @@ impl<'a> Player<'a> {
                         let mut i = 0;
                         while i < video.frames.len() {
                             let frame_time = video.frames[i].presentation_time();
-                            if last_frame_time.ticks <= frame_time.ticks {
+                            if last_frame_time <= frame_time {
                                 i += 1
                             } else {
                                 video.frames.remove(i);
@@ -171,7 +231,7 @@ impl<'a> Player<'a> {
 
                 // Determine when the video frame is to be shown.
                 self.next_frame_presentation_time =
-                    match video.frames.iter().min_by(|frame| frame.presentation_time().ticks) {
+                    match video.frames.iter().min_by(|frame| frame.presentation_time()) {
                         None => continue,
                         Some(frame) => Some(frame.presentation_time()),
                     };
@@ -179,26 +239,28 @@ impl<'a> Player<'a> {
 
             // Read the audio frame or frames.
             if let Some(ref mut audio) = self.audio {
-                audio.samples = Some(iter::repeat(Vec::new()).take(audio_track.as_ref()
-                                                                              .unwrap()
-                                                                              .channels() as usize)
-                                                             .collect());
+                // Keep any samples decoded before we last ran out of data.
+                if audio.samples.is_none() {
+                    let channels = audio_track.as_ref().unwrap().channels() as usize;
+                    audio.samples = Some(iter::repeat(Vec::new()).take(channels).collect());
+                }
                 loop {
                     let frame = match cluster.read_frame(audio.frame_index,
                                                          audio.track_number as c_long) {
-                        Err(_) => break,
+                        Err(MediaError::EndOfStream) => break,
+                        Err(err) => return Err(err),
                         Ok(frame) => frame,
                     };
-                    decode_audio_frame(&mut *audio.codec,
-                                       &*frame,
-                                       audio.samples.as_mut().unwrap().as_mut_slice());
+                    try!(decode_audio_frame(registry,
+                                            &**audio_track.as_ref().unwrap(),
+                                            audio,
+                                            &*frame));
                     audio.frame_index += 1;
 
                     // If there is a video track, we synchronize to it. Otherwise, read just one
                     // audio frame.
                     if self.video.is_some() {
-                        if frame.time().duration() >=
-                                self.next_frame_presentation_time.unwrap().duration() {
+                        if frame.time() >= self.next_frame_presentation_time.unwrap() {
                             break
                         }
                     } else {
@@ -212,6 +274,77 @@ impl<'a> Player<'a> {
         }
     }
 
+    /// Seeks to the given time. Playback restarts from the nearest preceding keyframe, and frames
+    /// are decoded and dropped until the next frame is at or after `time`.
+    pub fn seek(&mut self, time: Timestamp) -> Result<(),MediaError> {
+        // Find the keyframe in the track that drives playback.
+        let track_number = match (&self.video, &self.audio) {
+            (&Some(ref video), _) => video.track_number,
+            (&None, &Some(ref audio)) => audio.track_number,
+            (&None, &None) => return Err(MediaError::EndOfStream),
+        };
+        let position = try!(self.reader.track_by_number(track_number as c_long).seek(&time));
+
+        // Audio is read from the same cluster as the video, so start it at the video keyframe if
+        // it's in that cluster and at the beginning of the cluster otherwise.
+        let audio_frame_index = match self.audio {
+            Some(ref audio) if self.video.is_some() => {
+                let audio_track = self.reader.track_by_number(audio.track_number as c_long);
+                match audio_track.seek(&position.time) {
+                    Ok(audio_position) if audio_position.cluster_index ==
+                            position.cluster_index => audio_position.frame_index,
+                    Ok(_) | Err(MediaError::EndOfStream) => 0,
+                    Err(err) => return Err(err),
+                }
+            }
+            _ => position.frame_index,
+        };
+
+        // Throw away everything buffered and start over with fresh codecs, since their state
+        // refers to the old position.
+        if let Some(ref mut video) = self.video {
+            let video_track = self.reader.track_by_number(video.track_number as c_long);
+            let video_track = video_track.as_video_track().unwrap();
+            let (decoder_index, codec) =
+                try!(create_video_decoder(&*self.registry, &*video_track, video.decoder_index));
+            video.codec = codec;
+            video.decoder_index = decoder_index;
+            video.frames.clear();
+            video.frame_index = position.frame_index;
+        }
+        if let Some(ref mut audio) = self.audio {
+            let audio_track = self.reader.track_by_number(audio.track_number as c_long);
+            let audio_track = audio_track.as_audio_track().unwrap();
+            let mid_stream = position.cluster_index != 0 || audio_frame_index != 0;
+            let (decoder_index, codec) = try!(create_audio_decoder(&*self.registry,
+                                                                   &*audio_track,
+                                                                   audio.decoder_index,
+                                                                   mid_stream));
+            audio.codec = codec;
+            audio.decoder_index = decoder_index;
+            audio.mid_stream = mid_stream;
+            audio.samples = None;
+            audio.frame_index = audio_frame_index;
+        }
+        self.cluster_index = position.cluster_index;
+        self.subtitle_cues.clear();
+        self.subtitle_cluster_index = 0;
+        self.frame_delay = None;
+        self.last_frame_presentation_time = None;
+        self.next_frame_presentation_time = None;
+
+        // Decode forward from the keyframe, dropping frames until we reach the target.
+        loop {
+            try!(self.decode_frame());
+            if let Some(next_frame_presentation_time) = self.next_frame_presentation_time {
+                if next_frame_presentation_time >= time {
+                    return Ok(())
+                }
+            }
+            try!(self.advance());
+        }
+    }
+
     /// Returns the number of the video track, if present.
     pub fn video_track_number(&self) -> Option<i64> {
         self.video.as_ref().map(|video| video.track_number)
@@ -222,6 +355,53 @@ impl<'a> Player<'a> {
         self.audio.as_ref().map(|audio| audio.track_number)
     }
 
+    /// Returns the number of the subtitle track, if present.
+    pub fn subtitle_track_number(&self) -> Option<i64> {
+        self.subtitle_track_number
+    }
+
+    /// Returns the subtitle cues that should be showing along with the last frame.
+    pub fn subtitle_cues(&mut self) -> Result<Vec<SubtitleCue>,MediaError> {
+        let (track_number, time) =
+            match (self.subtitle_track_number, self.last_frame_presentation_time) {
+                (Some(track_number), Some(time)) => (track_number, time),
+                _ => return Ok(Vec::new()),
+            };
+
+        // Pick up the cues of every cluster we've reached since the last call. After a seek, cues
+        // from earlier clusters may still be showing, so those are read again from the start.
+        {
+            let track = self.reader.track_by_number(track_number as c_long);
+            let subtitle_track = track.as_subtitle_track().unwrap();
+            while self.subtitle_cluster_index <= self.cluster_index {
+                match subtitle_track.cues(self.subtitle_cluster_index) {
+                    Ok(cues) => self.subtitle_cues.extend(cues.into_iter()),
+                    Err(MediaError::EndOfStream) => {}
+                    Err(err) => return Err(err),
+                }
+                self.subtitle_cluster_index += 1
+            }
+        }
+
+        // Forget cues that have finished. Cues with no end stay up until the next one starts.
+        let mut active_cues = Vec::new();
+        for (index, cue) in self.subtitle_cues.iter().enumerate() {
+            let end = cue.end.or_else(|| {
+                self.subtitle_cues.get(index + 1).map(|next_cue| next_cue.start)
+            });
+            if end.map_or(true, |end| time < end) {
+                active_cues.push(cue.clone())
+            }
+        }
+        self.subtitle_cues = active_cues;
+
+        Ok(self.subtitle_cues
+               .iter()
+               .filter(|cue| cue.start <= time)
+               .map(|cue| cue.clone())
+               .collect())
+    }
+
     /// Returns the presentation time of the last frame, relative to the start of playback.
     pub fn last_frame_presentation_time(&self) -> Option<Timestamp> {
         self.last_frame_presentation_time
@@ -233,11 +413,10 @@ impl<'a> Player<'a> {
     }
     
     /// Retrieves the decoded frame data and advances to the next frame.
-    pub fn advance(&mut self) -> Result<DecodedFrame,()> {
+    pub fn advance(&mut self) -> Result<DecodedFrame,MediaError> {
         // Determine the frame delay, if possible.
         if let Some(last_frame_time) = self.last_frame_presentation_time {
-            self.frame_delay = Some(self.next_frame_presentation_time.unwrap().ticks -
-                                    last_frame_time.ticks);
+            self.frame_delay = Some(self.next_frame_presentation_time.unwrap() - last_frame_time);
         }
 
         // Record the current time.
@@ -249,8 +428,8 @@ impl<'a> Player<'a> {
                 match video.frames
                            .iter()
                            .enumerate()
-                           .min_by(|&(_, frame)| frame.presentation_time().ticks) {
-                    None => return Err(()),
+                           .min_by(|&(_, frame)| frame.presentation_time()) {
+                    None => return Err(MediaError::NeedMoreData),
                     Some((index, _)) => Some(index),
                 }
             }
@@ -273,6 +452,11 @@ impl<'a> Player<'a> {
 struct VideoPlayerInfo {
     /// The video codec.
     codec: Box<VideoDecoder + 'static>,
+    /// The position of `codec` among the registered decoders for this track's codec.
+    decoder_index: usize,
+    /// Whether the next registered decoder should be tried if `codec` fails. This is cleared once
+    /// `codec` has accepted a frame.
+    fallback_allowed: bool,
     /// The number of the video track.
     track_number: i64,
     /// Buffered video frames to be displayed.
@@ -285,6 +469,17 @@ struct VideoPlayerInfo {
 struct AudioPlayerInfo {
     /// The audio codec.
     codec: Box<AudioDecoder + 'static>,
+    /// The position of `codec` among the registered decoders for this track's codec.
+    decoder_index: usize,
+    /// Whether the next registered decoder should be tried if `codec` fails. This is cleared once
+    /// `codec` has accepted a frame.
+    fallback_allowed: bool,
+    /// Whether `codec` started decoding partway through the stream, after a seek.
+    mid_stream: bool,
+    /// The sampling rate that the output was configured with.
+    sampling_rate: c_double,
+    /// The number of channels that the output was configured with.
+    channels: u16,
     /// The number of the audio track.
     track_number: i64,
     /// Buffered audio samples to be played, in planar format.
@@ -298,70 +493,189 @@ pub struct DecodedFrame {
     pub audio_samples: Option<Vec<Vec<f32>>>,
 }
 
-fn read_track_metadata_and_initialize_codecs(reader: &mut ContainerReader)
-                                             -> (Option<Box<VideoDecoder + 'static>>,
-                                                 Option<Box<AudioDecoder + 'static>>) {
-    let (mut video_codec, mut audio_codec) = (None, None);
-    for track_index in 0..reader.track_count() {
-        let track = reader.track_by_index(track_index);
-        match track.track_type() {
-            TrackType::Video => {
-                let video_track = track.as_video_track().unwrap();
-                if let Some(codec) = video_track.codec() {
-                    let headers = video_track.headers();
-                    video_codec = Some(RegisteredVideoDecoder::get(codec.as_slice()).unwrap().new(
-                            &*headers,
-                            video_track.width() as i32,
-                            video_track.height() as i32).unwrap());
-                }
+/// Creates a decoder for the given video track by trying each registered decoder for its codec in
+/// order of preference, skipping the first `first_candidate`. Returns the position of the decoder
+/// that succeeded along with the decoder itself.
+fn create_video_decoder(registry: &Registry, video_track: &VideoTrack, first_candidate: usize)
+                        -> Result<(usize, Box<VideoDecoder + 'static>),MediaError> {
+    let codec = match video_track.codec() {
+        Some(codec) => codec,
+        None => return Err(MediaError::UnsupportedCodec),
+    };
+    let headers = video_track.headers();
+    let mut last_error = MediaError::UnsupportedCodec;
+    let candidates = RegisteredVideoDecoder::get_all(registry, codec.as_slice());
+    for (index, decoder) in candidates.into_iter().enumerate().skip(first_candidate) {
+        match decoder.new(&*headers, video_track.width() as i32, video_track.height() as i32) {
+            Ok(decoder) => return Ok((index, decoder)),
+            Err(err) => last_error = err,
+        }
+    }
+    Err(last_error)
+}
+
+/// Creates a decoder for the given audio track. See `create_video_decoder()`. If `mid_stream` is
+/// true, the decoder is told that decoding starts partway through the stream.
+fn create_audio_decoder(registry: &Registry,
+                        audio_track: &AudioTrack,
+                        first_candidate: usize,
+                        mid_stream: bool)
+                        -> Result<(usize, Box<AudioDecoder + 'static>),MediaError> {
+    let codec = match audio_track.codec() {
+        Some(codec) => codec,
+        None => return Err(MediaError::UnsupportedCodec),
+    };
+    let headers = audio_track.headers();
+    let mut last_error = MediaError::UnsupportedCodec;
+    let candidates = RegisteredAudioDecoder::get_all(registry, codec.as_slice());
+    for (index, decoder) in candidates.into_iter().enumerate().skip(first_candidate) {
+        let info = match decoder.new(&*headers,
+                                     audio_track.sampling_rate(),
+                                     audio_track.channels()) {
+            Ok(info) => info,
+            Err(err) => {
+                last_error = err;
+                continue
             }
-            TrackType::Audio => {
-                let audio_track = track.as_audio_track().unwrap();
-                if let Some(codec) = audio_track.codec() {
-                    let headers = audio_track.headers();
-                    let info = RegisteredAudioDecoder::get(codec.as_slice()).unwrap().new(
-                            &*headers,
-                            audio_track.sampling_rate(),
-                            audio_track.channels());
-                    audio_codec = Some(info.create_decoder());
+        };
+        match info.create_decoder() {
+            Ok(mut decoder) => {
+                if mid_stream {
+                    decoder.start_mid_stream()
                 }
+                return Ok((index, decoder))
             }
-            _ => {}
+            Err(err) => last_error = err,
         }
     }
-    (video_codec, audio_codec)
+    Err(last_error)
 }
 
-fn decode_video_frame(codec: &mut VideoDecoder,
-                      frame: &Frame,
-                      frames: &mut Vec<Box<DecodedVideoFrame + 'static>>) {
-    let mut data = Vec::new();
-    data.resize(frame.len() as usize, 0u8);
-    frame.read(data.as_mut_slice()).unwrap();
+/// Reads and decodes a video frame. The decoder is recreated at a discontinuity. If the decoder
+/// fails before it has accepted any frame, the next registered decoder for the codec is tried.
+/// Otherwise, errors from the codec are not fatal (the frame is simply dropped), but errors reading
+/// from the container are.
+fn decode_video_frame(registry: &Registry,
+                      video_track: &VideoTrack,
+                      video: &mut VideoPlayerInfo,
+                      frame: &Frame)
+                      -> Result<(),MediaError> {
+    if frame.discontinuity() {
+        let (decoder_index, codec) = try!(create_video_decoder(registry, video_track, 0));
+        video.codec = codec;
+        video.decoder_index = decoder_index;
+        video.fallback_allowed = true;
+    }
+
+    let mut buffer = Vec::new();
+    let data = try!(frame_data(frame, &mut buffer));
 
     let frame_presentation_time = frame.time() + frame.rendering_offset();
-    if let Ok(image) = codec.decode_frame(data.as_mut_slice(), &frame_presentation_time) {
-        frames.push(image)
+    loop {
+        match video.codec.decode_frame(data, &frame_presentation_time) {
+            Ok(image) => {
+                video.fallback_allowed = false;
+                video.frames.push(image);
+                return Ok(())
+            }
+            Err(MediaError::NeedMoreData) => {
+                video.fallback_allowed = false;
+                return Ok(())
+            }
+            Err(_) if video.fallback_allowed => {
+                match create_video_decoder(registry, video_track, video.decoder_index + 1) {
+                    Ok((decoder_index, codec)) => {
+                        video.codec = codec;
+                        video.decoder_index = decoder_index;
+                    }
+                    Err(_) => {
+                        // Nothing else can decode this track, so stay with the current decoder.
+                        video.fallback_allowed = false;
+                        return Ok(())
+                    }
+                }
+            }
+            Err(_) => return Ok(()),
+        }
     }
 }
 
-fn decode_audio_frame(codec: &mut AudioDecoder, frame: &Frame, samples: &mut [Vec<f32>]) {
-    let mut data: Vec<u8> = iter::repeat(0).take(frame.len() as usize).collect();
-    frame.read(data.as_mut_slice()).unwrap();
-    if codec.decode(data.as_slice()).is_err() {
-        return
+/// Returns the contents of `frame`, borrowing them from the container reader if possible and
+/// reading them into `buffer` otherwise.
+fn frame_data<'a>(frame: &'a Frame, buffer: &'a mut Vec<u8>) -> Result<&'a [u8],MediaError> {
+    if let Some(data) = frame.data() {
+        return Ok(data)
     }
+    buffer.resize(frame.len() as usize, 0u8);
+    try!(frame.read(buffer.as_mut_slice()));
+    Ok(buffer.as_slice())
+}
 
-    let sample_count = match codec.decoded_samples() {
+/// Reads and decodes an audio frame, appending the samples to `audio.samples`. As with video, the
+/// next registered decoder is tried if the first frame fails, and later errors from the codec are
+/// not fatal. Returns `AudioFormatChanged` if the sampling rate or number of channels differs
+/// after a discontinuity.
+fn decode_audio_frame(registry: &Registry,
+                      audio_track: &AudioTrack,
+                      audio: &mut AudioPlayerInfo,
+                      frame: &Frame)
+                      -> Result<(),MediaError> {
+    // A new section of the stream comes with new headers, so start over with a fresh decoder.
+    // The output can't be reconfigured, so the new section must have the same audio format.
+    if frame.discontinuity() {
+        if audio_track.sampling_rate() != audio.sampling_rate ||
+                audio_track.channels() != audio.channels {
+            return Err(MediaError::AudioFormatChanged)
+        }
+        let (decoder_index, codec) = try!(create_audio_decoder(registry, audio_track, 0, false));
+        audio.codec = codec;
+        audio.decoder_index = decoder_index;
+        audio.fallback_allowed = true;
+        audio.mid_stream = false;
+    }
+
+    let mut buffer = Vec::new();
+    let data = try!(frame_data(frame, &mut buffer));
+    loop {
+        match audio.codec.decode(data) {
+            Ok(()) => {
+                audio.fallback_allowed = false;
+                break
+            }
+            Err(MediaError::NeedMoreData) => {
+                audio.fallback_allowed = false;
+                return Ok(())
+            }
+            Err(_) if audio.fallback_allowed => {
+                match create_audio_decoder(registry,
+                                           audio_track,
+                                           audio.decoder_index + 1,
+                                           audio.mid_stream) {
+                    Ok((decoder_index, codec)) => {
+                        audio.codec = codec;
+                        audio.decoder_index = decoder_index;
+                    }
+                    Err(_) => {
+                        audio.fallback_allowed = false;
+                        return Ok(())
+                    }
+                }
+            }
+            Err(_) => return Ok(()),
+        }
+    }
+
+    let samples = audio.samples.as_mut().unwrap().as_mut_slice();
+    let sample_count = match audio.codec.decoded_samples() {
         Ok(pcm_output) => {
             for channel in range(0, samples.len() as i32) {
                 samples[channel as usize].push_all(pcm_output.samples(channel).unwrap())
             }
             pcm_output.samples(0).unwrap().len()
         }
-        Err(_) => return,
+        Err(_) => return Ok(()),
     };
 
-    codec.acknowledge(sample_count as c_int);
+    audio.codec.acknowledge(sample_count as c_int);
+    Ok(())
 }
-
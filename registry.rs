@@ -0,0 +1,152 @@
+// Copyright 2015 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use audiodecoder::RegisteredAudioDecoder;
+use codecs::vorbis;
+use codecs::vpx;
+use container::RegisteredContainerReader;
+use containers::gif;
+use containers::mkv;
+use containers::mp4;
+use containers::ogg;
+use videodecoder::RegisteredVideoDecoder;
+
+#[cfg(feature="opus")]
+use codecs::opus;
+#[cfg(feature="ffmpeg")]
+use codecs::libavcodec;
+#[cfg(target_os="macos")]
+use platform;
+
+/// The priority at which the built-in container readers and decoders are registered.
+pub const BUILTIN_PRIORITY: i32 = 0;
+
+/// The set of container readers and decoders available for playback.
+///
+/// Lookups consult entries with higher priority first. Entries with equal priority are consulted
+/// in the order they were registered, so an application can override a built-in by registering
+/// its own reader or decoder above `BUILTIN_PRIORITY`, or supply a fallback by registering below
+/// it.
+pub struct Registry {
+    container_readers: Vec<RegisteredContainerReader>,
+    container_reader_priorities: Vec<i32>,
+    video_decoders: Vec<RegisteredVideoDecoder>,
+    video_decoder_priorities: Vec<i32>,
+    audio_decoders: Vec<RegisteredAudioDecoder>,
+    audio_decoder_priorities: Vec<i32>,
+}
+
+impl Registry {
+    /// Creates a registry containing the built-in container readers and decoders.
+    pub fn new() -> Registry {
+        let mut registry = Registry::empty();
+
+        registry.register_container_reader(mkv::CONTAINER_READER, BUILTIN_PRIORITY);
+        registry.register_container_reader(mp4::CONTAINER_READER, BUILTIN_PRIORITY);
+        registry.register_container_reader(gif::CONTAINER_READER, BUILTIN_PRIORITY);
+        registry.register_container_reader(ogg::CONTAINER_READER, BUILTIN_PRIORITY);
+
+        registry.register_video_decoder(vpx::VIDEO_DECODER, BUILTIN_PRIORITY);
+        registry.register_video_decoder(vpx::VP9_VIDEO_DECODER, BUILTIN_PRIORITY);
+        registry.register_video_decoder(gif::VIDEO_DECODER, BUILTIN_PRIORITY);
+        registry.register_audio_decoder(vorbis::AUDIO_DECODER, BUILTIN_PRIORITY);
+        registry.register_opus_decoder();
+        registry.register_ffmpeg_decoders();
+        registry.register_platform_decoders();
+
+        registry
+    }
+
+    /// Creates a registry with nothing in it.
+    pub fn empty() -> Registry {
+        Registry {
+            container_readers: Vec::new(),
+            container_reader_priorities: Vec::new(),
+            video_decoders: Vec::new(),
+            video_decoder_priorities: Vec::new(),
+            audio_decoders: Vec::new(),
+            audio_decoder_priorities: Vec::new(),
+        }
+    }
+
+    pub fn register_container_reader(&mut self,
+                                     container_reader: RegisteredContainerReader,
+                                     priority: i32) {
+        insert_by_priority(&mut self.container_readers,
+                           &mut self.container_reader_priorities,
+                           container_reader,
+                           priority)
+    }
+
+    pub fn register_video_decoder(&mut self, decoder: RegisteredVideoDecoder, priority: i32) {
+        insert_by_priority(&mut self.video_decoders,
+                           &mut self.video_decoder_priorities,
+                           decoder,
+                           priority)
+    }
+
+    pub fn register_audio_decoder(&mut self, decoder: RegisteredAudioDecoder, priority: i32) {
+        insert_by_priority(&mut self.audio_decoders,
+                           &mut self.audio_decoder_priorities,
+                           decoder,
+                           priority)
+    }
+
+    /// Returns the registered container readers, in lookup order.
+    pub fn container_readers<'a>(&'a self) -> &'a [RegisteredContainerReader] {
+        self.container_readers.as_slice()
+    }
+
+    /// Returns the registered video decoders, in lookup order.
+    pub fn video_decoders<'a>(&'a self) -> &'a [RegisteredVideoDecoder] {
+        self.video_decoders.as_slice()
+    }
+
+    /// Returns the registered audio decoders, in lookup order.
+    pub fn audio_decoders<'a>(&'a self) -> &'a [RegisteredAudioDecoder] {
+        self.audio_decoders.as_slice()
+    }
+
+    #[cfg(feature="opus")]
+    fn register_opus_decoder(&mut self) {
+        self.register_audio_decoder(opus::AUDIO_DECODER, BUILTIN_PRIORITY);
+    }
+
+    #[cfg(not(feature="opus"))]
+    fn register_opus_decoder(&mut self) {}
+
+    #[cfg(feature="ffmpeg")]
+    fn register_ffmpeg_decoders(&mut self) {
+        self.register_video_decoder(libavcodec::VIDEO_DECODER, BUILTIN_PRIORITY);
+        self.register_audio_decoder(libavcodec::AUDIO_DECODER, BUILTIN_PRIORITY);
+    }
+
+    #[cfg(not(feature="ffmpeg"))]
+    fn register_ffmpeg_decoders(&mut self) {}
+
+    #[cfg(target_os="macos")]
+    fn register_platform_decoders(&mut self) {
+        self.register_video_decoder(platform::macos::videotoolbox::VIDEO_DECODER,
+                                    BUILTIN_PRIORITY);
+        self.register_audio_decoder(platform::macos::audiounit::AUDIO_DECODER, BUILTIN_PRIORITY);
+    }
+
+    #[cfg(not(target_os="macos"))]
+    fn register_platform_decoders(&mut self) {}
+}
+
+/// Inserts `item` after every existing item whose priority is at least `priority`, keeping
+/// `items` and `priorities` in step.
+fn insert_by_priority<T>(items: &mut Vec<T>, priorities: &mut Vec<i32>, item: T, priority: i32) {
+    let index = priorities.iter()
+                          .position(|&existing_priority| existing_priority < priority)
+                          .unwrap_or(priorities.len());
+    items.insert(index, item);
+    priorities.insert(index, priority);
+}
@@ -7,25 +7,643 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::old_io::fs::File;
-use std::old_io::{Reader, Seek};
+use std::cmp;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, ErrorKind, Read, Seek, SeekFrom};
+use std::iter;
+use std::slice::bytes;
+use std::sync::{Arc, Mutex};
+use std::u64;
 
-pub trait StreamReader : Reader + Seek {
+#[cfg(unix)]
+use libc::{self, c_void, size_t};
+#[cfg(unix)]
+use std::os::unix::AsRawFd;
+#[cfg(unix)]
+use std::ptr;
+#[cfg(unix)]
+use std::slice;
+#[cfg(unix)]
+use std::usize;
+
+/// A seekable source of media data.
+///
+/// Any `std::io` reader that can seek and knows its size can be a stream reader. Implementations
+/// are provided for files, in-memory cursors, and `BufReader`s, boxes, and mutable references
+/// wrapping other stream readers. Any other reader that implements `Read` and `Seek` can be wrapped
+/// in a `SeekableStreamReader`.
+///
+/// Streams that are still arriving (for example, over the network) report fewer bytes available
+/// than their total size, and fail reads past the available data with
+/// `ErrorKind::ResourceUnavailable`. The container readers turn this into
+/// `MediaError::NeedMoreData`, and the operation can be retried once more data has arrived.
+pub trait StreamReader : Read + Seek {
     /// Returns the number of bytes available in this stream.
     fn available_size(&self) -> u64;
     /// Returns the total number of octets in this stream, including those that are not yet
-    /// available.
+    /// available. Streams of unknown length return `u64::MAX`.
     fn total_size(&self) -> u64;
+    /// Returns the entire contents of the stream, if it's all in memory. The Matroska and Ogg
+    /// readers use this to hand out frame data without copying it.
+    fn as_bytes<'a>(&'a self) -> Option<&'a [u8]> {
+        None
+    }
+}
+
+/// Returns true if not all of `reader` has arrived yet, so a failed read from it may succeed once
+/// more data arrives. This only compares the available and total sizes; callers that have the
+/// error from the read should check it for `ErrorKind::ResourceUnavailable` instead.
+pub fn is_waiting_for_data(reader: &StreamReader) -> bool {
+    reader.available_size() < reader.total_size()
 }
 
-/// TODO(pcwalton): Should probably buffer reads, maybe by implementing on BufferedReader<File> or
-/// something.
+/// Reads into `buf` until it's full or the stream ends, returning the number of bytes read. Unlike
+/// a single call to `read()`, a short count means the end of the stream was reached.
+pub fn read_fully(reader: &mut StreamReader, buf: &mut [u8]) -> io::Result<usize> {
+    let mut bytes_read = 0;
+    while bytes_read < buf.len() {
+        match reader.read(&mut buf[bytes_read..]) {
+            Ok(0) => break,
+            Ok(length) => bytes_read += length,
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(bytes_read)
+}
+
+/// Returns the position that `pos` refers to in a stream of the given size, or an error if it's
+/// before the start of the stream.
+fn resolve_seek(pos: SeekFrom, position: u64, total_size: u64) -> io::Result<u64> {
+    let (base, offset) = match pos {
+        SeekFrom::Start(offset) => return Ok(offset),
+        SeekFrom::Current(offset) => (position, offset),
+        SeekFrom::End(offset) => (total_size, offset),
+    };
+    if offset < 0 && ((-offset) as u64) > base {
+        return Err(io::Error::new(ErrorKind::InvalidInput,
+                                  "seek to a negative position",
+                                  None))
+    }
+    Ok((base as i64 + offset) as u64)
+}
+
+/// Reads from a `File` are unbuffered, so every read the demuxers make becomes a system call. Wrap
+/// the file in a `BufferedStreamReader` to avoid that.
 impl StreamReader for File {
     fn available_size(&self) -> u64 {
         self.total_size()
     }
     fn total_size(&self) -> u64 {
-        self.stat().unwrap().size
+        self.metadata().unwrap().len()
+    }
+}
+
+impl StreamReader for Cursor<Vec<u8>> {
+    fn available_size(&self) -> u64 {
+        self.total_size()
+    }
+    fn total_size(&self) -> u64 {
+        self.get_ref().len() as u64
+    }
+    fn as_bytes<'a>(&'a self) -> Option<&'a [u8]> {
+        Some(self.get_ref().as_slice())
+    }
+}
+
+impl<'b> StreamReader for Cursor<&'b [u8]> {
+    fn available_size(&self) -> u64 {
+        self.total_size()
+    }
+    fn total_size(&self) -> u64 {
+        self.get_ref().len() as u64
+    }
+    fn as_bytes<'a>(&'a self) -> Option<&'a [u8]> {
+        Some(*self.get_ref())
     }
 }
 
+impl<R: StreamReader> StreamReader for BufReader<R> {
+    fn available_size(&self) -> u64 {
+        self.get_ref().available_size()
+    }
+    fn total_size(&self) -> u64 {
+        self.get_ref().total_size()
+    }
+    fn as_bytes<'a>(&'a self) -> Option<&'a [u8]> {
+        self.get_ref().as_bytes()
+    }
+}
+
+impl<R: StreamReader + ?Sized> StreamReader for Box<R> {
+    fn available_size(&self) -> u64 {
+        (**self).available_size()
+    }
+    fn total_size(&self) -> u64 {
+        (**self).total_size()
+    }
+    fn as_bytes<'a>(&'a self) -> Option<&'a [u8]> {
+        (**self).as_bytes()
+    }
+}
+
+impl<'b, R: StreamReader + ?Sized> StreamReader for &'b mut R {
+    fn available_size(&self) -> u64 {
+        (**self).available_size()
+    }
+    fn total_size(&self) -> u64 {
+        (**self).total_size()
+    }
+    fn as_bytes<'a>(&'a self) -> Option<&'a [u8]> {
+        (**self).as_bytes()
+    }
+}
+
+/// A read-only memory mapping of a file.
+///
+/// Reads copy straight out of the mapping, and `as_slice()` exposes the whole file without any
+/// copies at all. The mapping stays valid after the file is closed, but the results are undefined
+/// if the file is truncated while it's mapped.
+///
+/// This is only available on Unix.
+#[cfg(unix)]
+pub struct MmapStreamReader {
+    /// The start of the mapping, or null if the file is empty.
+    data: *const u8,
+    /// The length of the mapping, in bytes.
+    length: usize,
+    /// The position of the next read.
+    position: u64,
+}
+
+#[cfg(unix)]
+impl MmapStreamReader {
+    /// Maps the entire contents of `file`.
+    pub fn new(file: &File) -> io::Result<MmapStreamReader> {
+        let length = try!(file.metadata()).len();
+        if length > usize::MAX as u64 {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "file too large to map", None))
+        }
+        let length = length as usize;
+
+        // Empty mappings aren't allowed.
+        if length == 0 {
+            return Ok(MmapStreamReader {
+                data: ptr::null(),
+                length: 0,
+                position: 0,
+            })
+        }
+
+        let data = unsafe {
+            libc::mmap(ptr::null_mut(),
+                       length as size_t,
+                       libc::PROT_READ,
+                       libc::MAP_PRIVATE,
+                       file.as_raw_fd(),
+                       0)
+        };
+        if data == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error())
+        }
+        Ok(MmapStreamReader {
+            data: data as *const u8,
+            length: length,
+            position: 0,
+        })
+    }
+
+    /// Returns the contents of the file.
+    pub fn as_slice<'a>(&'a self) -> &'a [u8] {
+        if self.data.is_null() {
+            return &[]
+        }
+        unsafe {
+            slice::from_raw_buf(&self.data, self.length)
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for MmapStreamReader {
+    fn drop(&mut self) {
+        if !self.data.is_null() {
+            unsafe {
+                libc::munmap(self.data as *mut c_void, self.length as size_t);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Read for MmapStreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.length as u64 {
+            return Ok(0)
+        }
+        let start = self.position as usize;
+        let length = cmp::min(buf.len(), self.length - start);
+        bytes::copy_memory(&mut buf[0..length], &self.as_slice()[start..start + length]);
+        self.position += length as u64;
+        Ok(length)
+    }
+}
+
+#[cfg(unix)]
+impl Seek for MmapStreamReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = try!(resolve_seek(pos, self.position, self.length as u64));
+        Ok(self.position)
+    }
+}
+
+#[cfg(unix)]
+impl StreamReader for MmapStreamReader {
+    fn available_size(&self) -> u64 {
+        self.length as u64
+    }
+    fn total_size(&self) -> u64 {
+        self.length as u64
+    }
+    fn as_bytes<'a>(&'a self) -> Option<&'a [u8]> {
+        Some(self.as_slice())
+    }
+}
+
+/// Makes a stream reader out of any reader that can seek. The size of the stream is found by
+/// seeking to its end when the wrapper is created, so all of the stream must be available by then.
+pub struct SeekableStreamReader<R> {
+    reader: R,
+    size: u64,
+}
+
+impl<R: Read + Seek> SeekableStreamReader<R> {
+    pub fn new(mut reader: R) -> io::Result<SeekableStreamReader<R>> {
+        let position = try!(reader.seek(SeekFrom::Current(0)));
+        let size = try!(reader.seek(SeekFrom::End(0)));
+        try!(reader.seek(SeekFrom::Start(position)));
+        Ok(SeekableStreamReader {
+            reader: reader,
+            size: size,
+        })
+    }
+
+    pub fn get_ref<'a>(&'a self) -> &'a R {
+        &self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Read for SeekableStreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for SeekableStreamReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.reader.seek(pos)
+    }
+}
+
+impl<R: Read + Seek> StreamReader for SeekableStreamReader<R> {
+    fn available_size(&self) -> u64 {
+        self.size
+    }
+    fn total_size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// The default size of each block cached by a `BufferedStreamReader`, in bytes.
+pub const DEFAULT_BLOCK_SIZE: usize = 64 * 1024;
+
+/// The default number of blocks cached by a `BufferedStreamReader`.
+pub const DEFAULT_BLOCK_COUNT: usize = 32;
+
+/// The default number of blocks a `BufferedStreamReader` reads past the one it needs.
+pub const DEFAULT_READ_AHEAD: usize = 3;
+
+/// A stream reader that caches the underlying stream in fixed-size blocks.
+///
+/// On a cache miss, the missing block and up to `read_ahead` uncached blocks after it are read
+/// with a single request to the underlying stream. Seeking only moves the read position, so the
+/// demuxers can jump around within the cached window (as they do when parsing headers and indexes)
+/// without touching the underlying stream. When the cache is full, the least recently used block
+/// is discarded.
+pub struct BufferedStreamReader<R> {
+    /// The underlying stream.
+    inner: R,
+    /// The position of the underlying stream, if known, so that sequential reads don't need to
+    /// seek it.
+    inner_position: Option<u64>,
+    /// The position of the next read from this stream.
+    position: u64,
+    /// The size of each block, in bytes.
+    block_size: usize,
+    /// The maximum number of blocks to keep.
+    block_count: usize,
+    /// The number of blocks past the missing one to read on a cache miss.
+    read_ahead: usize,
+    /// The cached blocks, in no particular order.
+    blocks: Vec<CachedBlock>,
+    /// Incremented on every access to a block, to find the least recently used one.
+    clock: u64,
+}
+
+struct CachedBlock {
+    /// The offset of this block in the stream, divided by the block size.
+    index: u64,
+    /// The contents of this block. This is shorter than the block size only at the end of the
+    /// available data.
+    data: Vec<u8>,
+    /// The value of the clock when this block was last accessed.
+    last_used: u64,
+}
+
+impl<R: StreamReader> BufferedStreamReader<R> {
+    /// Creates a buffered reader with the default block size, cache size, and read-ahead.
+    pub fn new(inner: R) -> BufferedStreamReader<R> {
+        BufferedStreamReader::with_capacity(inner,
+                                            DEFAULT_BLOCK_SIZE,
+                                            DEFAULT_BLOCK_COUNT,
+                                            DEFAULT_READ_AHEAD)
+    }
+
+    /// Creates a buffered reader that caches up to `block_count` blocks of `block_size` bytes
+    /// each, reading up to `read_ahead` extra blocks on each cache miss. The read-ahead is limited
+    /// so that a single miss can't evict the block it was reading.
+    pub fn with_capacity(inner: R, block_size: usize, block_count: usize, read_ahead: usize)
+                         -> BufferedStreamReader<R> {
+        assert!(block_size > 0 && block_count > 0);
+        BufferedStreamReader {
+            inner: inner,
+            inner_position: None,
+            position: 0,
+            block_size: block_size,
+            block_count: block_count,
+            read_ahead: cmp::min(read_ahead, block_count - 1),
+            blocks: Vec::new(),
+            clock: 0,
+        }
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref<'a>(&'a self) -> &'a R {
+        &self.inner
+    }
+
+    /// Discards the cache and returns the underlying stream.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Discards every cached block. Call this if the underlying stream's contents change.
+    pub fn invalidate(&mut self) {
+        self.blocks.clear();
+        self.inner_position = None;
+    }
+
+    fn find_block(&self, index: u64) -> Option<usize> {
+        self.blocks.iter().position(|block| block.index == index)
+    }
+
+    /// Reads the block with the given index, along with any read-ahead, from the underlying
+    /// stream. Returns the position of the block in `blocks`, or `None` if the stream ends before
+    /// it.
+    fn fetch_block(&mut self, index: u64) -> io::Result<Option<usize>> {
+        let mut block_count = 1;
+        while block_count <= self.read_ahead &&
+                self.find_block(index + block_count as u64).is_none() {
+            block_count += 1
+        }
+
+        let start = index * self.block_size as u64;
+        if self.inner_position != Some(start) {
+            self.inner_position = None;
+            try!(self.inner.seek(SeekFrom::Start(start)));
+            self.inner_position = Some(start)
+        }
+
+        let mut data: Vec<u8> = iter::repeat(0).take(block_count * self.block_size).collect();
+        let mut length = 0;
+        while length < data.len() {
+            match self.inner.read(&mut data[length..]) {
+                Ok(0) => break,
+                Ok(bytes_read) => length += bytes_read,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(ref err) if err.kind() == ErrorKind::ResourceUnavailable => break,
+                Err(err) => {
+                    self.inner_position = None;
+                    return Err(err)
+                }
+            }
+        }
+        self.inner_position = Some(start + length as u64);
+        data.truncate(length);
+
+        // Insert the read-ahead blocks first so that the block that was asked for is the most
+        // recently used.
+        let chunks: Vec<&[u8]> = data.chunks(self.block_size).collect();
+        for (chunk_index, chunk) in chunks.iter().enumerate().rev() {
+            self.insert_block(index + chunk_index as u64, chunk.to_vec())
+        }
+        Ok(self.find_block(index))
+    }
+
+    fn insert_block(&mut self, index: u64, data: Vec<u8>) {
+        self.clock += 1;
+        let block = CachedBlock {
+            index: index,
+            data: data,
+            last_used: self.clock,
+        };
+        if let Some(existing_position) = self.find_block(index) {
+            self.blocks[existing_position] = block;
+            return
+        }
+        if self.blocks.len() < self.block_count {
+            self.blocks.push(block);
+            return
+        }
+        let mut least_recently_used = 0;
+        for (position, cached_block) in self.blocks.iter().enumerate() {
+            if cached_block.last_used < self.blocks[least_recently_used].last_used {
+                least_recently_used = position
+            }
+        }
+        self.blocks[least_recently_used] = block
+    }
+}
+
+impl<R: StreamReader> Read for BufferedStreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut bytes_read = 0;
+        while bytes_read < buf.len() {
+            let index = self.position / self.block_size as u64;
+            let offset = (self.position % self.block_size as u64) as usize;
+
+            // A cached block that ends before `offset` was the end of the data when it was read;
+            // the stream may have grown since, so read it again.
+            let block_position = match self.find_block(index) {
+                Some(block_position) if self.blocks[block_position].data.len() > offset => {
+                    block_position
+                }
+                _ => {
+                    match self.fetch_block(index) {
+                        Ok(Some(block_position))
+                                if self.blocks[block_position].data.len() > offset => {
+                            block_position
+                        }
+                        Ok(_) => break,
+                        Err(err) => {
+                            if bytes_read > 0 {
+                                break
+                            }
+                            return Err(err)
+                        }
+                    }
+                }
+            };
+
+            self.clock += 1;
+            let block = &mut self.blocks[block_position];
+            block.last_used = self.clock;
+            let length = cmp::min(buf.len() - bytes_read, block.data.len() - offset);
+            bytes::copy_memory(&mut buf[bytes_read..bytes_read + length],
+                               &block.data[offset..offset + length]);
+            bytes_read += length;
+            self.position += length as u64;
+        }
+
+        if bytes_read == 0 && !buf.is_empty() &&
+                self.position < self.inner.total_size() &&
+                self.inner.available_size() < self.inner.total_size() {
+            return Err(data_not_available())
+        }
+        Ok(bytes_read)
+    }
+}
+
+impl<R: StreamReader> Seek for BufferedStreamReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = try!(resolve_seek(pos, self.position, self.inner.total_size()));
+        Ok(self.position)
+    }
+}
+
+impl<R: StreamReader> StreamReader for BufferedStreamReader<R> {
+    fn available_size(&self) -> u64 {
+        self.inner.available_size()
+    }
+
+    fn total_size(&self) -> u64 {
+        self.inner.total_size()
+    }
+
+    fn as_bytes<'a>(&'a self) -> Option<&'a [u8]> {
+        self.inner.as_bytes()
+    }
+}
+
+/// An in-memory stream that can be appended to while it's being read, for progressive playback.
+///
+/// Clones share the same data, so a network layer can keep one clone to append to (possibly from
+/// another thread) while the player reads from another. Each clone has its own read position.
+#[derive(Clone)]
+pub struct GrowableStreamReader {
+    buffer: Arc<Mutex<GrowableBuffer>>,
+    position: u64,
+}
+
+struct GrowableBuffer {
+    /// The data that has arrived so far.
+    data: Vec<u8>,
+    /// The eventual size of the stream, if known.
+    total_size: Option<u64>,
+    /// True if no more data will be appended.
+    finished: bool,
+}
+
+impl GrowableStreamReader {
+    /// Creates an empty stream. `total_size` is the eventual size of the stream, if known (for
+    /// example, from a `Content-Length` header).
+    pub fn new(total_size: Option<u64>) -> GrowableStreamReader {
+        GrowableStreamReader {
+            buffer: Arc::new(Mutex::new(GrowableBuffer {
+                data: Vec::new(),
+                total_size: total_size,
+                finished: false,
+            })),
+            position: 0,
+        }
+    }
+
+    /// Appends newly arrived data to the end of the stream.
+    pub fn append(&self, data: &[u8]) {
+        let mut buffer = self.buffer.lock().unwrap();
+        assert!(!buffer.finished);
+        buffer.data.push_all(data)
+    }
+
+    /// Marks the stream as complete. Its total size becomes the amount of data appended so far.
+    pub fn finish(&self) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.total_size = Some(buffer.data.len() as u64);
+        buffer.finished = true
+    }
+
+    /// Returns true if `finish()` has been called.
+    pub fn is_finished(&self) -> bool {
+        self.buffer.lock().unwrap().finished
+    }
+}
+
+impl Read for GrowableStreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let buffer = self.buffer.lock().unwrap();
+        let available_size = buffer.data.len() as u64;
+        if self.position >= available_size {
+            let total_size = buffer.total_size.unwrap_or(u64::MAX);
+            if buffer.finished || self.position >= total_size || buf.is_empty() {
+                return Ok(0)
+            }
+            return Err(data_not_available())
+        }
+        let start = self.position as usize;
+        let length = cmp::min(buf.len(), buffer.data.len() - start);
+        bytes::copy_memory(&mut buf[0..length], &buffer.data[start..start + length]);
+        self.position += length as u64;
+        Ok(length)
+    }
+}
+
+impl Seek for GrowableStreamReader {
+    /// Seeking past the available data is allowed; reads from there fail until the data arrives.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let total_size = match (pos, self.buffer.lock().unwrap().total_size) {
+            (SeekFrom::End(_), None) => return Err(data_not_available()),
+            (_, total_size) => total_size.unwrap_or(u64::MAX),
+        };
+        self.position = try!(resolve_seek(pos, self.position, total_size));
+        Ok(self.position)
+    }
+}
+
+impl StreamReader for GrowableStreamReader {
+    fn available_size(&self) -> u64 {
+        self.buffer.lock().unwrap().data.len() as u64
+    }
+
+    fn total_size(&self) -> u64 {
+        self.buffer.lock().unwrap().total_size.unwrap_or(u64::MAX)
+    }
+}
+
+/// The error returned when reading past the data that has arrived so far.
+fn data_not_available() -> io::Error {
+    io::Error::new(ErrorKind::ResourceUnavailable, "data not yet available", None)
+}
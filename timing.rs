@@ -7,20 +7,142 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::cmp::Ordering;
+use std::i64;
 use std::ops::{Add, Sub};
 use std::time::duration::Duration;
+use std::u32;
+use std::u64;
 
-/// A timestamp relative to the beginning of playback. `ticks / ticks_per_second` represents the
-/// number of seconds. Use `.duration()` to convert to a Rust duration.
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// The length of one tick, in seconds, as an exact fraction. Time bases are always kept in lowest
+/// terms, so two time bases are equal if and only if they describe the same tick length.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeBase {
+    numerator: u32,
+    denominator: u32,
+}
+
+impl TimeBase {
+    /// Creates a time base in which each tick lasts `numerator / denominator` seconds. Panics if
+    /// either part is zero.
+    pub fn new(numerator: u32, denominator: u32) -> TimeBase {
+        match TimeBase::from_ratio(numerator as u64, denominator as u64) {
+            Some(time_base) => time_base,
+            None => panic!("time base must be positive"),
+        }
+    }
+
+    /// Creates a time base from a ratio whose parts may not fit in 32 bits until reduced, such as
+    /// a Matroska timecode scale over 10^9. Returns `None` if either part is zero or if the reduced
+    /// ratio still doesn't fit.
+    pub fn from_ratio(numerator: u64, denominator: u64) -> Option<TimeBase> {
+        if numerator == 0 || denominator == 0 {
+            return None
+        }
+        let divisor = gcd(numerator, denominator);
+        let (numerator, denominator) = (numerator / divisor, denominator / divisor);
+        if numerator > u32::MAX as u64 || denominator > u32::MAX as u64 {
+            return None
+        }
+        Some(TimeBase {
+            numerator: numerator as u32,
+            denominator: denominator as u32,
+        })
+    }
+
+    pub fn numerator(&self) -> u32 {
+        self.numerator
+    }
+
+    pub fn denominator(&self) -> u32 {
+        self.denominator
+    }
+}
+
+/// The time base of `Duration`, one nanosecond.
+pub const NANOSECONDS: TimeBase = TimeBase {
+    numerator: 1,
+    denominator: 1_000_000_000,
+};
+
+/// A timestamp relative to the beginning of playback. `ticks * time_base` represents the number
+/// of seconds. Use `.duration()` to convert to a Rust duration.
+///
+/// Timestamps in different time bases can be compared directly; the comparison is exact.
+#[derive(Clone, Copy, Debug)]
 pub struct Timestamp {
     pub ticks: i64,
-    pub ticks_per_second: f64,
+    pub time_base: TimeBase,
 }
 
 impl Timestamp {
+    pub fn new(ticks: i64, time_base: TimeBase) -> Timestamp {
+        Timestamp {
+            ticks: ticks,
+            time_base: time_base,
+        }
+    }
+
+    /// Converts this timestamp to the given time base, rounding to the nearest tick (halfway
+    /// cases away from zero) and saturating if the result doesn't fit in an `i64`.
+    pub fn rescale(&self, time_base: TimeBase) -> Timestamp {
+        let ticks = match rescale_ticks(self.ticks, self.time_base, time_base) {
+            Some((ticks, _)) => ticks,
+            None if self.ticks < 0 => i64::MIN,
+            None => i64::MAX,
+        };
+        Timestamp::new(ticks, time_base)
+    }
+
+    /// Converts this timestamp to the given time base, returning `None` if it can't be represented
+    /// there without rounding or overflow.
+    pub fn rescale_exact(&self, time_base: TimeBase) -> Option<Timestamp> {
+        match rescale_ticks(self.ticks, self.time_base, time_base) {
+            Some((ticks, true)) => Some(Timestamp::new(ticks, time_base)),
+            Some((_, false)) | None => None,
+        }
+    }
+
+    /// Returns the time since the beginning of playback, saturating at the range of an `i64`
+    /// number of nanoseconds.
     pub fn duration(&self) -> Duration {
-        Duration::nanoseconds(((self.ticks * 1_000_000_000) as f64 / self.ticks_per_second) as i64)
+        Duration::nanoseconds(self.rescale(NANOSECONDS).ticks)
+    }
+}
+
+impl PartialEq for Timestamp {
+    fn eq(&self, other: &Timestamp) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Timestamp {}
+
+impl PartialOrd for Timestamp {
+    fn partial_cmp(&self, other: &Timestamp) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Timestamp {
+    fn cmp(&self, other: &Timestamp) -> Ordering {
+        match (self.ticks < 0, other.ticks < 0) {
+            (true, false) => return Ordering::Less,
+            (false, true) => return Ordering::Greater,
+            _ => {}
+        }
+
+        // Cross-multiply: compare `|a| * a.num * b.den` against `|b| * b.num * a.den`. The time
+        // base products fit in 64 bits, so the full products fit in 128.
+        let self_scale = self.time_base.numerator as u64 * other.time_base.denominator as u64;
+        let other_scale = other.time_base.numerator as u64 * self.time_base.denominator as u64;
+        let ordering = mul_wide(magnitude(self.ticks), self_scale).cmp(
+            &mul_wide(magnitude(other.ticks), other_scale));
+        if self.ticks < 0 {
+            ordering.reverse()
+        } else {
+            ordering
+        }
     }
 }
 
@@ -30,7 +152,7 @@ impl Add<i64> for Timestamp {
     fn add(self, other: i64) -> Timestamp {
         Timestamp {
             ticks: self.ticks + other,
-            ticks_per_second: self.ticks_per_second,
+            time_base: self.time_base,
         }
     }
 }
@@ -41,8 +163,258 @@ impl Sub<i64> for Timestamp {
     fn sub(self, other: i64) -> Timestamp {
         Timestamp {
             ticks: self.ticks - other,
-            ticks_per_second: self.ticks_per_second,
+            time_base: self.time_base,
+        }
+    }
+}
+
+/// Returns the time elapsed between two timestamps, which need not share a time base. The result
+/// saturates at the range of an `i64` number of nanoseconds.
+impl Sub<Timestamp> for Timestamp {
+    type Output = Duration;
+
+    fn sub(self, other: Timestamp) -> Duration {
+        let (a, b) = (self.rescale(NANOSECONDS).ticks, other.rescale(NANOSECONDS).ticks);
+        let nanoseconds = if b > 0 && a < i64::MIN + b {
+            i64::MIN
+        } else if b < 0 && a > i64::MAX + b {
+            i64::MAX
+        } else {
+            a - b
+        };
+        Duration::nanoseconds(nanoseconds)
+    }
+}
+
+/// Converts `ticks` from one time base to another. Returns the new tick count and whether the
+/// conversion was exact, or `None` if the result doesn't fit in an `i64`.
+///
+/// This is a port of FFmpeg's `av_rescale_rnd()` with `AV_ROUND_NEAR_INF`, using 128-bit
+/// intermediates so that no precision is lost before the final rounding.
+fn rescale_ticks(ticks: i64, from: TimeBase, to: TimeBase) -> Option<(i64, bool)> {
+    if from == to {
+        return Some((ticks, true))
+    }
+    let multiplier = from.numerator as u64 * to.denominator as u64;
+    let divisor = from.denominator as u64 * to.numerator as u64;
+    let (result, exact) = match mul_div_round(magnitude(ticks), multiplier, divisor) {
+        Some(result) => result,
+        None => return None,
+    };
+    if ticks < 0 {
+        if result > (i64::MAX as u64) + 1 {
+            None
+        } else if result == (i64::MAX as u64) + 1 {
+            Some((i64::MIN, exact))
+        } else {
+            Some((-(result as i64), exact))
         }
+    } else if result > i64::MAX as u64 {
+        None
+    } else {
+        Some((result as i64, exact))
     }
 }
 
+/// Returns the absolute value of `value`, which can't overflow as an unsigned number.
+fn magnitude(value: i64) -> u64 {
+    if value < 0 {
+        (-(value + 1)) as u64 + 1
+    } else {
+        value as u64
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let remainder = a % b;
+        a = b;
+        b = remainder;
+    }
+    a
+}
+
+/// Computes `value * multiplier / divisor`, rounding halfway cases up. Returns the result and
+/// whether it was exact, or `None` if it doesn't fit in 64 bits.
+fn mul_div_round(value: u64, multiplier: u64, divisor: u64) -> Option<(u64, bool)> {
+    let (high, low) = mul_wide(value, multiplier);
+    let (quotient, remainder) = match div_wide(high, low, divisor) {
+        Some(result) => result,
+        None => return None,
+    };
+    if remainder >= divisor - remainder {
+        if quotient == u64::MAX {
+            return None
+        }
+        return Some((quotient + 1, false))
+    }
+    Some((quotient, remainder == 0))
+}
+
+/// Multiplies two 64-bit numbers, returning the high and low halves of the 128-bit product.
+fn mul_wide(a: u64, b: u64) -> (u64, u64) {
+    let (a_high, a_low) = (a >> 32, a & 0xffffffff);
+    let (b_high, b_low) = (b >> 32, b & 0xffffffff);
+    let low_low = a_low * b_low;
+    let high_low = a_high * b_low;
+    let low_high = a_low * b_high;
+    let middle = (low_low >> 32) + (high_low & 0xffffffff) + (low_high & 0xffffffff);
+    let low = (middle << 32) | (low_low & 0xffffffff);
+    let high = a_high * b_high + (high_low >> 32) + (low_high >> 32) + (middle >> 32);
+    (high, low)
+}
+
+/// Divides the 128-bit number `high:low` by `divisor` one bit at a time, returning the quotient
+/// and remainder, or `None` if the quotient doesn't fit in 64 bits.
+fn div_wide(high: u64, low: u64, divisor: u64) -> Option<(u64, u64)> {
+    if high >= divisor {
+        return None
+    }
+    let (mut quotient, mut remainder) = (0, high);
+    for bit in range(0usize, 64).rev() {
+        // `remainder` is less than `divisor`, so if its top bit is about to be shifted out, the
+        // shifted value is certainly at least `divisor`.
+        let carry = remainder >> 63;
+        remainder = (remainder << 1) | ((low >> bit) & 1);
+        quotient <<= 1;
+        if carry != 0 {
+            remainder = remainder + (u64::MAX - divisor) + 1;
+            quotient |= 1;
+        } else if remainder >= divisor {
+            remainder -= divisor;
+            quotient |= 1;
+        }
+    }
+    Some((quotient, remainder))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NANOSECONDS, TimeBase, Timestamp};
+    use std::i64;
+    use std::u32;
+
+    /// The time base of a Matroska file with the default timecode scale.
+    fn mkv() -> TimeBase {
+        TimeBase::from_ratio(1_000_000, 1_000_000_000).unwrap()
+    }
+
+    /// The time base of a typical MP4 video track.
+    fn mp4() -> TimeBase {
+        TimeBase::new(1, 90000)
+    }
+
+    /// The time base of GIF frame delays, which are in hundredths of a second.
+    fn gif() -> TimeBase {
+        TimeBase::new(1, 100)
+    }
+
+    fn rescale(ticks: i64, from: TimeBase, to: TimeBase) -> i64 {
+        Timestamp::new(ticks, from).rescale(to).ticks
+    }
+
+    #[test]
+    fn time_bases_are_reduced() {
+        assert_eq!(mkv(), TimeBase::new(1, 1000));
+        assert_eq!(TimeBase::new(3003, 90000), TimeBase::new(1001, 30000));
+    }
+
+    #[test]
+    fn from_ratio_rejects_zero_and_oversized_ratios() {
+        assert_eq!(TimeBase::from_ratio(0, 1000), None);
+        assert_eq!(TimeBase::from_ratio(1, 0), None);
+        assert_eq!(TimeBase::from_ratio(0, 0), None);
+        assert_eq!(TimeBase::from_ratio(1, u32::MAX as u64 + 1), None);
+        assert_eq!(TimeBase::from_ratio(2, u32::MAX as u64 * 2), Some(TimeBase::new(1, u32::MAX)));
+    }
+
+    #[test]
+    fn rescale_between_container_time_bases() {
+        assert_eq!(rescale(1500, mkv(), mp4()), 135000);
+        assert_eq!(rescale(135000, mp4(), mkv()), 1500);
+        assert_eq!(rescale(7, gif(), NANOSECONDS), 70_000_000);
+        assert_eq!(rescale(70, mkv(), gif()), 7);
+        assert_eq!(rescale(3003, mp4(), TimeBase::new(1001, 30000)), 1);
+    }
+
+    #[test]
+    fn rescale_rounds_halfway_cases_away_from_zero() {
+        assert_eq!(rescale(45, mp4(), mkv()), 1);
+        assert_eq!(rescale(44, mp4(), mkv()), 0);
+        assert_eq!(rescale(15, mkv(), gif()), 2);
+        assert_eq!(rescale(25, mkv(), gif()), 3);
+        assert_eq!(rescale(-15, mkv(), gif()), -2);
+        assert_eq!(rescale(-25, mkv(), gif()), -3);
+        assert_eq!(rescale(-14, mkv(), gif()), -1);
+    }
+
+    #[test]
+    fn rescale_exact_rejects_rounding() {
+        assert_eq!(Timestamp::new(15, mkv()).rescale_exact(gif()), None);
+        assert_eq!(Timestamp::new(20, mkv()).rescale_exact(gif()).map(|time| time.ticks), Some(2));
+        assert_eq!(Timestamp::new(-20, mkv()).rescale_exact(gif()).map(|time| time.ticks),
+                   Some(-2));
+    }
+
+    #[test]
+    fn rescale_handles_negative_ticks() {
+        assert_eq!(rescale(-135000, mp4(), mkv()), -1500);
+        assert_eq!(rescale(-1500, mkv(), mp4()), -135000);
+        assert_eq!(rescale(-7, gif(), NANOSECONDS), -70_000_000);
+    }
+
+    #[test]
+    fn rescale_saturates() {
+        let seconds = TimeBase::new(1, 1);
+        assert_eq!(rescale(i64::MAX, seconds, NANOSECONDS), i64::MAX);
+        assert_eq!(rescale(i64::MIN, seconds, NANOSECONDS), i64::MIN);
+        assert_eq!(Timestamp::new(i64::MAX, seconds).rescale_exact(NANOSECONDS), None);
+
+        // Doubling reaches exactly `i64::MIN`, but not `i64::MAX`.
+        let (milliseconds, half_milliseconds) = (mkv(), TimeBase::new(1, 2000));
+        assert_eq!(rescale(i64::MIN / 2, milliseconds, half_milliseconds), i64::MIN);
+        assert_eq!(rescale(i64::MIN / 2 - 1, milliseconds, half_milliseconds), i64::MIN);
+        assert_eq!(rescale(i64::MAX / 2, milliseconds, half_milliseconds), i64::MAX - 1);
+        assert_eq!(rescale(i64::MAX / 2 + 1, milliseconds, half_milliseconds), i64::MAX);
+    }
+
+    #[test]
+    fn rescale_keeps_precision_of_wide_products() {
+        // The intermediate products of the ticks and the time bases overflow 64 bits.
+        assert_eq!(rescale(9_000_000_000_000_000_000, NANOSECONDS, mkv()), 9_000_000_000_000);
+        assert_eq!(rescale(i64::MAX, mp4(), TimeBase::new(1, 45000)), i64::MAX / 2 + 1);
+        assert_eq!(rescale(i64::MAX, mp4(), TimeBase::new(1, 90001)), i64::MAX);
+    }
+
+    #[test]
+    fn timestamps_in_different_time_bases_compare_exactly() {
+        let a = Timestamp::new(9_000_000_000_000_000_000, NANOSECONDS);
+        let b = Timestamp::new(9_000_000_000_000_000, TimeBase::new(1, 1_000_000));
+        assert_eq!(a, b);
+        assert!(a < b + 1);
+        assert!(a + 1 > b);
+        assert!(a - 1 < b);
+
+        let ntsc = Timestamp::new(3_000_000_000_000_000, TimeBase::new(1001, 30000));
+        let ninety_khz = Timestamp::new(9_009_000_000_000_000_000, mp4());
+        assert_eq!(ntsc, ninety_khz);
+        assert!(ntsc > ninety_khz - 1);
+        assert!(ntsc < ninety_khz + 1);
+
+        assert!(Timestamp::new(i64::MAX, TimeBase::new(1, 3)) <
+                Timestamp::new(i64::MAX, TimeBase::new(1, 2)));
+        assert!(Timestamp::new(i64::MIN, TimeBase::new(1, 3)) >
+                Timestamp::new(i64::MIN, TimeBase::new(1, 2)));
+    }
+
+    #[test]
+    fn negative_timestamps_compare_exactly() {
+        let a = Timestamp::new(-9_000_000_000_000_000_000, NANOSECONDS);
+        let b = Timestamp::new(-9_000_000_000_000_000, TimeBase::new(1, 1_000_000));
+        assert_eq!(a, b);
+        assert!(a > b - 1);
+        assert!(a - 1 < b);
+        assert!(Timestamp::new(-1, NANOSECONDS) < Timestamp::new(0, TimeBase::new(1, 1)));
+        assert!(Timestamp::new(-1, TimeBase::new(1, 1)) < Timestamp::new(-1, NANOSECONDS));
+    }
+}
@@ -5,26 +5,27 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use codecs::vpx;
-use containers::gif;
+use error::MediaError;
 use pixelformat::PixelFormat;
+use registry::Registry;
 use timing::Timestamp;
 
 use libc::{c_int, c_uint};
 
-#[cfg(feature="ffmpeg")]
-use codecs::libavcodec;
-#[cfg(target_os="macos")]
-use platform;
-
 pub trait VideoDecoder {
+    /// Decodes a frame. Returns `MediaError::NeedMoreData` if the decoder accepted the data but
+    /// didn't produce a picture from it.
     fn decode_frame(&self, data: &[u8], presentation_time: &Timestamp)
-                    -> Result<Box<DecodedVideoFrame + 'static>,()>;
+                    -> Result<Box<DecodedVideoFrame + 'static>,MediaError>;
 }
 
 pub trait VideoHeaders {
     fn h264_seq_headers<'a>(&'a self) -> Option<Vec<&'a [u8]>>;
     fn h264_pict_headers<'a>(&'a self) -> Option<Vec<&'a [u8]>>;
+    /// Returns the size, in bytes, of the length that precedes each H.264 NAL unit in a frame.
+    fn h264_nal_length_size(&self) -> u8 {
+        4
+    }
 }
 
 pub trait DecodedVideoFrame {
@@ -58,21 +59,30 @@ impl VideoHeaders for EmptyVideoHeadersImpl {
 pub struct RegisteredVideoDecoder {
     pub id: [u8; 4],
     pub constructor: extern "Rust" fn(headers: &VideoHeaders, width: i32, height: i32)
-                                      -> Result<Box<VideoDecoder + 'static>,()>,
+                                      -> Result<Box<VideoDecoder + 'static>,MediaError>,
 }
 
 impl RegisteredVideoDecoder {
-    pub fn get(codec_id: &[u8]) -> Result<&'static RegisteredVideoDecoder,()> {
-        for decoder in VIDEO_DECODERS.iter() {
+    /// Finds the highest-priority decoder in `registry` for the given codec.
+    pub fn get<'a>(registry: &'a Registry, codec_id: &[u8])
+                   -> Result<&'a RegisteredVideoDecoder,MediaError> {
+        for decoder in registry.video_decoders().iter() {
             if decoder.id == codec_id {
                 return Ok(decoder)
             }
         }
-        Err(())
+        Err(MediaError::UnsupportedCodec)
+    }
+
+    /// Returns every decoder in `registry` for the given codec, in order of preference. Callers
+    /// should fall back to the next candidate if one fails.
+    pub fn get_all<'a>(registry: &'a Registry, codec_id: &[u8])
+                       -> Vec<&'a RegisteredVideoDecoder> {
+        registry.video_decoders().iter().filter(|decoder| decoder.id == codec_id).collect()
     }
 
     pub fn new(&self, headers: &VideoHeaders, width: i32, height: i32)
-               -> Result<Box<VideoDecoder + 'static>,()> {
+               -> Result<Box<VideoDecoder + 'static>,MediaError> {
         (self.constructor)(headers, width, height)
     }
 
@@ -80,34 +90,3 @@ impl RegisteredVideoDecoder {
         self.id
     }
 }
-
-// FIXME(pcwalton): Combinatorial explosion imminent. :( Can we do something clever with macros?
-
-#[cfg(all(target_os="macos", feature="ffmpeg"))]
-pub static VIDEO_DECODERS: [RegisteredVideoDecoder; 4] = [
-    vpx::VIDEO_DECODER,
-    gif::VIDEO_DECODER,
-    libavcodec::VIDEO_DECODER,
-    platform::macos::videotoolbox::VIDEO_DECODER,
-];
-
-#[cfg(all(target_os="macos", not(feature="ffmpeg")))]
-pub static VIDEO_DECODERS: [RegisteredVideoDecoder; 3] = [
-    vpx::VIDEO_DECODER,
-    gif::VIDEO_DECODER,
-    platform::macos::videotoolbox::VIDEO_DECODER,
-];
-
-#[cfg(all(not(target_os="macos"), feature="ffmpeg"))]
-pub static VIDEO_DECODERS: [RegisteredVideoDecoder; 3] = [
-    vpx::VIDEO_DECODER,
-    gif::VIDEO_DECODER,
-    libavcodec::VIDEO_DECODER,
-];
-
-#[cfg(all(not(target_os="macos"), not(feature="ffmpeg")))]
-pub static VIDEO_DECODERS: [RegisteredVideoDecoder; 2] = [
-    vpx::VIDEO_DECODER,
-    gif::VIDEO_DECODER,
-];
-